@@ -1,9 +1,25 @@
-use clap::{Args, Parser};
+use burn::backend::wgpu::WgpuDevice;
+use clap::{Args, Parser, Subcommand};
 use game::Game;
-use mcts::heuristics::Heuristics;
+use mcts::amaf::McRave;
+use mcts::analysis::SearchLimits;
+use mcts::book::{BookOptions, OpeningBook};
+use mcts::distribution::Objective;
+use mcts::dot::DotOptions;
+use mcts::engine::{play_game, EngineKind, MctsEngine};
+use mcts::heuristics::format::is_csv;
+use mcts::heuristics::{Heuristics, Parameters};
+use mcts::hindsight::{self, HindsightOptions};
+use mcts::rollout::RolloutPolicyKind;
+use mcts::scaling::{Budget, Scaling, ScalingOptions};
+use mcts::tournament::{
+    Entrant, SharedHeuristics, Sprt, SprtDecision, Tournament, TournamentConfig, TournamentOptions,
+};
+use mcts::trainer::ridge::{fit_parameters, Sample};
 use mcts::MonteCarloTree;
 use railroad_ink_solver::*;
 use rayon::prelude::*;
+use std::sync::Arc;
 use std::time;
 
 use rand::prelude::*;
@@ -12,6 +28,14 @@ use rand::prelude::*;
 enum Cli {
     NN(NeuralNetworkArgs),
     Play(PlayArgs),
+    RaveBench(RaveBenchArgs),
+    MigrateHeuristics(MigrateHeuristicsArgs),
+    FitHeuristics(FitHeuristicsArgs),
+    Heuristics(HeuristicsArgs),
+    Analyze(AnalyzeArgs),
+    BuildBook(BuildBookArgs),
+    Tournament(TournamentArgs),
+    Scaling(ScalingArgs),
 }
 
 #[derive(Args)]
@@ -30,6 +54,10 @@ struct NeuralNetworkArgs {
     #[arg(short, long, default_value = "700")]
     iterations: u64,
 
+    /// Engine to generate training data with: `mcts`, `random`, `greedy-score` or `greedy-heuristic`
+    #[arg(long, default_value = "mcts")]
+    engine: EngineKind,
+
     #[arg(short, long)]
     loop_training: bool,
 }
@@ -52,6 +80,314 @@ struct PlayArgs {
 
     #[arg(short, long)]
     loop_play: bool,
+
+    /// Engine to play with: `mcts`, `random`, `greedy-score` or `greedy-heuristic`.
+    /// The baselines do not search, and ignore the search and tree options
+    #[arg(long, default_value = "mcts")]
+    engine: EngineKind,
+
+    /// Heuristics parameters to search with, as a JSON file. Defaults to `src/mcts/heuristics/default.json`
+    #[arg(long)]
+    heuristics: Option<String>,
+
+    /// Directory of a trained neural network, for the `value_network` leaf evaluator
+    #[arg(long)]
+    model: Option<String>,
+
+    /// Rollout policy: `random`, `greedy`, `epsilon-greedy:<epsilon>` or `softmax:<temperature>`.
+    /// Overrides the policy in the heuristics parameters
+    #[arg(long)]
+    rollout_policy: Option<RolloutPolicyKind>,
+
+    /// Maximise the chance of scoring at least this much, instead of the mean score.
+    /// Overrides the objective in the heuristics parameters
+    #[arg(long)]
+    target_score: Option<f64>,
+
+    /// Opening book to play the first turn from, as built by `build-book`
+    #[arg(long)]
+    book: Option<String>,
+
+    /// Also play each game in hindsight, with all of its rolls known in advance, and
+    /// report the regret: how many points short of the best score found in hindsight the game fell
+    #[arg(long)]
+    regret: bool,
+
+    /// Number of boards the hindsight search keeps after every placement, see `--regret`
+    #[arg(long, default_value = "16")]
+    hindsight_beam: usize,
+
+    /// Maximum number of nodes to keep in the search tree. Low-visit subtrees are recycled when it is exceeded
+    #[arg(long)]
+    node_budget: Option<usize>,
+
+    /// Directory to write the search tree to as Graphviz DOT files, one file per move
+    #[arg(long)]
+    dot: Option<String>,
+
+    /// Maximum depth of the exported DOT trees
+    #[arg(long, default_value = "3")]
+    dot_depth: u16,
+
+    /// Leave edges with fewer visits than this out of the exported DOT trees
+    #[arg(long, default_value = "1")]
+    dot_min_visits: u64,
+}
+
+/// Play the same games with the heuristic prior, and with MC-RAVE
+#[derive(Args, Debug)]
+struct RaveBenchArgs {
+    /// Number of games to play with each configuration
+    #[arg(long, default_value = "20")]
+    count: u32,
+
+    /// Seed for random number generator. Both configurations play the same dice
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// Number of mcts search iterations per move
+    #[arg(short, long, default_value = "500")]
+    iterations: u64,
+
+    /// MC-RAVE equivalence parameter: visits at which the mean and the AMAF mean weigh the same
+    #[arg(long, default_value = "500")]
+    equivalence: f64,
+
+    /// Heuristics parameters to search with, as a JSON file. Defaults to `src/mcts/heuristics/default.json`
+    #[arg(long)]
+    heuristics: Option<String>,
+
+    /// Directory of a trained neural network, for the `value_network` leaf evaluator
+    #[arg(long)]
+    model: Option<String>,
+}
+
+/// Re-express heuristics parameters tuned for raw scores in normalised units
+#[derive(Args, Debug)]
+struct MigrateHeuristicsArgs {
+    /// Heuristics parameters to migrate, as a JSON file
+    input: String,
+
+    /// Where to write the migrated parameters
+    #[arg(
+        long,
+        required_unless_present = "in_place",
+        conflicts_with = "in_place"
+    )]
+    output: Option<String>,
+
+    /// Overwrite `input` with the migrated parameters
+    #[arg(long)]
+    in_place: bool,
+
+    /// Spread of scores, in points, that the exploration parameters were tuned against
+    #[arg(long, default_value_t = mcts::normalization::REFERENCE_SCORE_RANGE)]
+    score_range: f64,
+}
+
+/// Check, compare and convert heuristics parameter files, as JSON or as CSV tables
+#[derive(Args, Debug)]
+struct HeuristicsArgs {
+    #[command(subcommand)]
+    command: HeuristicsCommand,
+}
+
+#[derive(Subcommand, Debug)]
+enum HeuristicsCommand {
+    /// Check that a parameters file loads, and that its values make sense
+    Validate {
+        /// The parameters, as JSON, or CSV if the name ends with `.csv`
+        file: String,
+    },
+    /// Print every value that differs between two parameters files, turn by turn
+    Diff { first: String, second: String },
+    /// Write a parameters file in the format of the output's extension
+    Convert {
+        input: String,
+        output: String,
+
+        /// Parameters to take the settings a CSV input has no columns for from. Defaults to
+        /// those of old tables: no pruning, and raw score units. Only for CSV input
+        #[arg(long)]
+        base: Option<String>,
+    },
+}
+
+/// Fit the heuristic weights of every turn to the games in `data.sqlite` by ridge regression
+#[derive(Args, Debug)]
+struct FitHeuristicsArgs {
+    /// Heuristics parameters to start from, as a JSON file. Defaults to `src/mcts/heuristics/default.json`
+    #[arg(long)]
+    heuristics: Option<String>,
+
+    /// Where to write the fitted parameters
+    #[arg(short, long, default_value = "fitted.json")]
+    output: String,
+
+    /// Where to write the validation metrics, as JSON
+    #[arg(long)]
+    metrics: Option<String>,
+
+    /// Penalty on the squared weights
+    #[arg(long, default_value = "1.0")]
+    ridge: f64,
+
+    /// Share of the placements of every turn to hold out for validation
+    #[arg(long, default_value = "0.25")]
+    validation: f64,
+}
+
+/// Search a single position and print the moves worth considering
+#[derive(Args, Debug)]
+struct AnalyzeArgs {
+    /// The position, as encoded by `Game::encode`
+    #[arg(long, conflicts_with_all = ["board", "roll"])]
+    game: Option<String>,
+
+    /// The board, as encoded by `Board::encode`. Use with `--roll`
+    #[arg(long, requires = "roll")]
+    board: Option<String>,
+
+    /// The pieces left to place this turn, as hexadecimal piece IDs, like `0102030a`
+    #[arg(long)]
+    roll: Option<String>,
+
+    /// The turn of the position given by `--board` and `--roll`
+    #[arg(long, default_value = "1")]
+    turn: u8,
+
+    /// The special pieces used in earlier turns, as hexadecimal piece IDs
+    #[arg(long, default_value = "")]
+    expended_specials: String,
+
+    #[arg(short, long)]
+    iterations: Option<u64>,
+
+    /// Time to search, in milliseconds
+    #[arg(short, long)]
+    duration: Option<u128>,
+
+    /// Number of moves to list
+    #[arg(long, default_value = "5")]
+    top: usize,
+
+    /// Heuristics parameters to search with, as a JSON file. Defaults to `src/mcts/heuristics/default.json`
+    #[arg(long)]
+    heuristics: Option<String>,
+
+    /// Directory of a trained neural network, for the `value_network` leaf evaluator
+    #[arg(long)]
+    model: Option<String>,
+
+    /// Print the analysis as JSON
+    #[arg(long)]
+    json: bool,
+
+    /// File to keep the search tree in. If it exists, the search resumes from it,
+    /// and the position and heuristics arguments are refused. The tree is saved after the search
+    #[arg(long)]
+    tree: Option<String>,
+}
+
+/// Search every first-turn roll, and save the placements in an opening book
+#[derive(Args, Debug)]
+struct BuildBookArgs {
+    /// File to save the book to. If it exists, rolls already in it are skipped
+    output: String,
+
+    /// Number of mcts search iterations before each placement
+    #[arg(short, long, default_value = "20000")]
+    iterations: u64,
+
+    /// Only search one of the mirror images of each first placement
+    #[arg(long)]
+    fold_symmetries: bool,
+
+    /// Seed for random number generator
+    #[arg(long, default_value = "0")]
+    seed: u64,
+
+    /// Heuristics parameters to search with, as a JSON file. Defaults to `src/mcts/heuristics/default.json`
+    #[arg(long)]
+    heuristics: Option<String>,
+}
+
+/// Play several engines on the same dice, and compare their scores game by game
+#[derive(Args, Debug)]
+struct TournamentArgs {
+    /// The entrants, as a JSON file: `{ "entrants": [{ "name", "engine", "heuristics", "model", "limits" }] }`
+    config: String,
+
+    /// Most games to play
+    #[arg(long, default_value = "200")]
+    games: u64,
+
+    /// Seed of the first game. Game `i` is played with seed `seed + i`
+    #[arg(long, default_value = "0")]
+    seed: u64,
+
+    /// Number of games to play between checks of the SPRT
+    #[arg(long, default_value = "10")]
+    batch_size: u64,
+
+    /// Stop early once a sequential probability ratio test decides, for every pair of
+    /// entrants, between the second scoring this many points more than the first, or the same
+    #[arg(long)]
+    sprt_delta: Option<f64>,
+
+    /// Chance of the SPRT accepting an improvement that isn't there
+    #[arg(long, default_value = "0.05")]
+    alpha: f64,
+
+    /// Chance of the SPRT missing an improvement of `sprt-delta` points
+    #[arg(long, default_value = "0.05")]
+    beta: f64,
+
+    /// File to write the scores of every game to, as CSV
+    #[arg(long)]
+    csv: Option<String>,
+
+    /// File to write the results to, as JSON
+    #[arg(long)]
+    json: Option<String>,
+}
+
+/// Play the same games over a grid of search budgets, configurations and thread counts,
+/// and write every game as a row of CSV
+#[derive(Args, Debug)]
+struct ScalingArgs {
+    /// The configurations to sweep, as a JSON file in the format of `tournament`, whose
+    /// limits are ignored. Defaults to the tree search with the default heuristics
+    #[arg(long)]
+    config: Option<String>,
+
+    /// Numbers of mcts search iterations per move to sweep, separated by commas
+    #[arg(short, long, value_delimiter = ',', conflicts_with = "durations")]
+    iterations: Vec<u64>,
+
+    /// Search durations per move in ms to sweep, separated by commas
+    #[arg(short, long, value_delimiter = ',')]
+    durations: Vec<u128>,
+
+    /// Numbers of games to play in parallel to sweep, separated by commas. Every search runs on one thread
+    #[arg(long, value_delimiter = ',', default_value = "1")]
+    parallel_games: Vec<usize>,
+
+    /// Number of games to play at every point
+    #[arg(long, default_value = "20")]
+    games: u64,
+
+    /// Seed of the first game. Game `i` of every point is played with seed `seed + i`
+    #[arg(long, default_value = "0")]
+    seed: u64,
+
+    /// File to write the CSV to. Defaults to stdout
+    #[arg(short, long)]
+    output: Option<String>,
+
+    /// File to write the moves of every game to, as JSON lines keyed by the record id of the CSV
+    #[arg(long)]
+    records: Option<String>,
 }
 
 fn poisson(lambda: f64) -> f64 {
@@ -107,7 +443,14 @@ fn main() {
                 initial_run = false;
 
                 if args.generate_training_data {
-                    mcts::trainer::generate_training_data(args.count, args.iterations);
+                    mcts::trainer::generate_training_data(
+                        args.count,
+                        SearchLimits::iterations(args.iterations),
+                        || {
+                            args.engine
+                                .build(rand::thread_rng().gen(), Heuristics::default())
+                        },
+                    );
                 }
 
                 if args.train {
@@ -147,22 +490,70 @@ fn main() {
                     PlayMode::Duration(chaos_random())
                 };
 
+                let mut heuristics = load_heuristics(&args.heuristics, &args.model);
+                if let Some(policy) = args.rollout_policy {
+                    heuristics.parameters.rollout_policy = policy;
+                }
+                if let Some(target) = args.target_score {
+                    heuristics.parameters.objective = Objective::TargetScore { target };
+                }
+
+                let book = args.book.as_ref().map(|path| {
+                    Arc::new(OpeningBook::load(path).unwrap_or_else(|e| panic!("{}", e)))
+                });
+
                 let seed = args.seed.unwrap_or_else(|| rand::thread_rng().gen());
                 let start_time = time::Instant::now();
 
-                let scores: Vec<u64> = (0..args.count)
+                let hindsight_options = HindsightOptions {
+                    beam_width: args.hindsight_beam,
+                    ..HindsightOptions::default()
+                };
+
+                let results: Vec<(i32, Option<i32>)> = (0..args.count)
                     .into_par_iter()
                     .map(|i| {
                         // Give each thread a unique seed, while still being determinated from the root seed
                         let seed_bytes = (seed + i as u64).to_be_bytes();
-                        play(play_mode, seed_bytes)
+                        let dot = args.dot.as_ref().map(|dir| {
+                            let options = DotOptions {
+                                max_depth: args.dot_depth,
+                                min_visits: args.dot_min_visits,
+                            };
+                            (format!("{dir}/game-{i}"), options)
+                        });
+                        let (n, score) = play(
+                            args.engine,
+                            play_mode,
+                            seed_bytes,
+                            heuristics.get(),
+                            args.node_budget,
+                            book.clone(),
+                            dot,
+                        );
+                        let regret = args.regret.then(|| {
+                            let rolls = hindsight::rolls_from_seed(seed_bytes);
+                            let best_found = hindsight::solve_hindsight(&rolls, &hindsight_options);
+                            hindsight::regret(score, best_found.score)
+                        });
+                        (n, score, regret)
                     })
-                    .inspect(|(n, score)| match play_mode {
-                        PlayMode::Iterations(_) => println!("iterations: {n}, score: {score}"),
-                        PlayMode::Duration(_) => println!("{n},{score}"),
+                    .inspect(|(n, score, regret)| match (play_mode, regret) {
+                        (PlayMode::Iterations(_), None) => {
+                            println!("iterations: {n}, score: {score}")
+                        }
+                        (PlayMode::Iterations(_), Some(regret)) => {
+                            println!("iterations: {n}, score: {score}, regret: {regret}");
+                        }
+                        (PlayMode::Duration(_), None) => println!("{n},{score}"),
+                        (PlayMode::Duration(_), Some(regret)) => println!("{n},{score},{regret}"),
                     })
-                    .map(|(_, score)| score as u64)
+                    .map(|(_, score, regret)| (score, regret))
                     .collect();
+                let scores = results
+                    .iter()
+                    .map(|&(score, _)| score as u64)
+                    .collect::<Vec<_>>();
                 println!(
                     "Played {} games, average score {:.1} [{}-{}], finished in {:.1}s",
                     args.count,
@@ -171,26 +562,45 @@ fn main() {
                     scores.iter().max().unwrap(),
                     start_time.elapsed().as_secs_f32(),
                 );
+                if args.regret {
+                    let regrets = results
+                        .iter()
+                        .filter_map(|&(_, regret)| regret)
+                        .collect::<Vec<_>>();
+                    println!(
+                        "Average regret against the hindsight best found {:.1}",
+                        f64::from(regrets.iter().sum::<i32>()) / f64::from(args.count)
+                    );
+                    let missed = regrets.iter().filter(|&&regret| regret < 0).count();
+                    if missed > 0 {
+                        eprintln!(
+                            "The hindsight search scored below the game played in {} games; widen --hindsight-beam",
+                            missed
+                        );
+                    }
+                }
             }
         }
+        Cli::RaveBench(args) => rave_bench(&args),
+        Cli::Analyze(args) => analyze(&args),
+        Cli::BuildBook(args) => build_book(&args),
+        Cli::Tournament(args) => tournament(&args),
+        Cli::Scaling(args) => scaling(&args),
+        Cli::FitHeuristics(args) => fit_heuristics(&args),
+        Cli::Heuristics(args) => heuristics(&args.command),
+        Cli::MigrateHeuristics(args) => {
+            let mut parameters =
+                Parameters::from_json(&args.input).unwrap_or_else(|e| panic!("{}", e));
+            parameters.normalize_units(args.score_range);
+            // Without `--output`, `--in-place` is given
+            let output = args.output.as_ref().unwrap_or(&args.input);
+            parameters
+                .to_json(output)
+                .unwrap_or_else(|e| panic!("Could not write {}: {}", output, e));
+            println!("Wrote normalised parameters to {}", output);
+        }
     }
 
-    // // Run the simulated annealing algorithm_
-    // println!("Running simulated annealing...");
-    // let max_iterations = 10_000;
-    // let initial_temperature = 1.0;
-    // let initial_score = 53.279;
-    // let temperature_decay_rate = 0.95;
-    // let variable = Some(4);
-    // let heuristics = simulated_annealing(
-    //     max_iterations,
-    //     initial_temperature,
-    //     initial_score,
-    //     temperature_decay_rate,
-    //     variable,
-    // );
-    // println!("heuristics: {heuristics:?}");
-
     // use mcts::heuristics::nn::edge_strategy::EdgeStrategy;
     // let mut nn = EdgeStrategy::create_model();
     // nn.train_model_path("model-16-16");
@@ -204,35 +614,463 @@ enum PlayMode {
     Duration(u128),
 }
 
-/// Play single game
+/// Play single game with `engine`
+/// If `dot` is given, the search tree is written to `{prefix}-move-{n}.dot` before every move
 /// Returns duration or iteration and score
-fn play(play_mode: PlayMode, seed: [u8; 8]) -> (u64, i32) {
+fn play(
+    engine: EngineKind,
+    play_mode: PlayMode,
+    seed: [u8; 8],
+    heuristics: Heuristics,
+    node_budget: Option<usize>,
+    book: Option<Arc<OpeningBook>>,
+    dot: Option<(String, DotOptions)>,
+) -> (u64, i32) {
     let mut game = Game::new_from_seed(seed);
-    let mut mcts = MonteCarloTree::new_from_seed(game.clone(), seed);
+    let mut engine = match engine {
+        EngineKind::Mcts => {
+            let mut mcts = MonteCarloTree::new_from_seed(game.clone(), seed);
+            mcts.heuristics = heuristics;
+            if let Some(budget) = node_budget {
+                mcts = mcts.with_node_budget(budget);
+            }
+            if let Some(book) = book {
+                mcts = mcts.with_opening_book(book);
+            }
+            Box::new(MctsEngine::new(mcts))
+        }
+        engine => engine.build(seed, heuristics),
+    };
+    let limits = match play_mode {
+        PlayMode::Iterations(iterations) => SearchLimits::iterations(iterations),
+        PlayMode::Duration(duration) => SearchLimits::duration(duration),
+    };
+    let mut move_number = 0;
 
-    // use mcts::heuristics::nn::edge_strategy::EdgeStrategy;
-    // let nn = EdgeStrategy::load("model-2");
+    play_game(&mut *engine, &mut game, &limits, |engine, _, _| {
+        if let (Some((prefix, options)), Some(mcts)) = (&dot, engine.search_tree()) {
+            let path = format!("{prefix}-move-{move_number:03}.dot");
+            mcts.write_dot(*options, &path)
+                .unwrap_or_else(|e| panic!("Could not write search tree to {}: {}", path, e));
+        }
+        move_number += 1;
+    });
 
-    while !game.ended {
-        let mv = match play_mode {
-            PlayMode::Iterations(iterations) => mcts.search_iterations(iterations).best_move(),
-            PlayMode::Duration(duration) => mcts.search_duration(duration).best_move(),
+    match play_mode {
+        PlayMode::Iterations(iterations) => (iterations, game.board.score()),
+        PlayMode::Duration(duration) => (duration as u64, game.board.score()),
+    }
+}
+
+/// Play the tournament in `args.config`, printing the comparisons after every batch
+fn tournament(args: &TournamentArgs) {
+    let config = TournamentConfig::from_json(&args.config).unwrap_or_else(|e| panic!("{}", e));
+    let mut tournament = Tournament::new(config).unwrap_or_else(|e| panic!("{}", e));
+    let sprt = args.sprt_delta.map(|delta| Sprt {
+        delta,
+        alpha: args.alpha,
+        beta: args.beta,
+    });
+    let options = TournamentOptions {
+        games: args.games,
+        seed: args.seed,
+        batch_size: args.batch_size,
+        sprt,
+    };
+    let start_time = time::Instant::now();
+
+    tournament.run(&options, |tournament| {
+        let report = tournament.report(sprt);
+        println!("After {} games:", report.games);
+        for pair in &report.pairs {
+            let decision = match pair.decision {
+                Some(SprtDecision::AcceptH1) => ", SPRT: better",
+                Some(SprtDecision::AcceptH0) => ", SPRT: not better",
+                Some(SprtDecision::Continue) | None => "",
+            };
+            println!(
+                "  {} - {}: {:+.2} [{:+.2}, {:+.2}]{}",
+                pair.second,
+                pair.first,
+                pair.mean_difference,
+                pair.lower_bound,
+                pair.upper_bound,
+                decision
+            );
+        }
+    });
+
+    let report = tournament.report(sprt);
+    println!(
+        "Played {} games in {:.1}s",
+        report.games,
+        start_time.elapsed().as_secs_f32()
+    );
+    for entrant in &report.entrants {
+        println!(
+            "  {}: {:.1} [{:.1}, {:.1}]",
+            entrant.name, entrant.mean_score, entrant.lower_bound, entrant.upper_bound
+        );
+    }
+    if let Some(path) = &args.csv {
+        tournament
+            .write_csv(path)
+            .unwrap_or_else(|e| panic!("Could not write {}: {}", path, e));
+    }
+    if let Some(path) = &args.json {
+        report
+            .to_json(path)
+            .unwrap_or_else(|e| panic!("Could not write {}: {}", path, e));
+    }
+}
+
+/// Sweep the grid of `args`, printing the mean score of every point to stderr
+fn scaling(args: &ScalingArgs) {
+    let config = args.config.as_ref().map_or_else(
+        || TournamentConfig {
+            entrants: vec![Entrant {
+                name: EngineKind::Mcts.to_string(),
+                engine: EngineKind::Mcts,
+                heuristics: None,
+                model: None,
+                limits: SearchLimits::default(),
+            }],
+        },
+        |path| TournamentConfig::from_json(path).unwrap_or_else(|e| panic!("{}", e)),
+    );
+    let mut scaling = Scaling::new(config).unwrap_or_else(|e| panic!("{}", e));
+    let budgets = if args.durations.is_empty() {
+        let iterations = if args.iterations.is_empty() {
+            vec![1000]
+        } else {
+            args.iterations.clone()
         };
+        iterations.into_iter().map(Budget::Iterations).collect()
+    } else {
+        args.durations
+            .iter()
+            .copied()
+            .map(Budget::Milliseconds)
+            .collect()
+    };
+    let options = ScalingOptions {
+        budgets,
+        parallel_games: args.parallel_games.clone(),
+        games: args.games,
+        seed: args.seed,
+    };
+    let start_time = time::Instant::now();
 
-        // println!(
-        //     "{mv}, pred: {:.1}, depth: {}",
-        //     nn.predict(&game.board, &mv),
-        //     mcts.calculate_depth()
-        // );
-        mcts = MonteCarloTree::progress(mcts, mv, &mut game);
+    scaling
+        .run(&options, |games| {
+            let Some(first) = games.first() else {
+                return;
+            };
+            let count = games.len() as f64;
+            let score = games
+                .iter()
+                .map(|game| f64::from(game.record.score.total()))
+                .sum::<f64>();
+            let speed = games
+                .iter()
+                .map(|game| game.record.iterations_per_second())
+                .sum::<f64>();
+            eprintln!(
+                "{} with {} parallel games, {} {}: average score {:.1}, {:.0} iterations/s",
+                first.config,
+                first.parallel_games,
+                first.budget.value(),
+                first.budget.kind(),
+                score / count,
+                speed / count,
+            );
+        })
+        .unwrap_or_else(|e| panic!("{}", e));
+    eprintln!(
+        "Played {} games in {:.1}s",
+        scaling.games.len(),
+        start_time.elapsed().as_secs_f32()
+    );
+
+    match &args.output {
+        Some(path) => std::fs::File::create(path).and_then(|file| scaling.write_csv(file)),
+        None => scaling.write_csv(std::io::stdout().lock()),
+    }
+    .unwrap_or_else(|e| panic!("Could not write the CSV: {}", e));
+    if let Some(path) = &args.records {
+        scaling
+            .write_records(path)
+            .unwrap_or_else(|e| panic!("Could not write {}: {}", path, e));
     }
+}
 
-    match play_mode {
-        PlayMode::Iterations(iterations) => (iterations, game.board.score()),
-        PlayMode::Duration(duration) => (duration as u64, game.board.score()),
+fn heuristics(command: &HeuristicsCommand) {
+    let load = |path: &str| Parameters::load(path).unwrap_or_else(|e| panic!("{}", e));
+    match command {
+        HeuristicsCommand::Validate { file } => {
+            let problems = load(file).problems();
+            if problems.is_empty() {
+                println!("{} is valid", file);
+            } else {
+                for problem in &problems {
+                    println!("{}", problem);
+                }
+                std::process::exit(1);
+            }
+        }
+        HeuristicsCommand::Diff { first, second } => {
+            let changes = load(first).diff(&load(second));
+            if changes.is_empty() {
+                println!("No differences");
+            }
+            for change in changes {
+                println!("{}", change);
+            }
+        }
+        HeuristicsCommand::Convert {
+            input,
+            output,
+            base,
+        } => {
+            let parameters = match base {
+                Some(base) if is_csv(input) => {
+                    let contents = std::fs::read_to_string(input)
+                        .unwrap_or_else(|e| panic!("Could not read {}: {}", input, e));
+                    Parameters::parse_csv(&contents, load(base)).unwrap_or_else(|e| panic!("{}", e))
+                }
+                Some(_) => panic!("--base only applies to CSV input, not {}", input),
+                None => load(input),
+            };
+            parameters
+                .save(output)
+                .unwrap_or_else(|e| panic!("Could not write {}: {}", output, e));
+            println!("Wrote {} to {}", input, output);
+        }
     }
 }
 
+/// Fit the heuristic weights, and print how well every turn was fitted
+fn fit_heuristics(args: &FitHeuristicsArgs) {
+    let parameters = args.heuristics.as_ref().map_or_else(
+        || Heuristics::default().parameters,
+        |path| Parameters::from_json(path).unwrap_or_else(|e| panic!("{}", e)),
+    );
+    let samples = Sample::load_all();
+    assert!(
+        !samples.is_empty(),
+        "No placements with a turn in data.sqlite. Generate some with `nn --generate-training-data`"
+    );
+    let (fitted, report) = fit_parameters(&parameters, &samples, args.ridge, args.validation);
+
+    for turn in &report.turns {
+        match (turn.validation_rmse, turn.baseline_rmse, turn.validation_r2) {
+            (Some(rmse), Some(baseline), r2) => println!(
+                "Turn {}: {} placements, validation RMSE {:.2} against {:.2} for the mean, R² {:.3}",
+                turn.turn,
+                turn.training_samples + turn.validation_samples,
+                rmse,
+                baseline,
+                r2.unwrap_or_default(),
+            ),
+            _ if turn.fit.is_some() => println!(
+                "Turn {}: {} placements, none held out for validation",
+                turn.turn, turn.training_samples
+            ),
+            _ => println!(
+                "Turn {}: {} placements, too few to fit, keeping its weights",
+                turn.turn,
+                turn.training_samples + turn.validation_samples
+            ),
+        }
+    }
+
+    fitted
+        .to_json(&args.output)
+        .unwrap_or_else(|e| panic!("Could not write {}: {}", args.output, e));
+    println!("Wrote fitted parameters to {}", args.output);
+    if let Some(path) = &args.metrics {
+        report
+            .to_json(path)
+            .unwrap_or_else(|e| panic!("Could not write {}: {}", path, e));
+    }
+}
+
+/// Build the opening book at `args.output`, resuming from it if it exists
+fn build_book(args: &BuildBookArgs) {
+    let mut book = if std::path::Path::new(&args.output).exists() {
+        OpeningBook::load(&args.output).unwrap_or_else(|e| panic!("{}", e))
+    } else {
+        OpeningBook::new()
+    };
+    let parameters = args
+        .heuristics
+        .as_ref()
+        .map_or_else(Heuristics::default, |path| {
+            Heuristics::from_json(path).unwrap_or_else(|e| panic!("{}", e))
+        })
+        .parameters;
+    let options = BookOptions {
+        iterations: args.iterations,
+        fold_symmetries: args.fold_symmetries,
+        parameters,
+        seed: args.seed,
+    };
+
+    let start_time = time::Instant::now();
+    mcts::book::build_book(&mut book, &options, Some(&args.output));
+    println!(
+        "Opening book has {} rolls, finished in {:.1}s",
+        book.entries.len(),
+        start_time.elapsed().as_secs_f32()
+    );
+}
+
+/// Search the position given by `args`, and print the analysis
+fn analyze(args: &AnalyzeArgs) {
+    let limits = match (args.iterations, args.duration) {
+        (None, None) => SearchLimits::duration(1000),
+        (iterations, milliseconds) => SearchLimits {
+            iterations,
+            milliseconds,
+        },
+    };
+
+    let saved = args
+        .tree
+        .as_ref()
+        .filter(|path| std::path::Path::new(path).exists());
+    let mut mcts = if let Some(path) = saved {
+        assert!(
+            args.game.is_none() && args.board.is_none() && args.heuristics.is_none(),
+            "{} holds the position and heuristics to resume; drop --game, --board and --heuristics, or pick a new --tree",
+            path
+        );
+        let mut mcts = MonteCarloTree::load(path).unwrap_or_else(|e| panic!("{}", e));
+        // The model is not saved with the tree
+        mcts.heuristics.move_nn = args.model.as_ref().map(|dir| {
+            Heuristics::load_model(dir, &WgpuDevice::default()).unwrap_or_else(|e| panic!("{}", e))
+        });
+        mcts.heuristics
+            .parameters
+            .check_model(args.model.is_some())
+            .unwrap_or_else(|e| panic!("{}", e));
+        mcts
+    } else {
+        let heuristics = load_heuristics(&args.heuristics, &args.model).get();
+        MonteCarloTree::new_with_heuristics(position(args), heuristics)
+    };
+    let analysis = mcts.search_with_limits(limits).analysis(args.top);
+    if let Some(path) = &args.tree {
+        mcts.save(path)
+            .unwrap_or_else(|e| panic!("Could not write search tree to {}: {}", path, e));
+    }
+
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&analysis).unwrap());
+        return;
+    }
+    println!("iterations: {}", analysis.iterations);
+    for candidate in &analysis.candidates {
+        println!(
+            "{}: visits {}, mean {:.1} [{:.1}, {:.1}]",
+            candidate.mv,
+            candidate.visits,
+            candidate.mean_score,
+            candidate.lower_bound,
+            candidate.upper_bound
+        );
+    }
+    let continuation = analysis
+        .principal_variation
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>();
+    println!("continuation: {}", continuation.join(", "));
+    println!("expected score: {:.1}", analysis.expected_score);
+}
+
+/// The position given by `args`
+fn position(args: &AnalyzeArgs) -> Game {
+    let encoded = match (&args.game, &args.board, &args.roll) {
+        (Some(game), _, _) => game.clone(),
+        (None, Some(board), Some(roll)) => format!(
+            "{:X}|{}|{}||{}",
+            args.turn, roll, args.expended_specials, board
+        ),
+        _ => panic!("Give the position with --game, or with --board and --roll"),
+    };
+    Game::decode(&encoded).unwrap_or_else(|e| panic!("{}", e))
+}
+
+/// The heuristics given by `--heuristics` and `--model`, the defaults without them
+fn load_heuristics(heuristics: &Option<String>, model: &Option<String>) -> SharedHeuristics {
+    SharedHeuristics::load(heuristics.as_deref(), model.as_deref())
+        .unwrap_or_else(|e| panic!("{}", e))
+}
+
+/// Play `args.count` games with the heuristic prior, then the same games with MC-RAVE,
+/// and compare the scores
+fn rave_bench(args: &RaveBenchArgs) {
+    let heuristics = load_heuristics(&args.heuristics, &args.model);
+    let parameters = heuristics.parameters.clone();
+    let seed = args.seed.unwrap_or_else(|| rand::thread_rng().gen());
+
+    let mut rave_parameters = parameters.clone();
+    rave_parameters.mc_rave = Some(McRave {
+        equivalence: args.equivalence,
+    });
+
+    let configurations = [
+        ("heuristic prior", parameters),
+        ("mc-rave", rave_parameters),
+    ];
+    let results = configurations
+        .iter()
+        .map(|(name, parameters)| {
+            let start_time = time::Instant::now();
+            let scores: Vec<i32> = (0..args.count)
+                .into_par_iter()
+                .map(|i| {
+                    let seed_bytes = (seed + u64::from(i)).to_be_bytes();
+                    let mut heuristics = heuristics.get();
+                    heuristics.parameters = parameters.clone();
+                    let (_, score) = play(
+                        EngineKind::Mcts,
+                        PlayMode::Iterations(args.iterations),
+                        seed_bytes,
+                        heuristics,
+                        None,
+                        None,
+                        None,
+                    );
+                    score
+                })
+                .collect();
+            println!(
+                "{name}: average score {:.1} [{}-{}], finished in {:.1}s",
+                f64::from(scores.iter().sum::<i32>()) / f64::from(args.count),
+                scores.iter().min().unwrap(),
+                scores.iter().max().unwrap(),
+                start_time.elapsed().as_secs_f32(),
+            );
+            scores
+        })
+        .collect::<Vec<_>>();
+
+    let differences = results[1]
+        .iter()
+        .zip(&results[0])
+        .map(|(rave, prior)| rave - prior)
+        .collect::<Vec<_>>();
+    println!(
+        "mc-rave - heuristic prior: {:+.1} on average, better in {} and worse in {} of {} games",
+        f64::from(differences.iter().sum::<i32>()) / f64::from(args.count),
+        differences.iter().filter(|&&d| d > 0).count(),
+        differences.iter().filter(|&&d| d < 0).count(),
+        args.count,
+    );
+}
+
 /// Play single game
 /// Returns iterations and score
 pub fn play_and_dump_rave_heuristics(iterations: u64, _i: u64) -> (u64, i32) {
@@ -1,9 +1,117 @@
-// use railroad_ink_solver::mcts::trainer::simulated_annealing;
+use clap::Parser;
+use railroad_ink_solver::mcts::analysis::SearchLimits;
+use railroad_ink_solver::mcts::engine::EngineKind;
+use railroad_ink_solver::mcts::heuristics::{Heuristics, Parameters};
+use railroad_ink_solver::mcts::trainer::spsa::{Spsa, SpsaOptions};
+use std::time;
+
+/// Tune the heuristics parameters with SPSA, saving the tuner after every generation
+#[derive(Parser, Debug)]
+struct TuneArgs {
+    /// File to save the tuner to after every generation, and to resume from if it exists.
+    /// A resumed tuner keeps its options, except for `--generations`
+    checkpoint: String,
+
+    /// Heuristics parameters to start from, as a JSON file. Defaults to `src/mcts/heuristics/default.json`
+    #[arg(long)]
+    heuristics: Option<String>,
+
+    /// File to write the tuned parameters to after every generation
+    #[arg(short, long, default_value = "tuned.json")]
+    output: String,
+
+    /// Number of generations to tune for in total, counting those of a resumed tuner
+    #[arg(long, default_value = "100")]
+    generations: u64,
+
+    /// Rows of the parameters to tune, separated by commas. Defaults to all of them
+    #[arg(long, value_delimiter = ',')]
+    rows: Vec<String>,
+
+    /// Engine to play the games with, see `main play --engine`
+    #[arg(long, default_value = "mcts")]
+    engine: EngineKind,
+
+    /// Number of mcts search iterations per move
+    #[arg(short, long, default_value = "300")]
+    iterations: u64,
+
+    /// Number of games to play with either perturbation in every generation
+    #[arg(long, default_value = "16")]
+    games: u64,
+
+    /// Seed of the first game. Every generation plays new games
+    #[arg(long, default_value = "0")]
+    seed: u64,
+
+    /// Step size of the first generation, relative to the values
+    #[arg(long, default_value = "0.01")]
+    learning_rate: f64,
+
+    /// Perturbation of the first generation, relative to the values
+    #[arg(long, default_value = "0.2")]
+    perturbation: f64,
+}
 
 fn main() {
-    // Run the simulated annealing algorithm_
-    println!("Running simulated annealing...");
+    let args = TuneArgs::parse();
+
+    let mut spsa = if std::path::Path::new(&args.checkpoint).exists() {
+        let spsa = Spsa::load(&args.checkpoint).unwrap_or_else(|e| panic!("{}", e));
+        println!(
+            "Resuming from {} after {} generations",
+            args.checkpoint,
+            spsa.history.len()
+        );
+        spsa
+    } else {
+        let parameters = args.heuristics.as_ref().map_or_else(
+            || Heuristics::default().parameters,
+            |path| Parameters::from_json(path).unwrap_or_else(|e| panic!("{}", e)),
+        );
+        parameters
+            .check_model(false)
+            .unwrap_or_else(|e| panic!("{}", e));
+        let rows = if args.rows.is_empty() {
+            SpsaOptions::default().rows
+        } else {
+            args.rows
+                .iter()
+                .map(|name| {
+                    Parameters::ROW_NAMES
+                        .iter()
+                        .position(|row| row == name)
+                        .unwrap_or_else(|| panic!("Unknown parameter row: {}", name))
+                })
+                .collect()
+        };
+        let options = SpsaOptions {
+            rows,
+            engine: args.engine,
+            limits: SearchLimits::iterations(args.iterations),
+            games: args.games,
+            seed: args.seed,
+            learning_rate: args.learning_rate,
+            perturbation: args.perturbation,
+            ..SpsaOptions::default()
+        };
+        Spsa::new(parameters, options)
+    };
 
-    // let heuristics = simulated_annealing(100, 1.0, 46.279, 0.98);
-    // println!("heuristics: {heuristics:?}");
+    while (spsa.history.len() as u64) < args.generations {
+        let start_time = time::Instant::now();
+        let generation = spsa.step();
+        println!(
+            "Generation {}: {:.2} up, {:.2} down, in {:.1}s",
+            generation.generation,
+            generation.plus_score,
+            generation.minus_score,
+            start_time.elapsed().as_secs_f32()
+        );
+        spsa.parameters
+            .to_json(&args.output)
+            .unwrap_or_else(|e| panic!("Could not write {}: {}", args.output, e));
+        spsa.save(&args.checkpoint)
+            .unwrap_or_else(|e| panic!("Could not write {}: {}", args.checkpoint, e));
+    }
 }
@@ -29,6 +29,26 @@ use std::collections::{HashMap, HashSet, LinkedList};
 pub mod placement;
 use placement::{Orientation, Placement};
 
+/// The points of a board by scoring category, as counted by `Board::score`
+#[derive(Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ScoreBreakdown {
+    /// Points for exits connected by each network
+    pub networks: i32,
+    pub longest_road: i32,
+    pub longest_rail: i32,
+    /// One point for each filled square of the center
+    pub center: i32,
+    /// Penalty points for connections that end open, counted positively
+    pub open_ends: i32,
+}
+
+impl ScoreBreakdown {
+    #[must_use]
+    pub const fn total(&self) -> i32 {
+        self.networks + self.longest_road + self.longest_rail + self.center - self.open_ends
+    }
+}
+
 /// `Board` represents the squares and placements on a railroad ink board
 /// TODO: _Optimization ideas_
 /// * don't stick exits into the frontier. Check when looking up tiles instead
@@ -455,6 +475,12 @@ impl Board {
     /// We just apply the rules of the game, but assume we always get the longest road and rail
     #[must_use]
     pub fn score(&self) -> i32 {
+        self.score_breakdown().total()
+    }
+
+    /// The parts of `score`
+    #[must_use]
+    pub fn score_breakdown(&self) -> ScoreBreakdown {
         let networks = self.get_networks();
 
         let network_score = networks
@@ -471,7 +497,6 @@ impl Board {
             .flat_map(|(square, connections)| connections.iter().map(move |(d, c)| (square, d, c)))
             .filter_map(|(&square, &dir, _)| self.get(Self::get_neighbor(square, dir)).as_ref())
             .count();
-        let open_end_score = i32::try_from(open_end_score).unwrap_or(i32::MAX);
 
         #[rustfmt::skip]
         let center_tiles: [(u8, u8); 9] = [
@@ -488,13 +513,179 @@ impl Board {
 
         let end_nodes = self.get_end_nodes();
         let longest_rail = self.get_longest(Rail, &end_nodes);
-        let rail_score = longest_rail.len();
         let longest_road = self.get_longest(Road, &end_nodes);
-        let road_score = longest_road.len();
 
-        let score = network_score + road_score + rail_score + center_tile_score;
+        let points = |count: usize| i32::try_from(count).unwrap_or(i32::MAX);
+        ScoreBreakdown {
+            networks: points(network_score),
+            longest_road: points(longest_road.len()),
+            longest_rail: points(longest_rail.len()),
+            center: points(center_tile_score),
+            open_ends: points(open_end_score),
+        }
+    }
+
+    /// An upper bound on `score` after placing up to `pieces` more pieces, much cheaper to compute.
+    /// Every new piece is assumed to connect a new exit and merge all networks, to fill
+    /// the center, to close as many open ends as any one square has, and to join the
+    /// largest roads and rails into one. With no pieces left, the network, center and
+    /// open end scores are exact, and only the longest road and rail are bounded, by
+    /// the tiles a path could pass through.
+    #[must_use]
+    pub fn score_upper_bound(&self, pieces: usize) -> i32 {
+        let connections = self.get_connection_table();
+        let network_score = if pieces == 0 {
+            self.get_networks()
+                .iter()
+                .map(|(_, exits)| match exits {
+                    12 => 45,
+                    exits => usize::from(exits.saturating_sub(1)) * 4,
+                })
+                .sum::<usize>()
+        } else {
+            let exits = Self::EXITS
+                .iter()
+                .filter(|(square, (direction, connection))| {
+                    self.has(*square)
+                        && connections[square.raw as usize][*direction as usize] == *connection
+                })
+                .count();
+            match (exits + pieces).min(12) {
+                12 => 45,
+                exits => exits.saturating_sub(1) * 4,
+            }
+        };
+
+        let center_tiles = (2..=4)
+            .flat_map(|y| (2..=4).map(move |x| Square::new(x, y)))
+            .filter(|&square| self.has(square))
+            .count();
+        let center_tile_score = (center_tiles + pieces).min(9);
+
+        let mut open_ends = self
+            .frontier
+            .iter()
+            .map(|(&square, connections)| {
+                let count = connections
+                    .iter()
+                    .filter(|&&(dir, _)| self.has(Self::get_neighbor(square, dir)))
+                    .count();
+                (self.has(square), count)
+            })
+            .collect::<Vec<_>>();
+        let open_end_score = open_ends.iter().map(|(_, count)| count).sum::<usize>();
+        // Only the open ends into an empty square can be closed, a square at a time
+        open_ends.retain(|&(placed, _)| !placed);
+        open_ends.sort_unstable_by_key(|&(_, count)| std::cmp::Reverse(count));
+        let closable = open_ends
+            .iter()
+            .take(pieces)
+            .map(|(_, count)| count)
+            .sum::<usize>();
+        let open_end_score = open_end_score - closable;
+
+        // A path visits tiles connected to only one other tile at its ends only,
+        // and a path through the new pieces visits at most one part of a road between each
+        let path_bound = |connection| {
+            let mut components = self.get_component_sizes(&connections, connection);
+            if pieces == 0 {
+                return components
+                    .iter()
+                    .map(|&(size, dead_ends)| size - dead_ends.saturating_sub(2))
+                    .max()
+                    .unwrap_or(0);
+            }
+            components.sort_unstable_by_key(|&(size, _)| std::cmp::Reverse(size));
+            pieces
+                + components
+                    .iter()
+                    .take(pieces + 1)
+                    .map(|(size, _)| size)
+                    .sum::<usize>()
+        };
+
+        let score = network_score + path_bound(Road) + path_bound(Rail) + center_tile_score;
         let score = i32::try_from(score).unwrap_or(i32::MAX);
-        score - open_end_score
+        score - i32::try_from(open_end_score).unwrap_or(i32::MAX)
+    }
+
+    /// Sizes of the groups of tiles connected by `connection`, and the number of tiles in
+    /// each group connected to only one other tile, found with union-find
+    fn get_component_sizes(
+        &self,
+        connections: &[[Connection; 4]; (BOARD_SIZE as usize).pow(2)],
+        connection: Connection,
+    ) -> Vec<(usize, usize)> {
+        fn find(parents: &mut [u8], square: u8) -> u8 {
+            let parent = parents[square as usize];
+            if parent == square {
+                return square;
+            }
+            let root = find(parents, parent);
+            parents[square as usize] = root;
+            root
+        }
+
+        let typed = |square: Square<BOARD_SIZE>| {
+            self.has(square) && connections[square.raw as usize].contains(&connection)
+        };
+        let mut parents: [u8; (BOARD_SIZE as usize).pow(2)] = std::array::from_fn(|i| i as u8);
+        let mut degrees = [0_u8; (BOARD_SIZE as usize).pow(2)];
+        for square in self
+            .values()
+            .map(|place| place.square)
+            .filter(|&s| typed(s))
+        {
+            for direction in Direction::iter() {
+                let neighbor = Self::get_neighbor(square, direction);
+                if connections[square.raw as usize][direction as usize] == connection
+                    && self.has(neighbor)
+                    && connections[neighbor.raw as usize][direction.inverse() as usize]
+                        == connection
+                {
+                    degrees[square.raw as usize] += 1;
+                    let a = find(&mut parents, square.raw);
+                    let b = find(&mut parents, neighbor.raw);
+                    parents[a as usize] = b;
+                }
+            }
+        }
+
+        let mut components = [(0, 0); (BOARD_SIZE as usize).pow(2)];
+        for square in self
+            .values()
+            .map(|place| place.square)
+            .filter(|&s| typed(s))
+        {
+            let (size, dead_ends) = &mut components[find(&mut parents, square.raw) as usize];
+            *size += 1;
+            if degrees[square.raw as usize] == 1 {
+                *dead_ends += 1;
+            }
+        }
+        components
+            .iter()
+            .copied()
+            .filter(|&(size, _)| size > 0)
+            .collect()
+    }
+
+    /// The connection of every placed tile in every direction, indexed by square and direction
+    fn get_connection_table(&self) -> [[Connection; 4]; (BOARD_SIZE as usize).pow(2)] {
+        let mut connections = [[Connection::None; 4]; (BOARD_SIZE as usize).pow(2)];
+        for place in self.values() {
+            for network in place.get_networks().iter().flatten() {
+                for (connection, &network_connection) in connections[place.square.raw as usize]
+                    .iter_mut()
+                    .zip(network)
+                {
+                    if *connection == Connection::None {
+                        *connection = network_connection;
+                    }
+                }
+            }
+        }
+        connections
     }
 
     /// For each node, DFS through all connected nodes of same type
@@ -712,6 +903,11 @@ impl PartialEq for Board {
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::game::mv::Move;
+    use crate::game::Game;
+    use rand::seq::SliceRandom;
+    use rand::SeedableRng;
+    use rand_xoshiro::SplitMix64;
 
     #[test]
     fn cannot_place_tiles_into_wrong_network_type() {
@@ -723,4 +919,62 @@ mod test {
         let candidates = board.find_possible(3);
         assert_eq!(candidates.len(), 0);
     }
+
+    #[test]
+    fn score_upper_bound_is_an_upper_bound() {
+        let encoding = String::from(
+      "6F0315F0113G0122G0102F0121F0220F0310B0311B0231C0301D0133A0303B0104B0315B0D06B0315C0305D010",
+    );
+        let board = Board::decode(&encoding);
+
+        let score = board.score();
+        assert!(board.score_upper_bound(0) >= score);
+        for pieces in 1..6 {
+            assert!(board.score_upper_bound(pieces) >= board.score_upper_bound(pieces - 1));
+        }
+        for placement in board.find_possible(0x05) {
+            let mut next = board.clone();
+            next.place(placement);
+            assert!(board.score_upper_bound(1) >= next.score());
+        }
+    }
+
+    #[test]
+    fn score_upper_bound_holds_over_games() {
+        for seed in 0..8u8 {
+            let mut game = Game::new_from_seed([0, 0, 0, 0, 0, 0, 1, seed]);
+            let mut rng = SplitMix64::seed_from_u64(u64::from(seed));
+            // The board before every placement of the game
+            let mut boards = vec![];
+            while !game.ended {
+                let mv = *game.generate_moves().choose(&mut rng).unwrap();
+                if matches!(mv, Move::Place(_)) {
+                    boards.push(game.board.clone());
+                }
+                game.do_move(mv);
+            }
+
+            let score = game.board.score();
+            assert!(game.board.score_upper_bound(0) >= score);
+            let placements = boards.len();
+            for (placed, board) in boards.iter().enumerate() {
+                let bound = board.score_upper_bound(placements - placed);
+                assert!(bound >= score, "seed {}: {} < {}", seed, bound, score);
+            }
+        }
+    }
+
+    #[test]
+    fn score_breakdown_adds_up_to_the_score() {
+        let encoding = String::from(
+      "6F0315F0113G0122G0102F0121F0220F0310B0311B0231C0301D0133A0303B0104B0315B0D06B0315C0305D010",
+    );
+        let board = Board::decode(&encoding);
+
+        let breakdown = board.score_breakdown();
+        assert_eq!(breakdown.total(), board.score());
+        assert!(breakdown.center <= 9);
+        assert!(breakdown.networks > 0);
+        assert_eq!(Board::new().score_breakdown(), ScoreBreakdown::default());
+    }
 }
@@ -1,13 +1,20 @@
 use crate::console_log;
 use crate::game::Game;
+use crate::mcts::analysis::SearchLimits;
+use crate::mcts::engine::{Engine, EngineKind};
+use crate::mcts::heuristics::Heuristics;
+use crate::mcts::planner::{BeamPlanner, PlannerOptions};
 use crate::mcts::MonteCarloTree;
 use crate::utils::set_panic_hook;
+use rand::Rng;
 use wasm_bindgen::prelude::*;
 
 #[wasm_bindgen]
 pub struct GameController {
     game: Game,
     mcts: Option<MonteCarloTree>,
+    /// Plays the moves of `autoplay`
+    engine: Box<dyn Engine>,
 }
 
 impl Default for GameController {
@@ -26,6 +33,7 @@ impl GameController {
         Self {
             game: Game::default(),
             mcts: None,
+            engine: EngineKind::Mcts.build(rand::thread_rng().gen(), Heuristics::default()),
         }
     }
 
@@ -128,10 +136,46 @@ impl GameController {
         serde_wasm_bindgen::to_value(&mv).unwrap()
     }
 
+    #[wasm_bindgen(js_name = planTurn)]
+    /// The moves for the rest of the turn, planned by a beam search keeping `beam_width`
+    /// boards, and looking ahead at `lookahead_rolls` rolls of the next turn.
+    /// Much faster than `searchFor`, see `BeamPlanner`.
+    ///
+    /// # Panics
+    /// Panics if serde can't serialize
+    pub fn plan_turn(&mut self, beam_width: u32, lookahead_rolls: u32) -> JsValue {
+        let options = PlannerOptions {
+            beam_width: (beam_width as usize).max(1),
+            lookahead_rolls: lookahead_rolls as usize,
+            ..PlannerOptions::default()
+        };
+        let seed = rand::thread_rng().gen();
+        let mut planner = BeamPlanner::new(Heuristics::default(), options, seed);
+        serde_wasm_bindgen::to_value(&planner.plan_turn(&self.game)).unwrap()
+    }
+
     pub fn autoplay(&mut self, iterations: u32) {
-        let mut mcts = MonteCarloTree::new(self.game.clone());
-        mcts.search_iterations(u64::from(iterations));
-        let mv = mcts.best_move();
+        let limits = SearchLimits::iterations(u64::from(iterations));
+        let mv = self.engine.choose_move(&self.game, &limits);
+        self.engine.observe(&self.game, mv);
         self.game.do_move(mv);
     }
+
+    /// Choose the engine for `autoplay`, as named by `EngineKind`, like `mcts` or `beam:8:2`
+    ///
+    /// # Panics
+    /// Panics if serde can't serialize
+    ///
+    /// # Errors
+    /// Returns an error if the engine is unknown
+    #[wasm_bindgen(js_name = setEngine)]
+    pub fn set_engine(&mut self, engine: &str) -> Result<(), JsValue> {
+        match engine.parse::<EngineKind>() {
+            Ok(kind) => {
+                self.engine = kind.build(rand::thread_rng().gen(), Heuristics::default());
+                Ok(())
+            }
+            Err(message) => Err(serde_wasm_bindgen::to_value(&message).unwrap()),
+        }
+    }
 }
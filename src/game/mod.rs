@@ -4,6 +4,7 @@ use crate::pieces::Piece;
 use rand_xoshiro::SplitMix64;
 use serde::Serialize;
 use serde_with::serde_as;
+use std::convert::TryInto;
 
 pub mod mv;
 use mv::Move;
@@ -13,7 +14,6 @@ use roll::Roll;
 
 use crate::console_log;
 
-use rand::seq::SliceRandom;
 use rand::Rng;
 use rand::SeedableRng;
 
@@ -60,7 +60,9 @@ impl Game {
         game
     }
 
-    fn can_play_specials(&self) -> bool {
+    /// Whether a special piece may still be placed this turn
+    #[must_use]
+    pub fn can_play_specials(&self) -> bool {
         self.special_placed.is_none()
             && self
                 .expended_specials
@@ -107,18 +109,7 @@ impl Game {
     /// # Panics
     /// Should not panic ... I just haven't gotten around to ensure the code itself assert that
     pub fn generate_roll(&mut self) -> Roll {
-        static COMMON: [u8; 6] = [0x01, 0x02, 0x03, 0x04, 0x05, 0x06];
-        static TRANSITIONAL: [u8; 3] = [0x07, 0x08, 0x09];
-        // let mut rng = thread_rng();
-
-        let mut roll = [
-            *COMMON.choose(&mut self.rng).unwrap(),
-            *COMMON.choose(&mut self.rng).unwrap(),
-            *COMMON.choose(&mut self.rng).unwrap(),
-            *TRANSITIONAL.choose(&mut self.rng).unwrap(),
-        ];
-        roll.sort_unstable();
-        Roll(roll)
+        Roll::sample(&mut self.rng)
     }
 
     /// Roll the dice and set the game state to the new roll
@@ -142,6 +133,30 @@ impl Game {
         self.to_place = roll.to_vec();
     }
 
+    /// The generator the dice of this game are rolled with
+    #[must_use]
+    pub fn rng(&self) -> &SplitMix64 {
+        &self.rng
+    }
+
+    pub fn set_rng(&mut self, rng: SplitMix64) {
+        self.rng = rng;
+    }
+
+    /// Replace the roll of the current turn, without advancing the turn.
+    /// Used by search to explore a different outcome of the dice than the one rolled.
+    pub fn replace_roll(&mut self, roll: Roll) {
+        self.to_place = roll.to_vec();
+        self.available_moves = None;
+    }
+
+    /// The roll of the current turn, if no pieces have been placed yet this turn
+    #[must_use]
+    pub fn current_roll(&self) -> Option<Roll> {
+        let roll: [u8; 4] = self.to_place.clone().try_into().ok()?;
+        Some(Roll::new(roll))
+    }
+
     /// Generate all possible placements given the current game state
     /// If there are no moves for the remaining rolled pieces, `Move::Roll` is added to the list.
     /// If we can play specials in this turn, specials are added to the list
@@ -342,6 +357,7 @@ impl PartialEq for Game {
 #[cfg(test)]
 mod test {
     use super::*;
+    use rand::seq::SliceRandom;
 
     #[test]
     fn test_game_fresh_encoding() {
@@ -1,4 +1,7 @@
+use rand::seq::SliceRandom;
+use rand::Rng;
 use std::hash::{Hash, Hasher};
+use std::sync::OnceLock;
 
 #[derive(Debug, Clone, Copy, Eq)]
 pub struct Roll(pub [u8; 4]);
@@ -52,3 +55,123 @@ impl ToString for Roll {
             .join(",")
     }
 }
+
+/// Faces of the three common dice
+pub const COMMON_FACES: [u8; 6] = [0x01, 0x02, 0x03, 0x04, 0x05, 0x06];
+/// Faces of the transitional die
+pub const TRANSITIONAL_FACES: [u8; 3] = [0x07, 0x08, 0x09];
+
+impl Roll {
+    /// Every distinct sorted roll of three common dice and one transitional die,
+    /// with its exact probability
+    #[must_use]
+    pub fn outcomes() -> &'static [(Self, f64)] {
+        static OUTCOMES: OnceLock<Vec<(Roll, f64)>> = OnceLock::new();
+        OUTCOMES.get_or_init(|| {
+            let mut outcomes = Vec::new();
+
+            for (i, &a) in COMMON_FACES.iter().enumerate() {
+                for (j, &b) in COMMON_FACES.iter().enumerate().skip(i) {
+                    for &c in &COMMON_FACES[j..] {
+                        for &t in &TRANSITIONAL_FACES {
+                            let roll = Self([a, b, c, t]);
+                            outcomes.push((roll, roll.probability()));
+                        }
+                    }
+                }
+            }
+            outcomes
+        })
+    }
+
+    /// Roll the dice with `rng`. Every roll comes up with its `probability`, so this
+    /// is also how to draw from `outcomes`.
+    /// # Panics
+    /// Never: every die has faces
+    #[must_use]
+    pub fn sample<R: Rng + ?Sized>(rng: &mut R) -> Self {
+        let mut roll = [
+            *COMMON_FACES.choose(rng).unwrap(),
+            *COMMON_FACES.choose(rng).unwrap(),
+            *COMMON_FACES.choose(rng).unwrap(),
+            *TRANSITIONAL_FACES.choose(rng).unwrap(),
+        ];
+        roll.sort_unstable();
+        Self(roll)
+    }
+
+    /// The exact probability of rolling `self`
+    #[must_use]
+    pub fn probability(&self) -> f64 {
+        let [a, b, c, t] = self.0;
+        let is_roll = [a, b, c].iter().all(|face| COMMON_FACES.contains(face))
+            && TRANSITIONAL_FACES.contains(&t)
+            && a <= b
+            && b <= c;
+        if !is_roll {
+            return 0.;
+        }
+
+        // Number of orderings of the three common dice giving this roll
+        let orderings = match (a == b, b == c) {
+            (true, true) => 1.,
+            (false, false) => 6.,
+            _ => 3.,
+        };
+        orderings / (COMMON_FACES.len() as f64).powi(3) / TRANSITIONAL_FACES.len() as f64
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_roll_outcomes_are_a_distribution() {
+        let outcomes = Roll::outcomes();
+        // 56 multisets of three common dice, times three transitional faces
+        assert_eq!(outcomes.len(), 56 * 3);
+
+        let total: f64 = outcomes.iter().map(|(_, p)| p).sum();
+        assert!((total - 1.0).abs() < 1e-12);
+
+        let mut rolls = outcomes
+            .iter()
+            .map(|(roll, _)| u32::from(roll))
+            .collect::<Vec<_>>();
+        rolls.sort_unstable();
+        rolls.dedup();
+        assert_eq!(rolls.len(), outcomes.len());
+    }
+
+    #[test]
+    fn test_roll_probability() {
+        let p = 1. / 216. / 3.;
+        assert!((Roll([1, 1, 1, 7]).probability() - p).abs() < 1e-12);
+        assert!((Roll([1, 1, 2, 8]).probability() - 3. * p).abs() < 1e-12);
+        assert!((Roll([1, 2, 3, 9]).probability() - 6. * p).abs() < 1e-12);
+        assert!(Roll([7, 7, 7, 7]).probability() == 0.);
+    }
+
+    #[test]
+    fn test_sampled_rolls_follow_their_probability() {
+        let mut rng = rand_xoshiro::SplitMix64::seed_from_u64(5);
+        let samples = 200_000;
+        let mut counts = std::collections::HashMap::new();
+        for _ in 0..samples {
+            *counts.entry(Roll::sample(&mut rng).0).or_insert(0) += 1;
+        }
+        for (roll, p) in Roll::outcomes() {
+            let frequency =
+                f64::from(counts.get(&roll.0).copied().unwrap_or(0)) / f64::from(samples);
+            assert!(
+                (frequency - p).abs() < 0.002,
+                "{:?}: {} vs {}",
+                roll,
+                frequency,
+                p
+            );
+        }
+    }
+}
@@ -0,0 +1,114 @@
+//! MC-RAVE: all-moves-as-first (AMAF) statistics per node.
+//!
+//! Every iteration records the placements it makes below each node, both in the
+//! tree and in the rollout. On the way back up, each child edge of a node whose
+//! move was among those placements gets the result added to its AMAF statistics,
+//! as if the move had been played first. The AMAF statistics of a node are kept
+//! on its child edges, since those are the only moves it needs them for.
+//!
+//! The trail of placements an iteration made is kept by the tree, next to its
+//! nodes, as it only lives for the iteration.
+//!
+//! When selecting, the AMAF mean stands in for the heuristic estimate in the
+//! UCT blend, with the weight `beta = sqrt(k / (3n + k))` of Gelly and Silver,
+//! where `n` is the number of visits and `k` the `equivalence` parameter.
+
+use super::{Edge, Score};
+use crate::game::mv::Move;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct McRave {
+    /// Number of visits at which the mean of the edge and its AMAF mean weigh the same
+    pub equivalence: f64,
+}
+
+impl Default for McRave {
+    fn default() -> Self {
+        Self { equivalence: 500. }
+    }
+}
+
+impl McRave {
+    /// Weight of the AMAF mean for an edge with `visits` visits
+    #[must_use]
+    pub fn beta(self, visits: u64) -> f64 {
+        let k = self.equivalence;
+        (k / 3.0f64.mul_add(visits as f64, k)).sqrt()
+    }
+}
+
+impl Edge {
+    fn add_amaf_score(&mut self, score: Score) {
+        self.amaf_visits += 1;
+        self.amaf_score += (score - self.amaf_score) / self.amaf_visits as f64;
+    }
+}
+
+/// The placements made by the current iteration at or below the node being
+/// backpropagated. Kept by the tree, in `NodeArena::amaf_trail`.
+#[derive(Debug, Default)]
+pub struct AmafTrail(HashSet<Move>);
+
+impl AmafTrail {
+    /// Forget the placements of the previous iteration
+    pub fn start(&mut self) {
+        self.0.clear();
+    }
+
+    /// Record that `mv` was played in the current iteration, if MC-RAVE is on
+    pub fn extend(&mut self, rave: Option<McRave>, mv: Move) {
+        if rave.is_some() && matches!(mv, Move::Place(_)) {
+            self.0.insert(mv);
+        }
+    }
+
+    /// Add `score` to the AMAF statistics of every edge in `children`
+    /// whose move was played at or below their node in the current iteration
+    pub fn update(&self, children: &mut [Edge], score: Score) {
+        if self.0.is_empty() {
+            return;
+        }
+        for edge in children {
+            if self.0.contains(&edge.mv) {
+                edge.add_amaf_score(score);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::game::Game;
+    use crate::mcts::{MonteCarloTree, Single};
+
+    #[test]
+    fn test_beta_schedule() {
+        let rave = McRave { equivalence: 100. };
+        assert!((rave.beta(0) - 1.).abs() < 1e-9);
+        assert!((rave.beta(100) - 0.5).abs() < 1e-9);
+        assert!(rave.beta(10_000) < 0.1);
+    }
+
+    #[test]
+    fn test_amaf_statistics_cover_more_than_visits() {
+        let seed = [0, 0, 0, 0, 0, 0, 0, 29];
+        let game = Game::new_from_seed(seed);
+        let mut mcts = MonteCarloTree::new_from_seed(game, seed);
+        mcts.heuristics.parameters.mc_rave = Some(McRave::default());
+        mcts.search_iterations(300);
+
+        let Some(Single(node_id)) = mcts.root.child else {
+            panic!("Root was not expanded")
+        };
+        let children = &mcts.arena[node_id].children;
+        // Each iteration counts for every move it plays, not only the first one
+        let amaf_visits = children.iter().map(|edge| edge.amaf_visits).sum::<u64>();
+        let visits = children.iter().map(|edge| edge.visits).sum::<u64>();
+        assert!(amaf_visits > visits);
+        // Every visit of an edge plays its move
+        assert!(children.iter().all(|edge| edge.amaf_visits >= edge.visits));
+    }
+}
@@ -0,0 +1,281 @@
+//! Analysis of a single position: the moves worth considering and what to expect from them.
+//!
+//! `analyze` searches a position within some `SearchLimits` and reports the
+//! most visited moves with their mean scores and confidence bounds, the moves
+//! the search expects to follow the best one for the rest of the turn, and the
+//! expected final score.
+
+use super::halving::RootAlgorithm;
+use super::heuristics::Heuristics;
+use super::{Edge, MonteCarloTree, Score, Single};
+use crate::game::mv::Move;
+use crate::game::Game;
+use serde::{Deserialize, Serialize};
+use std::time::Instant;
+
+/// Standard normal quantile for the 95% confidence bounds of the candidates
+const CONFIDENCE_Z: f64 = 1.96;
+
+/// When to stop searching. With both limits, the search stops at whichever comes first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct SearchLimits {
+    pub iterations: Option<u64>,
+    pub milliseconds: Option<u128>,
+}
+
+impl SearchLimits {
+    #[must_use]
+    pub fn iterations(iterations: u64) -> Self {
+        Self {
+            iterations: Some(iterations),
+            milliseconds: None,
+        }
+    }
+
+    #[must_use]
+    pub fn duration(milliseconds: u128) -> Self {
+        Self {
+            iterations: None,
+            milliseconds: Some(milliseconds),
+        }
+    }
+}
+
+/// A move at the root, and how it fared in the search
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Candidate {
+    pub mv: Move,
+    pub visits: u64,
+    pub mean_score: Score,
+    /// Bounds of the 95% confidence interval of the mean score
+    pub lower_bound: Score,
+    pub upper_bound: Score,
+}
+
+impl From<&Edge> for Candidate {
+    fn from(edge: &Edge) -> Self {
+        let mean_score = edge.distribution.mean;
+        let margin = CONFIDENCE_Z * (edge.variance() / edge.visits.max(1) as f64).sqrt();
+        Self {
+            mv: edge.mv,
+            visits: edge.visits,
+            mean_score,
+            lower_bound: mean_score - margin,
+            upper_bound: mean_score + margin,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Analysis {
+    /// The most visited moves, most visited first
+    pub candidates: Vec<Candidate>,
+    /// The best move, followed by the most visited moves below it up to the end of the turn
+    pub principal_variation: Vec<Move>,
+    /// Mean final score of the best move
+    pub expected_score: Score,
+    /// Number of iterations searched from the root. 0 when the move comes from a
+    /// planned line, see `MonteCarloTree::planned_move`.
+    pub iterations: u64,
+}
+
+impl MonteCarloTree {
+    /// Search until one of `limits` is reached. The iterations are spread over the root
+    /// by `Parameters::root_algorithm`; Sequential Halving needs an iteration limit, and
+    /// searches like every other node without one. The final move is then settled, see
+    /// `settle_final_move`. Nothing is searched while there is a planned move, see
+    /// `planned_move`, or without any limit.
+    pub fn search_with_limits(&mut self, limits: SearchLimits) -> &mut Self {
+        if self.planned_move().is_some() || limits == SearchLimits::default() {
+            return self;
+        }
+        let start = Instant::now();
+        let out_of_time = || {
+            limits
+                .milliseconds
+                .is_some_and(|milliseconds| start.elapsed().as_millis() >= milliseconds)
+        };
+        match (self.heuristics.parameters.root_algorithm, limits.iterations) {
+            (RootAlgorithm::SequentialHalving { top_k, gumbel }, Some(iterations)) => {
+                self.search_sequential_halving(iterations, &out_of_time, top_k, gumbel);
+            }
+            (_, iterations) => {
+                let mut spent = 0;
+                while iterations.is_none_or(|iterations| spent < iterations) && !out_of_time() {
+                    self.search();
+                    spent += 1;
+                }
+            }
+        }
+        self.settle_final_move()
+    }
+
+    /// Report on the current state of the search, with the `top_n` most visited moves.
+    /// When the game follows a planned line, the line's move is the only candidate,
+    /// with the line's expected score.
+    #[must_use]
+    pub fn analysis(&mut self, top_n: usize) -> Analysis {
+        if let Some(mv) = self.planned_move() {
+            return self.planned_analysis(mv);
+        }
+        let best_move = self.best_move();
+
+        let mut children = self
+            .root_children()
+            .iter()
+            .filter(|edge| edge.visits > 0)
+            .collect::<Vec<_>>();
+        children.sort_by_key(|edge| std::cmp::Reverse(edge.visits));
+
+        let best = children.iter().find(|edge| edge.mv == best_move).copied();
+        let expected_score =
+            best.map_or(self.root.distribution.mean, |edge| edge.distribution.mean);
+        let principal_variation =
+            best.map_or_else(|| vec![best_move], |edge| self.principal_variation(edge));
+
+        Analysis {
+            candidates: children
+                .into_iter()
+                .take(top_n)
+                .map(Candidate::from)
+                .collect(),
+            principal_variation,
+            expected_score,
+            iterations: self.root.visits,
+        }
+    }
+
+    /// The analysis of a position where `mv` is the next move of the planned line
+    fn planned_analysis(&self, mv: Move) -> Analysis {
+        let expected_score = self.planned_score.unwrap_or(self.root.distribution.mean);
+        let turn_end = self
+            .planned_line
+            .iter()
+            .position(|mv| !matches!(mv, Move::Place(_)))
+            .map_or(self.planned_line.len(), |end| end + 1);
+        Analysis {
+            candidates: vec![Candidate {
+                mv,
+                visits: 0,
+                mean_score: expected_score,
+                lower_bound: expected_score,
+                upper_bound: expected_score,
+            }],
+            principal_variation: self.planned_line[..turn_end].to_vec(),
+            expected_score,
+            iterations: self.root.visits,
+        }
+    }
+
+    /// `edge`'s move, and the most visited moves below it until the turn ends
+    fn principal_variation<'a>(&'a self, mut edge: &'a Edge) -> Vec<Move> {
+        let mut moves = vec![edge.mv];
+        while matches!(edge.mv, Move::Place(_)) {
+            let Some(Single(node_id)) = edge.child else {
+                break;
+            };
+            let Some(next) = self.arena[node_id]
+                .children
+                .iter()
+                .filter(|edge| edge.visits > 0)
+                .max_by_key(|edge| edge.visits)
+            else {
+                break;
+            };
+            moves.push(next.mv);
+            edge = next;
+        }
+        moves
+    }
+}
+
+/// Search `game` with `heuristics` until one of `limits` is reached, and report
+/// the `top_n` most visited moves
+#[must_use]
+pub fn analyze(game: Game, heuristics: Heuristics, limits: SearchLimits, top_n: usize) -> Analysis {
+    let mut mcts = MonteCarloTree::new_with_heuristics(game, heuristics);
+    mcts.search_with_limits(limits);
+    mcts.analysis(top_n)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mcts::solver::solve_final_round;
+
+    #[test]
+    fn test_analysis_ranks_candidates() {
+        let seed = [0, 0, 0, 0, 0, 0, 0, 53];
+        let game = Game::new_from_seed(seed);
+        let mut mcts = MonteCarloTree::new_from_seed(game, seed);
+        mcts.search_with_limits(SearchLimits::iterations(300));
+        let analysis = mcts.analysis(5);
+
+        assert_eq!(analysis.iterations, 300);
+        assert!(!analysis.candidates.is_empty() && analysis.candidates.len() <= 5);
+        assert!(analysis
+            .candidates
+            .windows(2)
+            .all(|pair| pair[0].visits >= pair[1].visits));
+        for candidate in &analysis.candidates {
+            assert!(candidate.lower_bound <= candidate.mean_score);
+            assert!(candidate.mean_score <= candidate.upper_bound);
+        }
+
+        // The continuation starts with the move to play, and stays within the turn
+        assert_eq!(analysis.principal_variation[0], mcts.best_move());
+        let placements = analysis
+            .principal_variation
+            .iter()
+            .filter(|mv| matches!(mv, Move::Place(_)));
+        assert!(placements.count() <= 4 + 1);
+        assert!(analysis.expected_score > 0.);
+    }
+
+    #[test]
+    fn test_analysis_of_a_solved_position() {
+        let seed = [0, 0, 0, 0, 0, 0, 0, 61];
+        let mut game = Game::new_from_seed(seed);
+        // The last turn, with one die left to place
+        while game.turn < 7 || game.to_place.len() > 1 {
+            let mv = game.generate_moves()[0];
+            game.do_move(mv);
+        }
+        let position_limit = Heuristics::default().parameters.final_round_positions;
+        let solution = solve_final_round(&game, position_limit).unwrap();
+
+        for limits in [
+            SearchLimits::iterations(200),
+            SearchLimits::duration(50),
+            SearchLimits {
+                iterations: Some(200),
+                milliseconds: Some(60_000),
+            },
+        ] {
+            let mut mcts = MonteCarloTree::new_from_seed(game.clone(), seed);
+            let analysis = mcts.search_with_limits(limits).analysis(5);
+            assert_eq!(analysis.iterations, 0);
+            assert_eq!(analysis.candidates.len(), 1);
+            assert_eq!(analysis.candidates[0].mv, solution.moves[0]);
+            assert_eq!(analysis.principal_variation, solution.moves);
+            assert!((analysis.expected_score - f64::from(solution.score)).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_analyze_decoded_position() {
+        let seed = [0, 0, 0, 0, 0, 0, 0, 59];
+        let mut game = Game::new_from_seed(seed);
+        let mv = game.generate_moves()[0];
+        game.do_move(mv);
+
+        let position = Game::decode(&game.encode()).unwrap();
+        let limits = SearchLimits {
+            iterations: Some(200),
+            milliseconds: Some(60_000),
+        };
+        let analysis = analyze(position, Heuristics::default(), limits, 3);
+        assert_eq!(analysis.iterations, 200);
+        assert!(analysis.candidates.len() <= 3);
+    }
+}
@@ -0,0 +1,195 @@
+//! Index based storage for the nodes of the search tree.
+//!
+//! Edges refer to their child nodes by `NodeId`. Discarding a subtree only
+//! pushes its root onto a list in O(1); the nodes below it are reclaimed lazily,
+//! one node at a time, as new nodes are allocated or when `reclaim_discarded`
+//! is called.
+
+use super::amaf::AmafTrail;
+use super::{Edge, Node, SingleOrMultiple};
+use crate::game::Game;
+use std::convert::TryFrom;
+use std::ops::{Index, IndexMut};
+
+pub type NodeId = u32;
+
+#[derive(Debug, Default)]
+pub struct NodeArena {
+    nodes: Vec<Node>,
+    /// Slots that are ready to be reused
+    free: Vec<NodeId>,
+    /// Roots of discarded subtrees that have not been reclaimed yet
+    discarded: Vec<NodeId>,
+    /// Maximum number of nodes to keep. `None` is unbounded.
+    pub budget: Option<usize>,
+    /// Placements of the current iteration, for MC-RAVE. See `amaf`.
+    pub amaf_trail: AmafTrail,
+}
+
+impl NodeArena {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[must_use]
+    pub fn with_budget(budget: usize) -> Self {
+        Self {
+            budget: Some(budget),
+            ..Self::default()
+        }
+    }
+
+    /// Number of allocated nodes, including discarded nodes not yet reclaimed
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.nodes.len() - self.free.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    #[must_use]
+    pub fn is_over_budget(&self) -> bool {
+        self.budget.is_some_and(|budget| self.len() > budget)
+    }
+
+    /// Store `node`, reusing the slot of a freed or discarded node if there is one
+    /// # Panics
+    /// Panics if the arena grows beyond `NodeId::MAX` nodes
+    pub fn insert(&mut self, node: Node) -> NodeId {
+        if let Some(id) = self.free.pop() {
+            self[id] = node;
+            return id;
+        }
+
+        if let Some(id) = self.discarded.pop() {
+            let old = std::mem::replace(&mut self[id], node);
+            self.discard_children(&old.children);
+            return id;
+        }
+
+        let id = NodeId::try_from(self.nodes.len()).expect("Node arena is full");
+        self.nodes.push(node);
+        id
+    }
+
+    /// Mark the subtree below `id` as garbage. O(1).
+    pub fn discard(&mut self, id: NodeId) {
+        self.discarded.push(id);
+    }
+
+    /// Mark the subtree below `edge` as garbage, and detach it from the edge.
+    /// The edge keeps its statistics.
+    pub fn discard_child(&mut self, edge: &mut Edge) {
+        match edge.child.take() {
+            None => (),
+            Some(SingleOrMultiple::Single(id)) => self.discard(id),
+            Some(SingleOrMultiple::Multiple(nodes)) => self.discarded.extend(nodes.values()),
+        }
+    }
+
+    fn discard_children(&mut self, children: &[Edge]) {
+        for edge in children {
+            match &edge.child {
+                None => (),
+                Some(SingleOrMultiple::Single(id)) => self.discard(*id),
+                Some(SingleOrMultiple::Multiple(nodes)) => self.discarded.extend(nodes.values()),
+            }
+        }
+    }
+
+    /// Free every node below the discarded subtree roots, so their slots can be reused
+    pub fn reclaim_discarded(&mut self) {
+        while let Some(id) = self.discarded.pop() {
+            let old = std::mem::take(&mut self[id]);
+            self.discard_children(&old.children);
+            self.free.push(id);
+        }
+    }
+
+    /// Replace the children of the node at `id` with the moves available in `game`,
+    /// discarding any subtrees below the previous children
+    pub fn generate_children(&mut self, id: NodeId, game: &mut Game) {
+        let old = std::mem::take(&mut self[id].children);
+        self.discard_children(&old);
+        self[id].generate_children(game);
+    }
+
+    /// Discard every subtree below `edge` whose edge has fewer than `threshold` visits.
+    /// The edges keep their statistics, and the subtrees are regrown if selected again.
+    pub fn recycle_below(&mut self, edge: &mut Edge, threshold: u64) {
+        let ids: Vec<NodeId> = match &edge.child {
+            None => return,
+            Some(SingleOrMultiple::Single(id)) => vec![*id],
+            Some(SingleOrMultiple::Multiple(nodes)) => nodes.values().copied().collect(),
+        };
+
+        for id in ids {
+            let mut children = std::mem::take(&mut self[id].children);
+            for child in &mut children {
+                if child.visits < threshold {
+                    self.discard_child(child);
+                } else {
+                    self.recycle_below(child, threshold);
+                }
+            }
+            self[id].children = children;
+        }
+    }
+}
+
+impl Index<NodeId> for NodeArena {
+    type Output = Node;
+
+    fn index(&self, id: NodeId) -> &Node {
+        &self.nodes[id as usize]
+    }
+}
+
+impl IndexMut<NodeId> for NodeArena {
+    fn index_mut(&mut self, id: NodeId) -> &mut Node {
+        &mut self.nodes[id as usize]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::game::mv::Move;
+    use crate::mcts::MonteCarloTree;
+
+    #[test]
+    fn test_discarded_slots_are_reused() {
+        let mut arena = NodeArena::new();
+        let a = arena.insert(Node::new());
+        let b = arena.insert(Node::new());
+        arena[a].children = vec![Edge {
+            child: Some(SingleOrMultiple::Single(b)),
+            ..Edge::new(Move::End)
+        }]
+        .into_boxed_slice();
+
+        arena.discard(a);
+        assert_eq!(arena.len(), 2);
+        arena.reclaim_discarded();
+        assert_eq!(arena.len(), 0);
+
+        arena.insert(Node::new());
+        arena.insert(Node::new());
+        assert_eq!(arena.nodes.len(), 2);
+    }
+
+    #[test]
+    fn test_node_budget_is_respected() {
+        let seed = [0, 0, 0, 0, 0, 0, 0, 3];
+        let game = Game::new_from_seed(seed);
+        let budget = 200;
+        let mut mcts = MonteCarloTree::new_from_seed(game, seed).with_node_budget(budget);
+        mcts.search_iterations(2000);
+        assert!(mcts.arena.len() <= budget);
+        assert_eq!(mcts.root.visits, 2000);
+    }
+}
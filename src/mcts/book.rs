@@ -0,0 +1,473 @@
+//! Opening book for the first turn.
+//!
+//! On the first turn the board is empty, so the position is decided by the
+//! roll alone. `build_book` searches every distinct first-turn roll deeply, and
+//! stores the placements it settles on for the whole turn. A `MonteCarloTree`
+//! with a book plays those placements without searching, see
+//! `MonteCarloTree::with_opening_book`.
+//!
+//! The board looks the same mirrored left to right, top to bottom, and turned
+//! half way around. With `fold_symmetries`, the first placement of each roll is
+//! only searched in one of its mirror images, so the budget is not split over
+//! placements that are worth the same. Folded placements stay out of the search
+//! with `Parameters::pruning` too, which only prunes among the others.
+//!
+//! Books are saved after every roll, so an interrupted build resumes where it
+//! stopped. The file starts with `MAGIC` and the little-endian `FORMAT_VERSION`,
+//! followed by the entries encoded with bincode.
+
+use super::heuristics::{Heuristics, Parameters};
+use super::persist::SavedMove;
+use super::{Edge, MonteCarloTree, Score, Single};
+use crate::board::placement::Placement;
+use crate::board::square::Square;
+use crate::game::mv::Move;
+use crate::game::roll::Roll;
+use crate::game::Game;
+use crate::pieces::{Connection, Piece};
+use rand::{Rng, SeedableRng};
+use rand_xoshiro::SplitMix64;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::prelude::*;
+use std::sync::Mutex;
+
+pub const MAGIC: &[u8; 8] = b"RRINKBOK";
+/// Version of the file format. Files of other versions are refused.
+pub const FORMAT_VERSION: u32 = 1;
+
+/// The placements to make on the first turn after some roll
+#[derive(Debug, Clone, PartialEq)]
+pub struct BookEntry {
+    pub roll: Roll,
+    /// Every move of the turn, in order, up to and not including the next roll
+    pub moves: Vec<Move>,
+    /// Iterations searched for the whole turn
+    pub iterations: u64,
+    /// The search statistics of every move of `moves`, when it was chosen
+    pub statistics: Vec<MoveStatistics>,
+}
+
+/// Mean final score through a move, and the standard deviation of those scores
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct MoveStatistics {
+    pub mean_score: Score,
+    pub standard_deviation: Score,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedEntry {
+    roll: [u8; 4],
+    moves: Vec<SavedMove>,
+    iterations: u64,
+    statistics: Vec<MoveStatistics>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct OpeningBook {
+    pub entries: HashMap<Roll, BookEntry>,
+}
+
+impl OpeningBook {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[must_use]
+    pub fn get(&self, roll: &Roll) -> Option<&BookEntry> {
+        self.entries.get(roll)
+    }
+
+    pub fn insert(&mut self, entry: BookEntry) {
+        self.entries.insert(entry.roll, entry);
+    }
+
+    /// Encode the book
+    /// # Panics
+    /// Panics if the entries cannot be encoded
+    #[must_use]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut entries = self.entries.values().collect::<Vec<_>>();
+        entries.sort_by_key(|entry| entry.roll.0);
+        let entries = entries
+            .into_iter()
+            .map(|entry| SavedEntry {
+                roll: entry.roll.0,
+                moves: entry.moves.iter().map(|&mv| mv.into()).collect(),
+                iterations: entry.iterations,
+                statistics: entry.statistics.clone(),
+            })
+            .collect::<Vec<_>>();
+
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        let payload = bincode::serde::encode_to_vec(&entries, bincode::config::standard())
+            .expect("Opening books should encode");
+        bytes.extend(payload);
+        bytes
+    }
+
+    /// Decode a book encoded by `to_bytes`
+    /// # Errors
+    /// Returns an error if `bytes` are not an opening book of the current format version
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        let header_length = MAGIC.len() + 4;
+        if bytes.len() < header_length || &bytes[..MAGIC.len()] != MAGIC {
+            return Err("Error loading opening book: Not an opening book file".to_string());
+        }
+        let mut version = [0; 4];
+        version.copy_from_slice(&bytes[MAGIC.len()..header_length]);
+        let version = u32::from_le_bytes(version);
+        if version != FORMAT_VERSION {
+            return Err(format!(
+                "Error loading opening book: Format version {version} is not supported, expected {FORMAT_VERSION}"
+            ));
+        }
+
+        let (entries, _): (Vec<SavedEntry>, _) =
+            bincode::serde::decode_from_slice(&bytes[header_length..], bincode::config::standard())
+                .map_err(|e| format!("Error loading opening book: {e}"))?;
+        let mut book = Self::new();
+        for entry in entries {
+            book.insert(BookEntry {
+                roll: Roll(entry.roll),
+                moves: entry.moves.into_iter().map(Move::from).collect(),
+                iterations: entry.iterations,
+                statistics: entry.statistics,
+            });
+        }
+        Ok(book)
+    }
+
+    /// Save the book to a file at `path`
+    /// # Errors
+    /// Returns an error if the file cannot be created or written to
+    pub fn save(&self, path: &str) -> std::io::Result<()> {
+        let mut file = File::create(path)?;
+        file.write_all(&self.to_bytes())
+    }
+
+    /// Load a book saved by `save`
+    /// # Errors
+    /// Returns an error if the file cannot be read, or is not an opening book of the current format version
+    pub fn load(path: &str) -> Result<Self, String> {
+        let mut bytes = vec![];
+        File::open(path)
+            .and_then(|mut file| file.read_to_end(&mut bytes))
+            .map_err(|e| format!("Error loading opening book: {e}"))?;
+        Self::from_bytes(&bytes)
+    }
+}
+
+/// How to build an opening book
+#[derive(Clone)]
+pub struct BookOptions {
+    /// Iterations to search before each placement of the turn
+    pub iterations: u64,
+    pub fold_symmetries: bool,
+    pub parameters: Parameters,
+    pub seed: u64,
+}
+
+/// Search every first-turn roll that is not in `book` yet, in parallel.
+/// If `path` is given, the book is saved there after every roll.
+/// # Panics
+/// Panics if the book cannot be saved to `path`
+pub fn build_book(book: &mut OpeningBook, options: &BookOptions, path: Option<&str>) {
+    let missing = Roll::outcomes()
+        .iter()
+        .map(|(roll, _)| *roll)
+        .filter(|roll| book.get(roll).is_none())
+        .collect::<Vec<_>>();
+
+    let shared = Mutex::new(std::mem::take(book));
+    missing.into_par_iter().for_each(|roll| {
+        let entry = search_roll(roll, options);
+        let mut book = shared.lock().expect("Opening book lock was poisoned");
+        book.insert(entry);
+        if let Some(path) = path {
+            book.save(path)
+                .unwrap_or_else(|e| panic!("Could not save opening book to {}: {}", path, e));
+        }
+    });
+    *book = shared.into_inner().expect("Opening book lock was poisoned");
+}
+
+/// Search the first turn after `roll`, and return the placements settled on
+fn search_roll(roll: Roll, options: &BookOptions) -> BookEntry {
+    // Each roll gets its own seed, so the entries do not depend on the order they are built in
+    let seed: [u8; 8] = SplitMix64::seed_from_u64(options.seed ^ u64::from(u32::from(&roll))).gen();
+    let mut game = Game::new_from_seed(seed);
+    game.replace_roll(roll);
+    let mut mcts = MonteCarloTree::new_from_seed(game.clone(), seed);
+    mcts.heuristics = Heuristics::new(options.parameters.clone());
+
+    if options.fold_symmetries {
+        // The children of the root are generated on its second visit
+        mcts.search();
+        mcts.search();
+        if let Some(Single(node_id)) = mcts.root.child {
+            fold_symmetric_moves(&mut mcts.arena[node_id].children);
+        }
+    }
+
+    let mut moves = vec![];
+    let mut statistics = vec![];
+    while game.turn == 1 && !game.ended {
+        let mv = mcts.search_iterations(options.iterations).best_move();
+        if !matches!(mv, Move::Place(_)) {
+            break;
+        }
+        let chosen = mcts
+            .root_children()
+            .iter()
+            .filter(|edge| edge.mv == mv)
+            .max_by_key(|edge| edge.visits)
+            .map(|edge| MoveStatistics {
+                mean_score: edge.distribution.mean,
+                standard_deviation: edge.variance().sqrt(),
+            });
+        moves.push(mv);
+        statistics.push(chosen.unwrap_or_default());
+        mcts = MonteCarloTree::progress(mcts, mv, &mut game);
+    }
+
+    BookEntry {
+        roll,
+        iterations: options.iterations * (moves.len() as u64 + 1),
+        moves,
+        statistics,
+    }
+}
+
+/// The symmetries of the board besides the identity
+#[derive(Debug, Clone, Copy)]
+enum Symmetry {
+    MirrorLeftRight,
+    MirrorTopBottom,
+    HalfTurn,
+}
+
+const SYMMETRIES: [Symmetry; 3] = [
+    Symmetry::MirrorLeftRight,
+    Symmetry::MirrorTopBottom,
+    Symmetry::HalfTurn,
+];
+
+type Networks = [Option<[Connection; 4]>; 2];
+
+impl Symmetry {
+    fn square(self, square: Square<7>) -> Square<7> {
+        let (x, y) = (square.x(), square.y());
+        match self {
+            Self::MirrorLeftRight => Square::new(6 - x, y),
+            Self::MirrorTopBottom => Square::new(x, 6 - y),
+            Self::HalfTurn => Square::new(6 - x, 6 - y),
+        }
+    }
+
+    /// Connections are indexed by direction: north, east, south, west
+    fn networks(self, networks: Networks) -> Networks {
+        let swapped = match self {
+            Self::MirrorLeftRight => [0, 3, 2, 1],
+            Self::MirrorTopBottom => [2, 1, 0, 3],
+            Self::HalfTurn => [2, 3, 0, 1],
+        };
+        networks.map(|network| network.map(|connections| swapped.map(|i| connections[i])))
+    }
+}
+
+fn same_networks(a: Networks, b: Networks) -> bool {
+    a == b || a == [b[1], b[0]]
+}
+
+/// Whether `b` is `a` seen in a mirror, or is the same placement
+fn is_image(a: Placement, b: Placement) -> bool {
+    if a.piece != b.piece {
+        return false;
+    }
+    if a == b {
+        return true;
+    }
+    let networks = Piece::get_networks(a.piece, a.orientation);
+    let other = Piece::get_networks(b.piece, b.orientation);
+    SYMMETRIES.iter().any(|&symmetry| {
+        symmetry.square(a.square) == b.square && same_networks(symmetry.networks(networks), other)
+    })
+}
+
+/// Fold every placement in `children` that mirrors an earlier one
+fn fold_symmetric_moves(children: &mut [Edge]) {
+    for i in 0..children.len() {
+        let Move::Place(placement) = children[i].mv else {
+            continue;
+        };
+        let folded = children[..i].iter().any(|edge| match edge.mv {
+            Move::Place(other) => !edge.folded && is_image(other, placement),
+            _ => false,
+        });
+        children[i].folded = folded;
+    }
+}
+
+impl MonteCarloTree {
+    /// Play the first turn from `book` when the roll is in it, instead of searching
+    #[must_use]
+    pub fn with_opening_book(mut self, book: std::sync::Arc<OpeningBook>) -> Self {
+        self.book = Some(book);
+        self
+    }
+
+    /// Follow the line of the opening book for the roll, when the first turn is
+    /// rolled and the roll is in the book
+    pub(super) fn start_book_line(&mut self) {
+        if self.game.turn != 1 {
+            return;
+        }
+        if let (Some(book), Some(roll)) = (&self.book, self.game.current_roll()) {
+            if let Some(entry) = book.get(&roll) {
+                self.planned_line = entry.moves.clone();
+                self.planned_score = entry.statistics.last().map(|last| last.mean_score);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::board::placement::Orientation;
+    use std::sync::Arc;
+
+    fn options(iterations: u64) -> BookOptions {
+        BookOptions {
+            iterations,
+            fold_symmetries: true,
+            parameters: Heuristics::default().parameters,
+            seed: 7,
+        }
+    }
+
+    #[test]
+    fn test_mirrored_placements_are_images() {
+        // A straight road, along the north edge and along the south edge
+        let north = Placement {
+            square: Square::new(1, 0),
+            piece: 0x06,
+            orientation: Orientation::new(1, false),
+        };
+        let south = Placement {
+            square: Square::new(1, 6),
+            ..north
+        };
+        let turned = Placement {
+            square: Square::new(5, 6),
+            ..north
+        };
+        let elsewhere = Placement {
+            square: Square::new(2, 6),
+            ..north
+        };
+        assert!(is_image(north, south));
+        assert!(is_image(north, turned));
+        assert!(!is_image(north, elsewhere));
+
+        // A T junction without a north end on the north edge, mirrored, has no south end
+        let t_north = Placement {
+            piece: 0x05,
+            orientation: Orientation::new(2, false),
+            ..north
+        };
+        let t_south = Placement {
+            square: Square::new(1, 6),
+            orientation: Orientation::new(0, false),
+            ..t_north
+        };
+        assert!(is_image(t_north, t_south));
+        assert!(!is_image(
+            t_north,
+            Placement {
+                square: Square::new(1, 6),
+                ..t_north
+            }
+        ));
+    }
+
+    #[test]
+    fn test_folding_skips_mirror_images() {
+        let mut game = Game::new_from_seed([0, 0, 0, 0, 0, 0, 0, 73]);
+        let mut children = game
+            .generate_moves()
+            .into_iter()
+            .map(Edge::new)
+            .collect::<Vec<_>>();
+        fold_symmetric_moves(&mut children);
+        let kept = children.iter().filter(|edge| !edge.folded).count();
+        assert!(kept > 0);
+        assert!(kept * 2 < children.len());
+    }
+
+    #[test]
+    fn test_pruning_keeps_mirror_images_folded() {
+        let seed = [0, 0, 0, 0, 0, 0, 0, 83];
+        let mut mcts = MonteCarloTree::new_from_seed(Game::new_from_seed(seed), seed);
+        mcts.heuristics.parameters.pruning = true;
+        mcts.heuristics.parameters.prune_minimum_node_count = 1000;
+        mcts.search();
+        mcts.search();
+        let Some(Single(node_id)) = mcts.root.child else {
+            panic!("Root was not expanded")
+        };
+        fold_symmetric_moves(&mut mcts.arena[node_id].children);
+        let before = mcts.arena[node_id]
+            .children
+            .iter()
+            .map(|edge| edge.visits)
+            .collect::<Vec<_>>();
+        mcts.search_iterations(300);
+
+        let children = &mcts.arena[node_id].children;
+        assert!(children.iter().any(|edge| edge.folded));
+        for (edge, visits) in children.iter().zip(before) {
+            if edge.folded {
+                assert_eq!(edge.visits, visits, "{} was folded but searched", edge.mv);
+            }
+        }
+    }
+
+    #[test]
+    fn test_book_round_trip_and_lookup() {
+        let roll = Roll::outcomes()[10].0;
+        let entry = search_roll(roll, &options(60));
+        assert!(!entry.moves.is_empty() && entry.moves.len() <= 4 + 1);
+        assert_eq!(entry.statistics.len(), entry.moves.len());
+        assert!(entry
+            .statistics
+            .iter()
+            .all(|placement| placement.mean_score > 0.));
+
+        let mut book = OpeningBook::new();
+        book.insert(entry.clone());
+        let book = OpeningBook::from_bytes(&book.to_bytes()).unwrap();
+        assert_eq!(book.get(&roll), Some(&entry));
+
+        // A game that rolls `roll` plays the book line without searching
+        let mut game = Game::new_from_seed([0, 0, 0, 0, 0, 0, 0, 79]);
+        game.replace_roll(roll);
+        let mut mcts =
+            MonteCarloTree::new_from_seed(game.clone(), [1; 8]).with_opening_book(Arc::new(book));
+        for &expected in &entry.moves {
+            let mv = mcts.search_iterations(100).best_move();
+            assert_eq!(mv, expected);
+            assert_eq!(mcts.root.visits, 0);
+            mcts = MonteCarloTree::progress(mcts, mv, &mut game);
+        }
+        // Out of the book, the search takes over, and expects its own score
+        assert_eq!(mcts.planned_score, None);
+        mcts.search_iterations(20);
+        assert!(mcts.root.visits > 0);
+    }
+}
@@ -0,0 +1,187 @@
+//! Chance nodes: the `Multiple` children of a `Move::Roll` edge, one node per roll.
+//!
+//! Which roll to search below a chance node is picked by `RollSampling`, and the
+//! value backed up through the chance node is computed by `ChanceValue`.
+
+use super::arena::{NodeArena, NodeId};
+use super::distribution::Objective;
+use super::Score;
+use crate::game::roll::Roll;
+use crate::game::Game;
+use crate::identity_hasher::BuildHasher;
+use ord_subset::OrdSubsetIterExt;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+pub type ChanceNodes = HashMap<Roll, NodeId, BuildHasher>;
+
+/// How to pick the roll to search below a chance node
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RollSampling {
+    /// Roll the dice with the generator of the search. The dice of the game
+    /// itself are never looked at, as they would give away the future rolls.
+    #[default]
+    Random,
+    /// Pick the roll that is furthest behind its share of the visits, given its
+    /// exact probability. Spreads visits over the rolls with less variance than
+    /// random sampling, and still reaches rare rolls.
+    Stratified,
+}
+
+/// How to value a chance node from the nodes below it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChanceValue {
+    /// Mean of every score backed up through the node, weighted by how often each roll was drawn
+    SampleMean,
+    /// Mean of each explored roll, weighted by the exact probability of that roll
+    #[default]
+    Expectation,
+}
+
+impl RollSampling {
+    /// Pick the roll to search below `nodes`, and set it as the roll of `game`.
+    /// `game` must be at the start of a turn, right after `Move::Roll`.
+    ///
+    /// # Panics
+    /// Never: there are rolls to pick from
+    pub fn sample(
+        self,
+        nodes: &ChanceNodes,
+        arena: &NodeArena,
+        game: &mut Game,
+        rng: &mut dyn RngCore,
+    ) -> Roll {
+        match self {
+            Self::Random => {
+                let roll = Roll::sample(rng);
+                game.replace_roll(roll);
+                roll
+            }
+            Self::Stratified => {
+                let visits = |roll: &Roll| nodes.get(roll).map_or(0, |id| arena[*id].visits);
+                let total = Roll::outcomes()
+                    .iter()
+                    .map(|(roll, _)| visits(roll))
+                    .sum::<u64>();
+                let (roll, _) = Roll::outcomes()
+                    .iter()
+                    .ord_subset_max_by_key(|(roll, p)| p * (total + 1) as f64 - visits(roll) as f64)
+                    .unwrap();
+                game.replace_roll(*roll);
+                *roll
+            }
+        }
+    }
+}
+
+impl ChanceValue {
+    /// Value of the chance node with the given explored rolls, under `objective`.
+    /// For objectives other than the mean, the values of the rolls are weighted
+    /// like their means would be.
+    #[must_use]
+    pub fn value(self, nodes: &ChanceNodes, arena: &NodeArena, objective: Objective) -> Score {
+        let explored = nodes
+            .iter()
+            .map(|(roll, id)| (roll, &arena[*id]))
+            .filter(|(_, node)| node.visits > 0)
+            .map(|(roll, node)| {
+                let mean = node.total_score / node.visits as f64;
+                (roll, node, objective.value(mean, &node.distribution))
+            });
+
+        let (total, weight) = match self {
+            Self::SampleMean => explored.fold((0., 0.), |(total, weight), (_, node, value)| {
+                let visits = node.visits as f64;
+                (visits.mul_add(value, total), weight + visits)
+            }),
+            Self::Expectation => explored.fold((0., 0.), |(total, weight), (roll, _, value)| {
+                let p = roll.probability();
+                (p.mul_add(value, total), weight + p)
+            }),
+        };
+
+        if weight > 0. {
+            total / weight
+        } else {
+            0.
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mcts::Node;
+    use rand::SeedableRng;
+    use rand_xoshiro::SplitMix64;
+
+    fn chance_nodes(arena: &mut NodeArena, rolls: &[(Roll, u64, f64)]) -> ChanceNodes {
+        let mut nodes = HashMap::with_hasher(BuildHasher);
+        for &(roll, visits, total_score) in rolls {
+            let mut node = Node::new();
+            node.visits = visits;
+            node.total_score = total_score;
+            nodes.insert(roll, arena.insert(node));
+        }
+        nodes
+    }
+
+    #[test]
+    fn test_expectation_ignores_sampling_frequency() {
+        let mut arena = NodeArena::new();
+        // Equally likely rolls, but one is drawn far more often
+        let a = Roll([1, 2, 3, 7]);
+        let b = Roll([1, 2, 4, 7]);
+        let nodes = chance_nodes(&mut arena, &[(a, 90, 90. * 10.), (b, 10, 10. * 50.)]);
+
+        let expectation = ChanceValue::Expectation.value(&nodes, &arena, Objective::Mean);
+        let sample_mean = ChanceValue::SampleMean.value(&nodes, &arena, Objective::Mean);
+        assert!((expectation - 30.).abs() < 1e-9);
+        assert!((sample_mean - 14.).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_stratified_sampling_follows_probabilities() {
+        let mut arena = NodeArena::new();
+        let mut nodes: ChanceNodes = HashMap::with_hasher(BuildHasher);
+        let mut game = Game::new_from_seed([1; 8]);
+        let mut rng = SplitMix64::from_seed([2; 8]);
+
+        let n = 2000;
+        for _ in 0..n {
+            let roll = RollSampling::Stratified.sample(&nodes, &arena, &mut game, &mut rng);
+            assert_eq!(game.current_roll(), Some(roll));
+            let id = *nodes
+                .entry(roll)
+                .or_insert_with(|| arena.insert(Node::new()));
+            arena[id].visits += 1;
+        }
+
+        for (roll, p) in Roll::outcomes() {
+            let visits = nodes.get(roll).map_or(0, |id| arena[*id].visits);
+            assert!((visits as f64 - p * f64::from(n)).abs() <= 1.);
+        }
+    }
+
+    #[test]
+    fn test_random_sampling_does_not_see_the_dice_of_the_game() {
+        let arena = NodeArena::new();
+        let nodes: ChanceNodes = HashMap::with_hasher(BuildHasher);
+        let mut rng = SplitMix64::from_seed([3; 8]);
+
+        // Every game rolls the same dice, but the search should not know them
+        let mut rolls = vec![];
+        for _ in 0..20 {
+            let mut game = Game::new_from_seed([1; 8]);
+            let roll = RollSampling::Random.sample(&nodes, &arena, &mut game, &mut rng);
+            assert_eq!(game.current_roll(), Some(roll));
+            rolls.push(u32::from(&roll));
+        }
+        rolls.sort_unstable();
+        rolls.dedup();
+        assert!(rolls.len() > 1);
+    }
+}
@@ -0,0 +1,271 @@
+//! The distribution of the scores backed up through an edge or node, and the
+//! objective the search maximises.
+//!
+//! By default the search maximises the mean score. With a risk-aware `Objective`,
+//! `Edge::mean_score` holds the objective value instead: the chance of reaching a
+//! target score, or the mean of the worst outcomes (conditional value at risk), or a quantile. These
+//! are read off a histogram of the scores, with buckets of `BUCKET_WIDTH` points.
+//! The histogram is only kept for the objectives that read it, so that searches
+//! for the mean score don't pay for it in every edge and node.
+
+use super::Score;
+use serde::{Deserialize, Serialize};
+
+/// Lowest score with its own bucket. Lower scores are counted in the first bucket.
+pub const LOWEST_SCORE: Score = -20.;
+pub const BUCKET_WIDTH: Score = 5.;
+/// Number of buckets. Scores from `LOWEST_SCORE + BUCKET_WIDTH * BUCKETS` up are counted in the last bucket.
+pub const BUCKETS: usize = 28;
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct ScoreDistribution {
+    pub samples: u64,
+    pub mean: Score,
+    /// Mean of the squared scores
+    pub squared_mean: Score,
+    /// Number of scores in each bucket, from the first score added under an
+    /// objective that reads it. See `Objective::needs_histogram`.
+    pub histogram: Option<Box<[u32; BUCKETS]>>,
+}
+
+impl ScoreDistribution {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// An empty distribution that counts its scores in a histogram
+    #[must_use]
+    pub fn with_histogram() -> Self {
+        Self {
+            histogram: Some(Box::new([0; BUCKETS])),
+            ..Self::default()
+        }
+    }
+
+    #[allow(clippy::cast_sign_loss)] // Clamped to be positive
+    fn bucket(score: Score) -> usize {
+        let bucket = ((score - LOWEST_SCORE) / BUCKET_WIDTH).floor().max(0.) as usize;
+        bucket.min(BUCKETS - 1)
+    }
+
+    /// Lowest score of `bucket`
+    fn bucket_start(bucket: usize) -> Score {
+        (bucket as f64).mul_add(BUCKET_WIDTH, LOWEST_SCORE)
+    }
+
+    /// Add `score`, counting it in the histogram if there is one, or if
+    /// `objective` needs one and it is started now
+    pub fn add(&mut self, score: Score, objective: Objective) {
+        self.samples += 1;
+        let n = self.samples as f64;
+        self.mean += (score - self.mean) / n;
+        self.squared_mean += (score * score - self.squared_mean) / n;
+        if self.histogram.is_none() && objective.needs_histogram() {
+            self.histogram = Some(Box::new([0; BUCKETS]));
+        }
+        if let Some(histogram) = &mut self.histogram {
+            histogram[Self::bucket(score)] += 1;
+        }
+    }
+
+    /// The histogram, with the number of scores it counted. Empty if there is none.
+    fn counts(&self) -> (&[u32], f64) {
+        self.histogram.as_deref().map_or((&[], 0.), |histogram| {
+            (&histogram[..], f64::from(histogram.iter().sum::<u32>()))
+        })
+    }
+
+    #[must_use]
+    pub fn variance(&self) -> Score {
+        self.mean.mul_add(-self.mean, self.squared_mean).max(0.)
+    }
+
+    /// Share of the scores that are at least `target`.
+    /// Exact for integer scores when `target` is on a bucket boundary, and
+    /// interpolated within the bucket otherwise.
+    #[must_use]
+    pub fn probability_at_least(&self, target: Score) -> f64 {
+        let (histogram, samples) = self.counts();
+        if samples == 0. {
+            return 0.;
+        }
+        let bucket = Self::bucket(target);
+        let above = histogram[bucket + 1..].iter().sum::<u32>();
+        let within = f64::from(histogram[bucket])
+            * (1. - ((target - Self::bucket_start(bucket)) / BUCKET_WIDTH).clamp(0., 1.));
+        (f64::from(above) + within) / samples
+    }
+
+    /// Lowest score with at least a share `q` of the scores at or below it,
+    /// to the resolution of the buckets
+    #[must_use]
+    pub fn quantile(&self, q: f64) -> Score {
+        let (histogram, samples) = self.counts();
+        let wanted = q * samples;
+        let mut seen = 0.;
+        for (bucket, &count) in histogram.iter().enumerate() {
+            if count == 0 {
+                continue;
+            }
+            let count = f64::from(count);
+            if seen + count >= wanted {
+                let within = ((wanted - seen) / count).clamp(0., 1.);
+                return within.mul_add(BUCKET_WIDTH, Self::bucket_start(bucket));
+            }
+            seen += count;
+        }
+        Self::bucket_start(BUCKETS)
+    }
+
+    /// Conditional value at risk: mean of the worst share `alpha` of the scores,
+    /// taking each score as the middle of its bucket
+    #[must_use]
+    pub fn cvar(&self, alpha: f64) -> Score {
+        let (histogram, samples) = self.counts();
+        let wanted = (alpha * samples).max(1.);
+        let mut taken = 0.;
+        let mut total = 0.;
+        for (bucket, &count) in histogram.iter().enumerate() {
+            let count = f64::from(count).min(wanted - taken);
+            if count <= 0. {
+                continue;
+            }
+            let middle = BUCKET_WIDTH.mul_add(0.5, Self::bucket_start(bucket));
+            total += count * middle;
+            taken += count;
+            if taken >= wanted {
+                break;
+            }
+        }
+        if taken > 0. {
+            total / taken
+        } else {
+            0.
+        }
+    }
+}
+
+/// What the search maximises
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "objective")]
+pub enum Objective {
+    /// The expected score
+    #[default]
+    Mean,
+    /// The chance of scoring at least `target`, as a percentage so it is on the
+    /// same scale as scores
+    TargetScore { target: Score },
+    /// The mean of the worst share `alpha` of the scores
+    Cvar { alpha: f64 },
+    /// The score that a share `q` of the outcomes fall at or below
+    Quantile { q: f64 },
+}
+
+impl Objective {
+    /// Whether the value is read off the histogram of the scores
+    #[must_use]
+    pub const fn needs_histogram(self) -> bool {
+        !matches!(self, Self::Mean)
+    }
+
+    /// Value of an edge or node with the given `mean` score and score `distribution`
+    #[must_use]
+    pub fn value(self, mean: Score, distribution: &ScoreDistribution) -> Score {
+        match self {
+            Self::Mean => mean,
+            Self::TargetScore { target } => 100. * distribution.probability_at_least(target),
+            Self::Cvar { alpha } => distribution.cvar(alpha),
+            Self::Quantile { q } => distribution.quantile(q),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::game::Game;
+    use crate::mcts::MonteCarloTree;
+
+    fn distribution(scores: &[Score]) -> ScoreDistribution {
+        let mut distribution = ScoreDistribution::with_histogram();
+        for &score in scores {
+            distribution.add(score, Objective::Mean);
+        }
+        distribution
+    }
+
+    #[test]
+    fn test_moments() {
+        let distribution = distribution(&[10., 20., 30., 40.]);
+        assert!((distribution.mean - 25.).abs() < 1e-9);
+        assert!((distribution.variance() - 125.).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_target_probability() {
+        let distribution = distribution(&[30., 45., 50., 52., 70., -40., 200.]);
+        assert!((distribution.probability_at_least(50.) - 4. / 7.).abs() < 1e-9);
+        assert!((distribution.probability_at_least(-100.) - 1.).abs() < 1e-9);
+        assert!(distribution.probability_at_least(500.).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_risk_measures_look_at_the_worst_scores() {
+        // A safe option and a gamble with a better mean
+        let safe = distribution(&[40.; 10]);
+        let mut gamble_scores = [70.; 10];
+        gamble_scores[0] = 0.;
+        gamble_scores[1] = 0.;
+        let gamble = distribution(&gamble_scores);
+        assert!(gamble.mean > safe.mean);
+
+        let cvar = Objective::Cvar { alpha: 0.2 };
+        assert!(cvar.value(gamble.mean, &gamble) < cvar.value(safe.mean, &safe));
+        let quantile = Objective::Quantile { q: 0.1 };
+        assert!(quantile.value(gamble.mean, &gamble) < quantile.value(safe.mean, &safe));
+        let target = Objective::TargetScore { target: 60. };
+        assert!(target.value(gamble.mean, &gamble) > target.value(safe.mean, &safe));
+    }
+
+    #[test]
+    fn test_target_score_search_backs_up_probabilities() {
+        let seed = [0, 0, 0, 0, 0, 0, 0, 43];
+        let game = Game::new_from_seed(seed);
+        let mut mcts = MonteCarloTree::new_from_seed(game, seed);
+        mcts.heuristics.parameters.objective = Objective::TargetScore { target: 40. };
+        mcts.search_iterations(300);
+
+        let children = mcts.root_children();
+        assert!(children.iter().any(|edge| edge.visits > 0));
+        for edge in children.iter().filter(|edge| edge.visits > 0) {
+            assert!((0. ..=100.).contains(&edge.mean_score));
+            assert_eq!(edge.distribution.samples, edge.visits);
+            assert!(edge.distribution.histogram.is_some());
+        }
+    }
+
+    #[test]
+    fn test_mean_search_keeps_no_histograms() {
+        let seed = [0, 0, 0, 0, 0, 0, 0, 43];
+        let game = Game::new_from_seed(seed);
+        let mut mcts = MonteCarloTree::new_from_seed(game, seed);
+        mcts.search_iterations(100);
+
+        assert!(mcts.root.distribution.histogram.is_none());
+        let children = mcts.root_children();
+        assert!(children.iter().any(|edge| edge.visits > 0));
+        assert!(children
+            .iter()
+            .all(|edge| edge.distribution.histogram.is_none()));
+
+        let mut scores = ScoreDistribution::new();
+        scores.add(10., Objective::Mean);
+        assert!(scores.histogram.is_none());
+        scores.add(20., Objective::Quantile { q: 0.5 });
+        assert_eq!(
+            scores.histogram.as_ref().map(|h| h.iter().sum::<u32>()),
+            Some(1)
+        );
+    }
+}
@@ -0,0 +1,224 @@
+//! Graphviz DOT export of the search tree.
+//!
+//! Decision nodes are drawn as boxes, chance nodes (the `Multiple` children of a
+//! `Move::Roll` edge) as diamonds. Edges are labelled with the move, visits and
+//! mean score, and pruned or folded edges are greyed out.
+//!
+//! Render with `dot -Tsvg tree.dot -o tree.svg`.
+
+use super::{Edge, Node, NodeArena, SingleOrMultiple};
+use std::fmt::Write;
+
+/// Limits for how much of the tree is written
+#[derive(Debug, Clone, Copy)]
+pub struct DotOptions {
+    /// Maximum number of edges from the root to follow
+    pub max_depth: u16,
+    /// Edges with fewer visits than this are left out
+    pub min_visits: u64,
+}
+
+impl Default for DotOptions {
+    fn default() -> Self {
+        Self {
+            max_depth: 3,
+            min_visits: 1,
+        }
+    }
+}
+
+struct DotWriter<'a> {
+    arena: &'a NodeArena,
+    options: DotOptions,
+    out: String,
+    next_id: usize,
+}
+
+impl<'a> DotWriter<'a> {
+    fn new(arena: &'a NodeArena, options: DotOptions) -> Self {
+        Self {
+            arena,
+            options,
+            out: String::new(),
+            next_id: 0,
+        }
+    }
+
+    fn id(&mut self) -> String {
+        self.next_id += 1;
+        format!("n{}", self.next_id - 1)
+    }
+
+    fn decision_node(&mut self, node: &Node) -> String {
+        let id = self.id();
+        let mean = if node.visits == 0 {
+            0.
+        } else {
+            node.total_score / node.visits as f64
+        };
+        let style = if node.is_terminal { ", style=bold" } else { "" };
+        writeln!(
+            self.out,
+            "  {id} [shape=box{style}, label=\"n={}\\nmean={mean:.2}\"];",
+            node.visits
+        )
+        .unwrap();
+        id
+    }
+
+    fn chance_node(&mut self, rolls: usize) -> String {
+        let id = self.id();
+        writeln!(
+            self.out,
+            "  {id} [shape=diamond, label=\"Roll\\n{rolls} seen\"];"
+        )
+        .unwrap();
+        id
+    }
+
+    fn leaf_node(&mut self) -> String {
+        let id = self.id();
+        writeln!(self.out, "  {id} [shape=point];").unwrap();
+        id
+    }
+
+    fn edge(&mut self, from: &str, to: &str, label: &str, pruned: bool) {
+        let label = label.replace('"', "\\\"");
+        let style = if pruned {
+            ", color=gray, fontcolor=gray, style=dashed"
+        } else {
+            ""
+        };
+        writeln!(self.out, "  {from} -> {to} [label=\"{label}\"{style}];").unwrap();
+    }
+
+    /// Write the node below `edge`, and everything below it within the limits.
+    /// Returns the id of the written node.
+    fn write_child(&mut self, edge: &Edge, depth: u16) -> String {
+        let arena = self.arena;
+        match edge.child.as_ref() {
+            None => self.leaf_node(),
+            Some(SingleOrMultiple::Single(node_id)) => {
+                let node = &arena[*node_id];
+                let id = self.decision_node(node);
+                self.write_children(&id, node, depth);
+                id
+            }
+            Some(SingleOrMultiple::Multiple(nodes)) => {
+                let id = self.chance_node(nodes.len());
+                let min_visits = self.options.min_visits;
+                let mut rolls = nodes
+                    .iter()
+                    .map(|(roll, id)| (roll, &arena[*id]))
+                    .filter(|(_, node)| node.visits >= min_visits)
+                    .collect::<Vec<_>>();
+                rolls.sort_unstable_by_key(|(_, node)| std::cmp::Reverse(node.visits));
+
+                for (roll, node) in rolls {
+                    let child_id = self.decision_node(node);
+                    let mean = node.total_score / node.visits.max(1) as f64;
+                    let label = format!("{}\\nn={} mean={mean:.2}", roll.to_string(), node.visits);
+                    self.edge(&id, &child_id, &label, false);
+                    self.write_children(&child_id, node, depth);
+                }
+                id
+            }
+        }
+    }
+
+    fn write_children(&mut self, id: &str, node: &Node, depth: u16) {
+        if depth >= self.options.max_depth {
+            return;
+        }
+        let min_visits = self.options.min_visits;
+        for edge in node
+            .children
+            .iter()
+            .filter(|edge| edge.visits >= min_visits)
+        {
+            let child_id = self.write_child(edge, depth + 1);
+            let label = format!(
+                "{}\\nn={} mean={:.2}",
+                edge.mv, edge.visits, edge.mean_score
+            );
+            self.edge(id, &child_id, &label, edge.pruned || edge.folded);
+        }
+    }
+}
+
+/// Render the tree below `root` as a DOT digraph
+#[must_use]
+pub fn to_dot(root: &Edge, arena: &NodeArena, options: DotOptions) -> String {
+    let mut writer = DotWriter::new(arena, options);
+    writeln!(writer.out, "digraph mcts {{").unwrap();
+    writeln!(writer.out, "  node [fontname=\"monospace\"];").unwrap();
+    writeln!(writer.out, "  edge [fontname=\"monospace\", fontsize=10];").unwrap();
+
+    let root_id = writer.id();
+    writeln!(
+        writer.out,
+        "  {root_id} [shape=doublecircle, label=\"root\\nn={}\\nmean={:.2}\"];",
+        root.visits, root.mean_score
+    )
+    .unwrap();
+    let child_id = writer.write_child(root, 0);
+    let label = format!("{}", root.mv);
+    writer.edge(&root_id, &child_id, &label, root.pruned || root.folded);
+
+    writeln!(writer.out, "}}").unwrap();
+    writer.out
+}
+
+/// Write the tree below `root` as a DOT file at the given `path`
+/// # Errors
+/// Returns an error if the file cannot be created or written to
+pub fn write_dot(
+    root: &Edge,
+    arena: &NodeArena,
+    options: DotOptions,
+    path: &str,
+) -> std::io::Result<()> {
+    std::fs::write(path, to_dot(root, arena, options))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::game::Game;
+    use crate::mcts::MonteCarloTree;
+
+    #[test]
+    fn test_dot_export_is_a_digraph() {
+        let seed = [0, 0, 0, 0, 0, 0, 0, 7];
+        let game = Game::new_from_seed(seed);
+        let mut mcts = MonteCarloTree::new_from_seed(game, seed);
+        mcts.search_iterations(50);
+
+        let dot = mcts.to_dot(DotOptions {
+            max_depth: 2,
+            min_visits: 1,
+        });
+        assert!(dot.starts_with("digraph mcts {"));
+        assert!(dot.trim_end().ends_with('}'));
+        assert!(dot.contains("shape=box"));
+        assert!(dot.contains("->"));
+    }
+
+    #[test]
+    fn test_dot_export_respects_visit_threshold() {
+        let seed = [0, 0, 0, 0, 0, 0, 0, 9];
+        let game = Game::new_from_seed(seed);
+        let mut mcts = MonteCarloTree::new_from_seed(game, seed);
+        mcts.search_iterations(50);
+
+        let all = mcts.to_dot(DotOptions {
+            max_depth: 1,
+            min_visits: 1,
+        });
+        let few = mcts.to_dot(DotOptions {
+            max_depth: 1,
+            min_visits: 10,
+        });
+        assert!(few.matches("->").count() < all.matches("->").count());
+    }
+}
@@ -0,0 +1,537 @@
+//! Players that pick moves for a game, behind a common `Engine` trait.
+//!
+//! Besides the Monte Carlo tree search, there are three baselines that do not
+//! search at all and ignore the `SearchLimits`: uniformly random moves, the
+//! move that raises `Board::score` the most, and the move with the best
+//! `Heuristics::get_move_estimation`. They put the strength of the search in
+//! perspective, and play a game in a fraction of a second.
+
+use super::analysis::SearchLimits;
+use super::arena::NodeArena;
+use super::expectimax::{ExpectimaxEngine, ExpectimaxOptions};
+use super::heuristics::Heuristics;
+use super::normalization::ValueBounds;
+use super::planner::{BeamPlanner, PlannerOptions};
+use super::{Edge, MonteCarloTree};
+use crate::board::ScoreBreakdown;
+use crate::game::mv::Move;
+use crate::game::Game;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use rand_xoshiro::SplitMix64;
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+pub trait Engine {
+    /// Pick a move in `game`, which must not have ended
+    fn choose_move(&mut self, game: &Game, limits: &SearchLimits) -> Move;
+
+    /// Called with every move played in `game`, before it is played. Engines that keep
+    /// state between moves update it here.
+    fn observe(&mut self, _game: &Game, _mv: Move) {}
+
+    /// The tree searched for the last move, for engines that search one
+    fn search_tree(&self) -> Option<&MonteCarloTree> {
+        None
+    }
+
+    /// Number of search iterations run so far, for engines that count them
+    fn iterations(&self) -> u64 {
+        0
+    }
+}
+
+/// The rest of a turn planned in one go, for engines that plan whole turns
+#[derive(Default)]
+pub(super) struct TurnPlan {
+    moves: Vec<Move>,
+    /// The game the first of `moves` is played in
+    start: Option<Game>,
+}
+
+impl TurnPlan {
+    /// The next planned move in `game`, if the plan was made for it
+    pub(super) fn next(&self, game: &Game) -> Option<Move> {
+        if self.start.as_ref() == Some(game) {
+            self.moves.first().copied()
+        } else {
+            None
+        }
+    }
+
+    /// Follow `moves` from `game`, and return the first move. Without moves,
+    /// the turn is over and the first move is `Move::Roll` or `Move::End`.
+    pub(super) fn start(&mut self, game: &Game, moves: Vec<Move>) -> Move {
+        self.moves = moves;
+        self.start = Some(game.clone());
+        self.next(game).unwrap_or_else(|| {
+            game.clone()
+                .generate_moves()
+                .into_iter()
+                .find(|mv| matches!(mv, Move::Roll | Move::End))
+                .expect("A finished turn should end with a roll")
+        })
+    }
+
+    /// Keep to the plan if `mv` is the next planned move in `game`, and drop it otherwise
+    pub(super) fn observe(&mut self, game: &Game, mv: Move) {
+        if self.next(game) == Some(mv) {
+            if let Some(start) = &mut self.start {
+                start.do_move(mv);
+            }
+            self.moves.remove(0);
+        } else {
+            *self = Self::default();
+        }
+    }
+}
+
+/// Monte Carlo tree search, keeping the tree from one move to the next
+pub struct MctsEngine {
+    /// Only `None` while the tree is being progressed
+    tree: Option<MonteCarloTree>,
+    /// Search iterations run so far, counted by the visits they added to the root
+    iterations: u64,
+}
+
+impl MctsEngine {
+    /// Search with `tree`, its heuristics, node budget and opening book.
+    /// When asked for a move in another game than the tree's, the search starts over.
+    #[must_use]
+    pub fn new(tree: MonteCarloTree) -> Self {
+        Self {
+            tree: Some(tree),
+            iterations: 0,
+        }
+    }
+
+    fn tree_mut(&mut self) -> &mut MonteCarloTree {
+        self.tree
+            .as_mut()
+            .expect("The search tree should be in place")
+    }
+}
+
+impl MonteCarloTree {
+    /// A fresh tree for `game`, keeping the heuristics parameters, node budget, opening book and seed
+    fn restart(mut self, game: Game) -> Self {
+        let mut arena = NodeArena::new();
+        arena.budget = self.arena.budget;
+        self.heuristics.value_bounds = ValueBounds::default();
+        Self {
+            game,
+            root: Edge::default(),
+            arena,
+            root_choice: None,
+            planned_line: vec![],
+            planned_score: None,
+            unsolved: false,
+            ..self
+        }
+    }
+}
+
+impl Engine for MctsEngine {
+    fn choose_move(&mut self, game: &Game, limits: &SearchLimits) -> Move {
+        if self.tree_mut().game != *game {
+            let tree = self
+                .tree
+                .take()
+                .expect("The search tree should be in place");
+            self.tree = Some(tree.restart(game.clone()));
+        }
+        let tree = self.tree_mut();
+        let visits = tree.root.visits;
+        let mv = tree.search_with_limits(*limits).best_move();
+        self.iterations += self.tree_mut().root.visits.saturating_sub(visits);
+        mv
+    }
+
+    fn observe(&mut self, game: &Game, mv: Move) {
+        let tree = self
+            .tree
+            .take()
+            .expect("The search tree should be in place");
+        self.tree = Some(if tree.game == *game {
+            MonteCarloTree::progress(tree, mv, &mut game.clone())
+        } else {
+            tree
+        });
+    }
+
+    fn search_tree(&self) -> Option<&MonteCarloTree> {
+        self.tree.as_ref()
+    }
+
+    fn iterations(&self) -> u64 {
+        self.iterations
+    }
+}
+
+/// Uniformly random moves
+pub struct RandomEngine {
+    rng: SplitMix64,
+}
+
+impl RandomEngine {
+    #[must_use]
+    pub fn new(seed: [u8; 8]) -> Self {
+        Self {
+            rng: SplitMix64::from_seed(seed),
+        }
+    }
+}
+
+impl Engine for RandomEngine {
+    fn choose_move(&mut self, game: &Game, _limits: &SearchLimits) -> Move {
+        *game
+            .clone()
+            .generate_moves()
+            .choose(&mut self.rng)
+            .expect("A game in progress should have a move")
+    }
+}
+
+/// The move that raises `Board::score` the most, looking one move ahead
+pub struct GreedyScoreEngine;
+
+impl Engine for GreedyScoreEngine {
+    fn choose_move(&mut self, game: &Game, _limits: &SearchLimits) -> Move {
+        game.clone()
+            .generate_moves()
+            .into_iter()
+            .max_by_key(|&mv| {
+                let mut next = game.clone();
+                next.do_move(mv);
+                next.board.score()
+            })
+            .expect("A game in progress should have a move")
+    }
+}
+
+/// The move with the best `Heuristics::get_move_estimation`
+pub struct GreedyHeuristicEngine {
+    pub heuristics: Heuristics,
+}
+
+impl Engine for GreedyHeuristicEngine {
+    fn choose_move(&mut self, game: &Game, _limits: &SearchLimits) -> Move {
+        let moves = game.clone().generate_moves();
+        self.heuristics
+            .select_rollout_move(game, moves)
+            .expect("A game in progress should have a move")
+    }
+}
+
+/// The engines to choose from on the command line. Stored in files by their names.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum EngineKind {
+    #[default]
+    Mcts,
+    Random,
+    GreedyScore,
+    GreedyHeuristic,
+    Expectimax {
+        depth: u8,
+        roll_samples: Option<usize>,
+    },
+    Beam {
+        beam_width: usize,
+        lookahead_rolls: usize,
+    },
+}
+
+impl EngineKind {
+    /// An engine of this kind, seeded with `seed` and searching with `heuristics` where it uses them
+    #[must_use]
+    pub fn build(self, seed: [u8; 8], heuristics: Heuristics) -> Box<dyn Engine> {
+        match self {
+            Self::Mcts => {
+                let mut tree = MonteCarloTree::new_from_seed(Game::default(), seed);
+                tree.heuristics = heuristics;
+                Box::new(MctsEngine::new(tree))
+            }
+            Self::Random => Box::new(RandomEngine::new(seed)),
+            Self::GreedyScore => Box::new(GreedyScoreEngine),
+            Self::GreedyHeuristic => Box::new(GreedyHeuristicEngine { heuristics }),
+            Self::Expectimax {
+                depth,
+                roll_samples,
+            } => {
+                let options = ExpectimaxOptions {
+                    depth,
+                    roll_samples,
+                    final_round_positions: heuristics.parameters.final_round_positions,
+                    evaluator: heuristics.parameters.leaf_evaluator,
+                    ..ExpectimaxOptions::default()
+                };
+                Box::new(ExpectimaxEngine::new(heuristics, options, seed))
+            }
+            Self::Beam {
+                beam_width,
+                lookahead_rolls,
+            } => {
+                let options = PlannerOptions {
+                    beam_width,
+                    lookahead_rolls,
+                    evaluator: heuristics.parameters.leaf_evaluator,
+                };
+                Box::new(BeamPlanner::new(heuristics, options, seed))
+            }
+        }
+    }
+}
+
+/// Parses `mcts`, `random`, `greedy-score`, `greedy-heuristic`,
+/// `expectimax:<depth>:<roll samples>` and `beam:<beam width>:<lookahead rolls>`,
+/// where the arguments are optional. Arguments the engine does not take are refused.
+impl FromStr for EngineKind {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let mut arguments = input.split(':');
+        let name = arguments.next().unwrap_or_default();
+        let mut argument = || {
+            arguments
+                .next()
+                .map(|argument| {
+                    argument
+                        .parse::<usize>()
+                        .map_err(|_| format!("Could not parse engine argument: {argument}"))
+                })
+                .transpose()
+        };
+
+        let kind = match name {
+            "mcts" => Ok(Self::Mcts),
+            "random" => Ok(Self::Random),
+            "greedy-score" => Ok(Self::GreedyScore),
+            "greedy-heuristic" => Ok(Self::GreedyHeuristic),
+            "expectimax" => {
+                let default = ExpectimaxOptions::default();
+                let depth = match argument()? {
+                    Some(depth) => u8::try_from(depth)
+                        .ok()
+                        .filter(|&depth| depth > 0)
+                        .ok_or(format!("Expectimax depth should be 1 to 255, not {depth}"))?,
+                    None => default.depth,
+                };
+                Ok(Self::Expectimax {
+                    depth,
+                    roll_samples: argument()?.or(default.roll_samples),
+                })
+            }
+            "beam" => {
+                let default = PlannerOptions::default();
+                let beam_width = argument()?.unwrap_or(default.beam_width);
+                if beam_width == 0 {
+                    return Err("The beam width should be at least 1".to_string());
+                }
+                Ok(Self::Beam {
+                    beam_width,
+                    lookahead_rolls: argument()?.unwrap_or(default.lookahead_rolls),
+                })
+            }
+            _ => Err(format!("Unknown engine: {input}")),
+        }?;
+        if arguments.next().is_some() {
+            return Err(format!("Too many arguments for engine {name}: {input}"));
+        }
+        Ok(kind)
+    }
+}
+
+impl TryFrom<String> for EngineKind {
+    type Error = String;
+
+    fn try_from(name: String) -> Result<Self, Self::Error> {
+        name.parse()
+    }
+}
+
+impl From<EngineKind> for String {
+    fn from(kind: EngineKind) -> Self {
+        kind.to_string()
+    }
+}
+
+impl fmt::Display for EngineKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Mcts => write!(f, "mcts"),
+            Self::Random => write!(f, "random"),
+            Self::GreedyScore => write!(f, "greedy-score"),
+            Self::GreedyHeuristic => write!(f, "greedy-heuristic"),
+            Self::Expectimax {
+                depth,
+                roll_samples: None,
+            } => write!(f, "expectimax:{depth}"),
+            Self::Expectimax {
+                depth,
+                roll_samples: Some(samples),
+            } => write!(f, "expectimax:{depth}:{samples}"),
+            Self::Beam {
+                beam_width,
+                lookahead_rolls,
+            } => write!(f, "beam:{beam_width}:{lookahead_rolls}"),
+        }
+    }
+}
+
+/// Play `game` to the end with `engine`, calling `on_move` with every move chosen,
+/// before it is played
+pub fn play_game(
+    engine: &mut dyn Engine,
+    game: &mut Game,
+    limits: &SearchLimits,
+    mut on_move: impl FnMut(&dyn Engine, &Game, Move),
+) {
+    while !game.ended {
+        let mv = engine.choose_move(game, limits);
+        on_move(engine, game, mv);
+        engine.observe(game, mv);
+        game.do_move(mv);
+    }
+}
+
+/// A game played to the end by an engine, and what the engine spent on it
+#[derive(Debug, Clone, Serialize)]
+pub struct GameRecord {
+    pub moves: Vec<Move>,
+    pub score: ScoreBreakdown,
+    /// Search iterations run over the game, for engines that count them
+    pub iterations: u64,
+    /// Wall time of the game, nearly all of it spent choosing moves
+    pub seconds: f64,
+}
+
+impl GameRecord {
+    /// Search iterations per second of the game, zero for engines that do not count them
+    #[must_use]
+    pub fn iterations_per_second(&self) -> f64 {
+        if self.seconds > 0.0 {
+            self.iterations as f64 / self.seconds
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Play `game` to the end with `engine` like `play_game`, recording the moves
+pub fn record_game(engine: &mut dyn Engine, game: &mut Game, limits: &SearchLimits) -> GameRecord {
+    let start = std::time::Instant::now();
+    let iterations = engine.iterations();
+    let mut moves = vec![];
+    play_game(engine, game, limits, |_, _, mv| moves.push(mv));
+    GameRecord {
+        moves,
+        score: game.board.score_breakdown(),
+        iterations: engine.iterations() - iterations,
+        seconds: start.elapsed().as_secs_f64(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn final_score(engine: &mut dyn Engine, seed: [u8; 8], limits: &SearchLimits) -> i32 {
+        let mut game = Game::new_from_seed(seed);
+        play_game(engine, &mut game, limits, |_, game, mv| {
+            assert!(game.clone().generate_moves().contains(&mv));
+        });
+        game.board.score()
+    }
+
+    #[test]
+    fn test_baselines_play_legal_games() {
+        let seed = [0, 0, 0, 0, 0, 0, 0, 101];
+        let limits = SearchLimits::default();
+        for kind in [
+            EngineKind::Random,
+            EngineKind::GreedyScore,
+            EngineKind::GreedyHeuristic,
+        ] {
+            let score = final_score(&mut *kind.build(seed, Heuristics::default()), seed, &limits);
+            let again = final_score(&mut *kind.build(seed, Heuristics::default()), seed, &limits);
+            assert_eq!(score, again, "{kind} should be deterministic");
+            assert_eq!(kind.to_string().parse(), Ok(kind));
+        }
+        assert!("best".parse::<EngineKind>().is_err());
+        assert!("random:3".parse::<EngineKind>().is_err());
+
+        let expectimax = EngineKind::Expectimax {
+            depth: 3,
+            roll_samples: Some(20),
+        };
+        assert_eq!("expectimax:3:20".parse(), Ok(expectimax));
+        assert_eq!(expectimax.to_string().parse(), Ok(expectimax));
+        assert!("expectimax:0".parse::<EngineKind>().is_err());
+        assert!("expectimax:3:20:5".parse::<EngineKind>().is_err());
+        assert_eq!(
+            "beam".parse(),
+            Ok(EngineKind::Beam {
+                beam_width: 8,
+                lookahead_rolls: 0
+            })
+        );
+        assert!("beam:0".parse::<EngineKind>().is_err());
+    }
+
+    #[test]
+    fn test_mcts_engine_plays_like_the_tree() {
+        let seed = [0, 0, 0, 0, 0, 0, 0, 103];
+        let mut game = Game::new_from_seed(seed);
+        let mut mcts = MonteCarloTree::new_from_seed(game.clone(), seed);
+        while !game.ended {
+            let mv = mcts.search_iterations(30).best_move();
+            mcts = MonteCarloTree::progress(mcts, mv, &mut game);
+        }
+
+        let mut engine = EngineKind::Mcts.build(seed, Heuristics::default());
+        let score = final_score(&mut *engine, seed, &SearchLimits::iterations(30));
+        assert_eq!(score, game.board.score());
+    }
+
+    #[test]
+    fn test_mcts_engine_follows_other_games() {
+        let seed = [0, 0, 0, 0, 0, 0, 0, 107];
+        let mut engine = MctsEngine::new(MonteCarloTree::new_from_seed(Game::default(), seed));
+        let limits = SearchLimits::iterations(20);
+        let mut game = Game::new_from_seed(seed);
+        let mv = engine.choose_move(&game, &limits);
+        // The move is played without telling the engine
+        game.do_move(mv);
+        let mv = engine.choose_move(&game, &limits);
+        assert!(game.generate_moves().contains(&mv));
+        assert_eq!(engine.search_tree().unwrap().game, game);
+    }
+
+    #[test]
+    fn test_records_replay_the_game() {
+        let seed = [0, 0, 0, 0, 0, 0, 0, 109];
+        let mut engine = EngineKind::Mcts.build(seed, Heuristics::default());
+        let mut game = Game::new_from_seed(seed);
+        let record = record_game(&mut *engine, &mut game, &SearchLimits::iterations(20));
+        assert_eq!(record.score.total(), game.board.score());
+        assert!(record.iterations > 0);
+        assert!(record.iterations_per_second() > 0.0);
+
+        let mut replay = Game::new_from_seed(seed);
+        for &mv in &record.moves {
+            replay.do_move(mv);
+        }
+        assert_eq!(replay, game);
+
+        let mut greedy = EngineKind::GreedyScore.build(seed, Heuristics::default());
+        let record = record_game(
+            &mut *greedy,
+            &mut Game::new_from_seed(seed),
+            &SearchLimits::default(),
+        );
+        assert_eq!(record.iterations, 0);
+    }
+}
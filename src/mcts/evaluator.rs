@@ -0,0 +1,205 @@
+//! Evaluation of positions where a rollout is cut off before the game ends.
+//!
+//! When `Parameters::rollout_cutoff` is set, a rollout stops after the given
+//! number of plies or turns, and the rest of the game is replaced by the
+//! estimate of the `LeafEvaluator` in `Parameters::leaf_evaluator`.
+
+use super::heuristics::nn::data::DataItem;
+use super::heuristics::Heuristics;
+use super::Score;
+use crate::game::Game;
+use burn::backend::Wgpu;
+use burn::prelude::*;
+use ord_subset::OrdSubsetIterExt;
+use serde::{Deserialize, Serialize};
+
+/// Number of the last turn of a game
+const LAST_TURN: u8 = 7;
+
+pub trait LeafEvaluator {
+    /// Estimate the final score of `game`, which has not ended
+    fn evaluate(&self, game: &mut Game, heuristics: &mut Heuristics) -> Score;
+}
+
+/// The current score of the board, plus a fixed value for every turn left to play.
+/// Nothing about the board beyond its score is weighed, unlike `Heuristics`.
+pub struct BoardScoreEvaluator {
+    pub turn_value: f64,
+}
+
+/// The best final score predicted by `Heuristics::move_nn` over the legal moves.
+/// There is no value head: the move network scores each legal move, and the
+/// position is taken to be worth the best of them.
+///
+/// # Panics
+/// Evaluating panics if no model is loaded, see `SharedHeuristics::load`.
+/// Parameters selecting it are rejected when loaded without a model, see
+/// `Parameters::check_model`.
+pub struct ValueNetwork;
+
+impl LeafEvaluator for BoardScoreEvaluator {
+    fn evaluate(&self, game: &mut Game, _heuristics: &mut Heuristics) -> Score {
+        let turns_left = LAST_TURN.saturating_sub(game.turn);
+        self.turn_value
+            .mul_add(f64::from(turns_left), f64::from(game.board.score()))
+    }
+}
+
+impl LeafEvaluator for ValueNetwork {
+    fn evaluate(&self, game: &mut Game, heuristics: &mut Heuristics) -> Score {
+        let model = heuristics
+            .move_nn
+            .as_ref()
+            .expect("The value network evaluator needs a model, see `SharedHeuristics::load`");
+        let device = model.devices()[0].clone();
+
+        let moves = game.generate_moves();
+        if moves.is_empty() {
+            return f64::from(game.board.score());
+        }
+
+        let board = &game.board;
+        let boards = moves
+            .iter()
+            .map(|mv| TensorData::from([DataItem::get_features(board, *mv)]))
+            .map(|data| Tensor::<Wgpu, 4>::from_data(data, &device))
+            .collect();
+        let meta = moves
+            .iter()
+            .map(|mv| TensorData::from([DataItem::get_heuristics(board, *mv)]))
+            .map(|data| Tensor::<Wgpu, 2>::from_data(data, &device))
+            .collect();
+
+        let predictions = model.forward(Tensor::cat(boards, 0), Tensor::cat(meta, 0));
+        predictions
+            .into_data()
+            .iter::<f64>()
+            .ord_subset_max()
+            .expect("The value network gave no predictions")
+    }
+}
+
+/// The leaf evaluator to use, as stored in `Parameters`
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "evaluator")]
+pub enum LeafEvaluatorKind {
+    /// See `BoardScoreEvaluator`. Parameters written before it was named for what it
+    /// does call it `heuristic`.
+    #[serde(alias = "heuristic")]
+    BoardScore {
+        turn_value: f64,
+    },
+    ValueNetwork,
+}
+
+impl Default for LeafEvaluatorKind {
+    fn default() -> Self {
+        Self::BoardScore { turn_value: 6.0 }
+    }
+}
+
+impl LeafEvaluator for LeafEvaluatorKind {
+    fn evaluate(&self, game: &mut Game, heuristics: &mut Heuristics) -> Score {
+        match *self {
+            Self::BoardScore { turn_value } => {
+                BoardScoreEvaluator { turn_value }.evaluate(game, heuristics)
+            }
+            Self::ValueNetwork => ValueNetwork.evaluate(game, heuristics),
+        }
+    }
+}
+
+/// Where to cut rollouts off, counted from the start of the rollout
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RolloutCutoff {
+    /// After this many moves
+    Plies(u16),
+    /// After this many rolls of the dice
+    Turns(u8),
+}
+
+impl RolloutCutoff {
+    /// Whether a rollout that started at `start_turn` and has done `plies` moves
+    /// should stop at `game`
+    #[must_use]
+    pub fn is_reached(self, game: &Game, start_turn: u8, plies: u16) -> bool {
+        match self {
+            Self::Plies(limit) => plies >= limit,
+            Self::Turns(limit) => game.turn.saturating_sub(start_turn) >= limit,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mcts::tournament::SharedHeuristics;
+    use crate::mcts::MonteCarloTree;
+
+    #[test]
+    fn test_board_score_evaluator_counts_turns_left() {
+        let mut game = Game::new_from_seed([2; 8]);
+        let mut heuristics = Heuristics::default();
+
+        let evaluator = BoardScoreEvaluator { turn_value: 5.0 };
+        let expected = 5.0f64.mul_add(
+            f64::from(LAST_TURN - game.turn),
+            f64::from(game.board.score()),
+        );
+        let score = evaluator.evaluate(&mut game, &mut heuristics);
+        assert!((score - expected).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_old_evaluator_name_still_loads() {
+        let kind: LeafEvaluatorKind =
+            serde_json::from_str(r#"{ "evaluator": "heuristic", "turn_value": 4.0 }"#).unwrap();
+        assert_eq!(kind, LeafEvaluatorKind::BoardScore { turn_value: 4.0 });
+    }
+
+    #[test]
+    fn test_cutoff() {
+        let mut game = Game::new_from_seed([2; 8]);
+        let start_turn = game.turn;
+        assert!(!RolloutCutoff::Plies(3).is_reached(&game, start_turn, 2));
+        assert!(RolloutCutoff::Plies(3).is_reached(&game, start_turn, 3));
+
+        assert!(!RolloutCutoff::Turns(1).is_reached(&game, start_turn, 10));
+        game.turn += 1;
+        assert!(RolloutCutoff::Turns(1).is_reached(&game, start_turn, 10));
+    }
+
+    #[test]
+    fn test_truncated_search_plays_full_game() {
+        let seed = [0, 0, 0, 0, 0, 0, 0, 13];
+        let mut game = Game::new_from_seed(seed);
+        let mut mcts = MonteCarloTree::new_from_seed(game.clone(), seed);
+        mcts.heuristics.parameters.rollout_cutoff = Some(RolloutCutoff::Turns(1));
+
+        while !game.ended {
+            let mv = mcts.search_iterations(50).best_move();
+            mcts = MonteCarloTree::progress(mcts, mv, &mut game);
+        }
+        assert_eq!(game.turn, LAST_TURN);
+    }
+
+    #[test]
+    fn test_value_network_needs_a_model() {
+        let mut parameters = Heuristics::default().parameters;
+        assert!(parameters.check_model(false).is_ok());
+        parameters.leaf_evaluator = LeafEvaluatorKind::ValueNetwork;
+        assert!(parameters.check_model(false).is_err());
+        assert!(parameters.check_model(true).is_ok());
+
+        let path = std::env::temp_dir().join(format!(
+            "railroad-ink-value-network-{}.json",
+            std::process::id()
+        ));
+        let path = path.to_str().unwrap();
+        parameters.to_json(path).unwrap();
+        assert!(Heuristics::from_json(path).is_err());
+        assert!(SharedHeuristics::load(Some(path), None).is_err());
+        std::fs::remove_file(path).ok();
+    }
+}
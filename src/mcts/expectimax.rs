@@ -0,0 +1,232 @@
+//! Depth-limited expectimax over whole turns.
+//!
+//! Instead of sampling like the tree search, the expectimax engine takes the
+//! best arrangement of the pieces of the current turn, valuing each by the
+//! expectation over the rolls of the next turn of the best arrangement of that
+//! turn, and so on for `depth` rounds. The arrangements of a turn are found by
+//! the beam search of `hindsight`, keeping the `beam_width` best boards after
+//! every placement, and the last turn is solved exactly where it is small
+//! enough. Below the last round searched, the boards are valued by a
+//! `LeafEvaluator`.
+//!
+//! The expectation is over every roll with its exact probability, or over a
+//! sample of `roll_samples` rolls. With a seed, the engine is deterministic.
+//! Every round multiplies the work by the number of rolls and the beam width,
+//! so deep searches are only practical late in the game.
+
+use super::analysis::SearchLimits;
+use super::engine::{Engine, TurnPlan};
+use super::evaluator::{LeafEvaluator, LeafEvaluatorKind};
+use super::heuristics::Heuristics;
+use super::hindsight::{best_lines, finish_turn, Line};
+use super::solver::DEFAULT_POSITION_LIMIT;
+use super::Score;
+use crate::game::mv::Move;
+use crate::game::roll::Roll;
+use crate::game::Game;
+use rand::SeedableRng;
+use rand_xoshiro::SplitMix64;
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ExpectimaxOptions {
+    /// Number of turns to search, counting the current one
+    pub depth: u8,
+    /// Number of boards kept after every placement within a turn
+    pub beam_width: usize,
+    /// Take the expectation over this many rolls drawn by their probabilities,
+    /// instead of over every roll
+    pub roll_samples: Option<usize>,
+    /// Position limit of the exact solver on the last turn, see `solver::solve_final_round`
+    pub final_round_positions: usize,
+    /// Value of the boards at the end of the last turn searched, unless the game ends there
+    pub evaluator: LeafEvaluatorKind,
+}
+
+impl Default for ExpectimaxOptions {
+    fn default() -> Self {
+        Self {
+            depth: 2,
+            beam_width: 8,
+            roll_samples: None,
+            final_round_positions: DEFAULT_POSITION_LIMIT,
+            evaluator: LeafEvaluatorKind::default(),
+        }
+    }
+}
+
+pub struct ExpectimaxEngine {
+    pub options: ExpectimaxOptions,
+    pub heuristics: Heuristics,
+    /// Draws the rolls when `roll_samples` is set
+    rng: SplitMix64,
+    /// The rest of the best arrangement of the turn
+    plan: TurnPlan,
+}
+
+impl ExpectimaxEngine {
+    /// # Panics
+    /// Panics if `options.depth` or `options.beam_width` is zero
+    #[must_use]
+    pub fn new(heuristics: Heuristics, options: ExpectimaxOptions, seed: [u8; 8]) -> Self {
+        assert!(
+            options.depth > 0,
+            "Expectimax should search at least a turn"
+        );
+        assert!(options.beam_width > 0, "The beam should keep some boards");
+        Self {
+            options,
+            heuristics,
+            rng: SplitMix64::from_seed(seed),
+            plan: TurnPlan::default(),
+        }
+    }
+
+    /// The rolls to take the expectation over, with weights summing to one
+    fn rolls(&mut self) -> Vec<(Roll, f64)> {
+        let outcomes = Roll::outcomes();
+        let Some(samples) = self.options.roll_samples else {
+            return outcomes.to_vec();
+        };
+        let mut counts = HashMap::new();
+        for _ in 0..samples.max(1) {
+            *counts.entry(Roll::sample(&mut self.rng).0).or_insert(0) += 1;
+        }
+        let mut rolls = counts.into_iter().collect::<Vec<_>>();
+        rolls.sort_unstable();
+        rolls
+            .into_iter()
+            .map(|(roll, count)| (Roll(roll), f64::from(count) / samples.max(1) as f64))
+            .collect()
+    }
+
+    /// The best arrangement of the rest of the turn from `game`, searching `depth`
+    /// turns, and its expected final score
+    fn best_line(&mut self, game: &Game, depth: u8) -> (Score, Vec<Move>) {
+        let start = Line {
+            game: game.clone(),
+            moves: vec![],
+        };
+        let width = self.options.beam_width;
+        let lines = finish_turn(vec![start], width, self.options.final_round_positions);
+        best_lines(lines, width)
+            .into_iter()
+            .map(|line| (self.value(&line.game, depth - 1), line.moves))
+            .max_by(|(a, _), (b, _)| a.total_cmp(b))
+            .expect("Some line should reach the end of the turn")
+    }
+
+    /// Expected final score of `game` at the end of a turn, searching `depth` more turns
+    fn value(&mut self, game: &Game, depth: u8) -> Score {
+        if game.ended || game.turn >= 7 {
+            return f64::from(game.board.score());
+        }
+        if depth == 0 {
+            let evaluator = self.options.evaluator;
+            return evaluator.evaluate(&mut game.clone(), &mut self.heuristics);
+        }
+        self.rolls()
+            .into_iter()
+            .map(|(roll, weight)| {
+                let mut next = game.clone();
+                next.do_move(Move::SetRoll(roll));
+                weight * self.best_line(&next, depth).0
+            })
+            .sum()
+    }
+}
+
+impl Engine for ExpectimaxEngine {
+    /// Plays the best arrangement of the turn, searching `ExpectimaxOptions::depth`
+    /// turns regardless of `limits`
+    fn choose_move(&mut self, game: &Game, _limits: &SearchLimits) -> Move {
+        if let Some(mv) = self.plan.next(game) {
+            return mv;
+        }
+        let moves = self.best_line(game, self.options.depth).1;
+        self.plan.start(game, moves)
+    }
+
+    fn observe(&mut self, game: &Game, mv: Move) {
+        self.plan.observe(game, mv);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mcts::engine::play_game;
+    use crate::mcts::solver::solve_final_round;
+
+    /// A game at the start of `turn`, played with the first legal moves
+    fn start_of_turn(seed: [u8; 8], turn: u8) -> Game {
+        let mut game = Game::new_from_seed(seed);
+        while game.turn < turn {
+            let mv = game.generate_moves()[0];
+            game.do_move(mv);
+        }
+        game
+    }
+
+    #[test]
+    fn test_roll_weights_sum_to_one() {
+        for roll_samples in [None, Some(10)] {
+            let options = ExpectimaxOptions {
+                roll_samples,
+                ..ExpectimaxOptions::default()
+            };
+            let mut engine = ExpectimaxEngine::new(Heuristics::default(), options, [1; 8]);
+            let total = engine.rolls().iter().map(|(_, weight)| weight).sum::<f64>();
+            assert!((total - 1.0).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_last_turn_is_solved() {
+        let seed = [0, 0, 0, 0, 0, 0, 0, 109];
+        let mut game = start_of_turn(seed, 7);
+        for _ in 0..2 {
+            let mv = game.generate_moves()[0];
+            game.do_move(mv);
+        }
+        let solution = solve_final_round(&game, 100_000).unwrap();
+
+        let options = ExpectimaxOptions {
+            final_round_positions: 100_000,
+            ..ExpectimaxOptions::default()
+        };
+        let mut engine = ExpectimaxEngine::new(Heuristics::default(), options, seed);
+        play_game(
+            &mut engine,
+            &mut game,
+            &SearchLimits::default(),
+            |_, _, _| {},
+        );
+        assert_eq!(game.board.score(), solution.score);
+    }
+
+    #[test]
+    fn test_plays_late_game_deterministically() {
+        let seed = [0, 0, 0, 0, 0, 0, 0, 113];
+        let options = ExpectimaxOptions {
+            beam_width: 2,
+            roll_samples: Some(3),
+            final_round_positions: 0,
+            ..ExpectimaxOptions::default()
+        };
+        let scores = [0, 1].map(|_| {
+            let mut game = start_of_turn(seed, 6);
+            let mut engine = ExpectimaxEngine::new(Heuristics::default(), options, seed);
+            play_game(
+                &mut engine,
+                &mut game,
+                &SearchLimits::default(),
+                |_, game, mv| {
+                    assert!(game.clone().generate_moves().contains(&mv));
+                },
+            );
+            game.board.score()
+        });
+        assert_eq!(scores[0], scores[1]);
+    }
+}
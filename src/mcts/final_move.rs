@@ -0,0 +1,136 @@
+//! Picking the move to play once the search is done.
+
+use super::{Edge, Score};
+use ord_subset::OrdSubsetIterExt;
+use serde::{Deserialize, Serialize};
+
+/// How `MonteCarloTree::best_move` picks among the visited moves at the root
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "selection")]
+pub enum FinalMoveSelection {
+    /// The most visited move
+    #[default]
+    MaxVisits,
+    /// The move with the best mean score
+    MaxMean,
+    /// The move that has both the most visits and the best mean. While those are
+    /// different moves, the search goes on, up to `extra_iterations` more iterations,
+    /// see `MonteCarloTree::settle_final_move`. Falls back to the most visited move.
+    RobustMax { extra_iterations: u64 },
+    /// The move with the best lower confidence bound, `mean - confidence / sqrt(visits)`
+    SecureChild { confidence: f64 },
+}
+
+impl FinalMoveSelection {
+    /// Index of the move to play among `children`, or `None` if none of them have been visited
+    #[must_use]
+    pub fn choose(self, children: &[Edge]) -> Option<usize> {
+        let visited = children
+            .iter()
+            .enumerate()
+            .filter(|(_, edge)| edge.visits != 0);
+        match self {
+            Self::MaxVisits | Self::RobustMax { .. } => {
+                visited.max_by_key(|(_, edge)| edge.visits).map(|(i, _)| i)
+            }
+            Self::MaxMean => visited
+                .ord_subset_max_by_key(|(_, edge)| edge.mean_score)
+                .map(|(i, _)| i),
+            Self::SecureChild { confidence } => visited
+                .ord_subset_max_by_key(|(_, edge)| lower_bound(edge, confidence))
+                .map(|(i, _)| i),
+        }
+    }
+
+    /// Whether the most visited and the best scoring move are the same, so that
+    /// `RobustMax` can stop searching
+    #[must_use]
+    pub fn is_settled(children: &[Edge]) -> bool {
+        Self::MaxVisits.choose(children).map(|i| children[i].mv)
+            == Self::MaxMean.choose(children).map(|i| children[i].mv)
+    }
+}
+
+fn lower_bound(edge: &Edge, confidence: f64) -> Score {
+    edge.mean_score - confidence / (edge.visits as f64).sqrt()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::board::placement::Placement;
+    use crate::board::square::Square;
+    use crate::game::mv::Move;
+    use crate::game::Game;
+    use crate::mcts::MonteCarloTree;
+
+    fn edge(square: u8, visits: u64, mean_score: Score) -> Edge {
+        Edge {
+            visits,
+            mean_score,
+            ..Edge::new(Move::Place(Placement {
+                square: Square::new(square, 0),
+                ..Placement::default()
+            }))
+        }
+    }
+
+    #[test]
+    fn test_strategies_disagree_on_lucky_moves() {
+        let children = [
+            edge(0, 100, 40.),
+            edge(1, 2, 50.),
+            edge(2, 30, 45.),
+            edge(3, 0, 0.),
+        ];
+        assert_eq!(FinalMoveSelection::MaxVisits.choose(&children), Some(0));
+        assert_eq!(FinalMoveSelection::MaxMean.choose(&children), Some(1));
+        let secure = FinalMoveSelection::SecureChild { confidence: 20. };
+        assert_eq!(secure.choose(&children), Some(2));
+        assert!(!FinalMoveSelection::is_settled(&children));
+        assert_eq!(FinalMoveSelection::MaxMean.choose(&children[3..]), None);
+    }
+
+    #[test]
+    fn test_unsearched_tree_plays_heuristic_best() {
+        let seed = [0, 0, 0, 0, 0, 0, 0, 37];
+        let mut game = Game::new_from_seed(seed);
+        let mut mcts = MonteCarloTree::new_from_seed(game.clone(), seed);
+
+        let mv = mcts.best_move();
+        let best = game
+            .generate_moves()
+            .into_iter()
+            .map(|mv| mcts.heuristics.get_move_estimation(&game, mv))
+            .fold(f64::MIN, f64::max);
+        assert!((mcts.heuristics.get_move_estimation(&game, mv) - best).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_robust_max_searches_until_settled() {
+        let seed = [0, 0, 0, 0, 0, 0, 0, 41];
+        let mut game = Game::new_from_seed(seed);
+        // A position a placement into the turn, with few enough moves
+        // left for the best and the most visited to meet
+        let mv = MonteCarloTree::new_from_seed(game.clone(), seed)
+            .search_iterations(100)
+            .best_move();
+        game.do_move(mv);
+        let mut mcts = MonteCarloTree::new_from_seed(game, seed);
+        mcts.heuristics.parameters.final_move_selection = FinalMoveSelection::RobustMax {
+            extra_iterations: 5000,
+        };
+        mcts.search_iterations(100);
+        let visits = mcts.root.visits;
+        assert!(visits > 100 && visits < 100 + 5000, "{}", visits);
+
+        // Picking the move searches no more
+        let mv = mcts.best_move();
+        assert_eq!(mcts.root.visits, visits);
+
+        let children = mcts.root_children();
+        assert!(FinalMoveSelection::is_settled(children));
+        let most_visited = FinalMoveSelection::MaxVisits.choose(children).unwrap();
+        assert_eq!(children[most_visited].mv, mv);
+    }
+}
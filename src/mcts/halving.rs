@@ -0,0 +1,213 @@
+//! Sequential Halving at the root, for searches with few iterations.
+//!
+//! Instead of letting the selection policy spread a small budget over every
+//! move at the root, the top `top_k` moves by heuristic estimate are searched
+//! in rounds. Each round splits its share of the budget evenly over the moves
+//! still in the running, then drops the worse half. The last move standing is
+//! returned by `MonteCarloTree::best_move`. Below the root, `Edge::select` is
+//! used as usual.
+//!
+//! With `gumbel`, the top `top_k` moves are sampled with the Gumbel-top-k trick
+//! instead, treating the heuristic estimates as logits, and the moves are ranked
+//! by `gumbel + estimate + mean` between rounds.
+
+use super::{MonteCarloTree, Score, Single};
+use crate::game::mv::Move;
+use rand::{Rng, RngCore};
+use serde::{Deserialize, Serialize};
+
+/// How to spend the iterations of `MonteCarloTree::search_with_limits` at the root
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "algorithm")]
+pub enum RootAlgorithm {
+    /// Search from the root with `Edge::select`, like every other node
+    #[default]
+    Select,
+    SequentialHalving {
+        top_k: usize,
+        gumbel: bool,
+    },
+}
+
+/// A move at the root that is still in the running
+struct Candidate {
+    index: usize,
+    gumbel: Score,
+    estimate: Score,
+}
+
+/// Sample from the standard Gumbel distribution
+fn gumbel(rng: &mut dyn RngCore) -> Score {
+    let u = 1. - rng.gen::<f64>();
+    -(-u.ln()).ln()
+}
+
+impl MonteCarloTree {
+    /// Search `iterations` times with Sequential Halving over the moves at the root,
+    /// or until `out_of_time`, and remember the surviving move for `best_move`
+    pub(super) fn search_sequential_halving(
+        &mut self,
+        iterations: u64,
+        out_of_time: &dyn Fn() -> bool,
+        top_k: usize,
+        gumbel: bool,
+    ) {
+        let mut spent = 0;
+        let searching = |spent: u64| spent < iterations && !out_of_time();
+        // The root needs its children before they can be ranked
+        while self.root.visits < 2 && searching(spent) {
+            self.search();
+            spent += 1;
+        }
+        let node_id = match self.root.child {
+            Some(Single(node_id)) if !self.arena[node_id].children.is_empty() => node_id,
+            _ => {
+                while searching(spent) {
+                    self.search();
+                    spent += 1;
+                }
+                return;
+            }
+        };
+
+        let mut rng = self.next_rng();
+        let mut candidates = {
+            let game = &self.game;
+            let heuristics = &mut self.heuristics;
+            let children = &mut self.arena[node_id].children;
+            // Pruning never leaves no child in the running, but a tree searched
+            // with other parameters might
+            let all_pruned = children.iter().all(|edge| edge.pruned || edge.folded);
+            children
+                .iter_mut()
+                .enumerate()
+                .filter(|(_, edge)| !edge.folded && (all_pruned || !edge.pruned))
+                .map(|(index, edge)| {
+                    let mv = edge.mv;
+                    let estimate = *edge
+                        .heuristic_value
+                        .get_or_insert_with(|| heuristics.get_move_estimation(game, mv));
+                    Candidate {
+                        index,
+                        gumbel: if gumbel { self::gumbel(&mut rng) } else { 0. },
+                        estimate,
+                    }
+                })
+                .collect::<Vec<_>>()
+        };
+        candidates
+            .sort_unstable_by(|a, b| (b.gumbel + b.estimate).total_cmp(&(a.gumbel + a.estimate)));
+        candidates.truncate(top_k.max(1));
+
+        let rounds = usize::BITS - (candidates.len() - 1).leading_zeros();
+        for round in 0..rounds {
+            let rounds_left = u64::from(rounds - round);
+            let per_candidate = (iterations - spent) / rounds_left / candidates.len() as u64;
+            for _ in 0..per_candidate.max(1) {
+                for candidate in &candidates {
+                    if searching(spent) {
+                        self.search_child(candidate.index);
+                        spent += 1;
+                    }
+                }
+            }
+
+            let children = &self.arena[node_id].children;
+            let rank = |candidate: &Candidate| {
+                let mean = children[candidate.index].mean_score;
+                if gumbel {
+                    candidate.gumbel + candidate.estimate + mean
+                } else {
+                    mean
+                }
+            };
+            candidates.sort_unstable_by(|a, b| rank(b).total_cmp(&rank(a)));
+            candidates.truncate(candidates.len().div_ceil(2));
+        }
+
+        let survivor = candidates[0].index;
+        while searching(spent) {
+            self.search_child(survivor);
+            spent += 1;
+        }
+        self.root_choice = Some(self.arena[node_id].children[survivor].mv);
+    }
+
+    /// The move picked by the last Sequential Halving search, if it is still current
+    #[must_use]
+    pub fn root_choice(&self) -> Option<Move> {
+        self.root_choice
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::game::Game;
+
+    fn halving_search(seed: [u8; 8], gumbel: bool) -> MonteCarloTree {
+        let game = Game::new_from_seed(seed);
+        let mut mcts = MonteCarloTree::new_from_seed(game, seed);
+        mcts.heuristics.parameters.root_algorithm =
+            RootAlgorithm::SequentialHalving { top_k: 8, gumbel };
+        mcts.search_iterations(200);
+        mcts
+    }
+
+    #[test]
+    fn test_sequential_halving_spends_budget_on_top_k() {
+        for gumbel in [false, true] {
+            let mut mcts = halving_search([0, 0, 0, 0, 0, 0, 0, 21], gumbel);
+            let Some(Single(node_id)) = mcts.root.child else {
+                panic!("Root was not expanded")
+            };
+            let children = &mcts.arena[node_id].children;
+            // The top 8, and the move searched while the root was expanded
+            let visited = children.iter().filter(|edge| edge.visits > 0).count();
+            assert!(visited <= 8 + 1);
+
+            let most_visits = children.iter().map(|edge| edge.visits).max().unwrap();
+            let survivor = mcts
+                .root_choice()
+                .expect("Sequential Halving picked no move");
+            // A roll with two of the same piece gives duplicate moves
+            let survivor_visits = children
+                .iter()
+                .filter(|edge| edge.mv == survivor)
+                .map(|edge| edge.visits)
+                .max();
+            assert_eq!(survivor_visits, Some(most_visits));
+            assert_eq!(mcts.best_move(), survivor);
+        }
+    }
+
+    #[test]
+    fn test_sequential_halving_with_every_child_pruned() {
+        let seed = [0, 0, 0, 0, 0, 0, 0, 29];
+        let mut mcts = MonteCarloTree::new_from_seed(Game::new_from_seed(seed), seed);
+        mcts.search_iterations(2);
+        let Some(Single(node_id)) = mcts.root.child else {
+            panic!("Root was not expanded")
+        };
+        for edge in &mut mcts.arena[node_id].children {
+            edge.pruned = true;
+        }
+
+        mcts.heuristics.parameters.root_algorithm = RootAlgorithm::SequentialHalving {
+            top_k: 8,
+            gumbel: false,
+        };
+        mcts.search_iterations(50);
+        assert_eq!(mcts.root.visits, 2 + 50);
+        assert!(mcts.root_choice().is_some());
+    }
+
+    #[test]
+    fn test_sequential_halving_is_deterministic() {
+        let seed = [0, 0, 0, 0, 0, 0, 0, 23];
+        let mut a = halving_search(seed, true);
+        let mut b = halving_search(seed, true);
+        assert_eq!(a.best_move(), b.best_move());
+        assert_eq!(a.root.visits, b.root.visits);
+    }
+}
@@ -0,0 +1,362 @@
+//! Parameter files in other formats than JSON, and comparisons between them.
+//!
+//! The CSV format is a table of the per-turn weights, with a column for every
+//! row of `Parameters::as_array` and a line for every turn, as in
+//! `heuristics.csv`. The pruning settings can be added as columns too, with
+//! the same value on every line. The other settings have no place in the
+//! table, and are taken from a base set of parameters when reading one.
+
+use super::Parameters;
+use serde_json::Value;
+use std::fmt;
+use std::fs::File;
+use std::io::prelude::*;
+
+/// The settings that may be columns of a CSV file besides the per-turn weights
+const CSV_SETTINGS: [&str; 3] = ["pruning", "prune_minimum_node_count", "prune_alpha"];
+
+/// Number of turns, and of lines of a CSV file
+const TURNS: usize = 7;
+
+impl Parameters {
+    /// The parameters in a CSV file, with the settings not in the file as in
+    /// old files without them: no pruning, and raw score units
+    /// # Errors
+    /// Returns an error if the file cannot be read or is not a parameters table
+    pub fn from_csv(path: &str) -> Result<Self, String> {
+        let mut contents = String::new();
+        File::open(path)
+            .and_then(|mut file| file.read_to_string(&mut contents))
+            .map_err(|e| format!("Error loading Heuristics: {e}"))?;
+        Self::parse_csv(&contents, Self::from([[0.0; TURNS]; 8]))
+    }
+
+    /// `base` with the columns of the CSV table `contents`
+    /// # Errors
+    /// Returns an error if a column is unknown, a value does not parse, the table
+    /// does not have a line for every turn, or a setting differs between lines
+    pub fn parse_csv(contents: &str, base: Self) -> Result<Self, String> {
+        let mut lines = contents.lines().filter(|line| !line.trim().is_empty());
+        let header = lines
+            .next()
+            .ok_or("Error loading Heuristics: The table is empty")?;
+        let columns = header.split(',').map(str::trim).collect::<Vec<_>>();
+        let lines = lines.collect::<Vec<_>>();
+        if lines.len() != TURNS {
+            return Err(format!(
+                "Error loading Heuristics: The table should have a line for each of the {TURNS} turns, not {}",
+                lines.len()
+            ));
+        }
+
+        let mut parameters = serde_json::to_value(base).map_err(|e| e.to_string())?;
+        for (index, &column) in columns.iter().enumerate() {
+            let values = lines
+                .iter()
+                .enumerate()
+                .map(|(turn, line)| {
+                    line.split(',').nth(index).map(str::trim).ok_or(format!(
+                        "Error loading Heuristics: No {column} on the line of turn {}",
+                        turn + 1
+                    ))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            let value = if Self::ROW_NAMES.contains(&column) {
+                let row = values
+                    .iter()
+                    .map(|value| parse_number(column, value))
+                    .collect::<Result<Vec<_>, _>>()?;
+                Value::from(row)
+            } else if CSV_SETTINGS.contains(&column) {
+                if values.iter().any(|value| *value != values[0]) {
+                    return Err(format!(
+                        "Error loading Heuristics: {column} should be the same for every turn"
+                    ));
+                }
+                serde_json::from_str(values[0]).map_err(|_| {
+                    format!(
+                        "Error loading Heuristics: Could not parse {column}: {}",
+                        values[0]
+                    )
+                })?
+            } else {
+                return Err(format!("Error loading Heuristics: Unknown column {column}"));
+            };
+            parameters[column] = value;
+        }
+        serde_json::from_value(parameters).map_err(|e| format!("Error loading Heuristics: {e}"))
+    }
+
+    /// The per-turn weights and the pruning settings as a CSV table
+    #[must_use]
+    pub fn to_csv_string(&self) -> String {
+        let mut table = Self::ROW_NAMES
+            .iter()
+            .chain(&CSV_SETTINGS)
+            .copied()
+            .collect::<Vec<_>>()
+            .join(",");
+        table.push('\n');
+        let rows = self.as_array();
+        for turn in 0..TURNS {
+            let weights = rows.iter().map(|row| row[turn].to_string());
+            let settings = [
+                self.pruning.to_string(),
+                self.prune_minimum_node_count.to_string(),
+                self.prune_alpha.to_string(),
+            ];
+            table.push_str(&weights.chain(settings).collect::<Vec<_>>().join(","));
+            table.push('\n');
+        }
+        table
+    }
+
+    /// Save the per-turn weights and the pruning settings to a CSV file
+    /// # Errors
+    /// Returns an error if the file cannot be opened or written to
+    pub fn to_csv(&self, path: &str) -> std::io::Result<()> {
+        let mut file = File::create(path)?;
+        write!(file, "{}", self.to_csv_string())
+    }
+
+    /// Load parameters from a CSV file if `path` ends with `.csv`, and from JSON otherwise
+    /// # Errors
+    /// Returns an error if the file cannot be read or holds no parameters
+    pub fn load(path: &str) -> Result<Self, String> {
+        if is_csv(path) {
+            Self::from_csv(path)
+        } else {
+            Self::from_json(path)
+        }
+    }
+
+    /// Save parameters to a CSV file if `path` ends with `.csv`, and to JSON otherwise.
+    /// Only the per-turn weights and the pruning settings are kept in CSV.
+    /// # Errors
+    /// Returns an error if the file cannot be opened or written to
+    pub fn save(&self, path: &str) -> std::io::Result<()> {
+        if is_csv(path) {
+            self.to_csv(path)
+        } else {
+            self.to_json(path)
+        }
+    }
+
+    /// Problems with the values, that would make the search misbehave
+    #[must_use]
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = vec![];
+        for (name, row) in Self::ROW_NAMES.iter().zip(self.as_array()) {
+            for (turn, value) in row.iter().enumerate() {
+                if !value.is_finite() {
+                    problems.push(format!("{name} of turn {} is {value}", turn + 1));
+                }
+            }
+        }
+        for (turn, value) in self.exploration_variables.iter().enumerate() {
+            if *value < 0.0 {
+                problems.push(format!(
+                    "exploration_variables of turn {} is negative: {value}",
+                    turn + 1
+                ));
+            }
+        }
+        if !(self.prune_alpha >= 0.0 && self.prune_alpha.is_finite()) {
+            problems.push(format!(
+                "prune_alpha should be a finite number, zero or more, not {}",
+                self.prune_alpha
+            ));
+        }
+        if self.pruning && self.prune_minimum_node_count == 0 {
+            problems.push("prune_minimum_node_count should be positive when pruning".to_string());
+        }
+        problems
+    }
+
+    /// Every value that differs from `self` in `other`. Weights that differ in the
+    /// last digits only, as JSON does not always read back the exact value
+    /// written, are the same.
+    #[must_use]
+    pub fn diff(&self, other: &Self) -> Vec<ParameterChange> {
+        let (Ok(Value::Object(from)), Ok(Value::Object(to))) =
+            (serde_json::to_value(self), serde_json::to_value(other))
+        else {
+            return vec![];
+        };
+        let mut changes = vec![];
+        for (name, from) in &from {
+            let to = to.get(name).unwrap_or(&Value::Null);
+            if let Some(row) = Self::ROW_NAMES.iter().find(|row| *row == name) {
+                let turns = from
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .zip(to.as_array().into_iter().flatten());
+                for (turn, (from, to)) in turns.enumerate() {
+                    if let (Some(from), Some(to)) = (from.as_f64(), to.as_f64()) {
+                        if !nearly_equal(from, to) {
+                            changes.push(ParameterChange::Weight {
+                                row,
+                                turn: turn + 1,
+                                from,
+                                to,
+                            });
+                        }
+                    }
+                }
+            } else if from != to {
+                changes.push(ParameterChange::Setting {
+                    name: name.clone(),
+                    from: from.clone(),
+                    to: to.clone(),
+                });
+            }
+        }
+        changes
+    }
+}
+
+/// A value that differs between two sets of parameters
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParameterChange {
+    /// A per-turn weight, of turns 1 to 7
+    Weight {
+        row: &'static str,
+        turn: usize,
+        from: f64,
+        to: f64,
+    },
+    /// Any other setting, as it is written in JSON
+    Setting {
+        name: String,
+        from: Value,
+        to: Value,
+    },
+}
+
+impl fmt::Display for ParameterChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Weight {
+                row,
+                turn,
+                from,
+                to,
+            } => write!(f, "{row}[turn {turn}]: {from} -> {to} ({:+})", to - from),
+            Self::Setting { name, from, to } => write!(f, "{name}: {from} -> {to}"),
+        }
+    }
+}
+
+fn nearly_equal(a: f64, b: f64) -> bool {
+    (a - b).abs() <= 1e-12 * a.abs().max(b.abs())
+}
+
+/// Whether `path` names a CSV file, going by its extension
+#[must_use]
+pub fn is_csv(path: &str) -> bool {
+    std::path::Path::new(path)
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("csv"))
+}
+
+fn parse_number(column: &str, value: &str) -> Result<f64, String> {
+    value
+        .parse()
+        .map_err(|_| format!("Error loading Heuristics: Could not parse {column}: {value}"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mcts::evaluator::LeafEvaluatorKind;
+    use crate::mcts::heuristics::Heuristics;
+
+    const HEURISTICS_CSV: &str = include_str!("heuristics.csv");
+
+    #[test]
+    fn test_reads_the_old_tables() {
+        let parameters =
+            Parameters::parse_csv(HEURISTICS_CSV, Parameters::from([[0.0; 7]; 8])).unwrap();
+        assert!((parameters.exploration_variables[0] - 22.890_895_232_924_937).abs() < 1e-12);
+        assert!(
+            (parameters.piece_connects_to_other_piece[6] - 0.020_743_070_522_264_304).abs() < 1e-12
+        );
+        assert!(!parameters.pruning);
+        assert!(parameters.problems().is_empty());
+
+        // The weights are written back as they were read
+        let table = parameters.to_csv_string();
+        for (line, original) in table.lines().zip(HEURISTICS_CSV.lines()) {
+            let columns = line.split(',').take(8).collect::<Vec<_>>().join(",");
+            assert_eq!(columns, original);
+        }
+    }
+
+    #[test]
+    fn test_csv_round_trip_keeps_the_pruning_settings() {
+        let mut parameters = Heuristics::default().parameters;
+        parameters.pruning = true;
+        parameters.prune_minimum_node_count = 12;
+        parameters.prune_alpha = 2.5;
+        parameters.special_cost[3] = -7.25;
+
+        let read = Parameters::parse_csv(
+            &parameters.to_csv_string(),
+            Heuristics::default().parameters,
+        )
+        .unwrap();
+        assert!(read.diff(&parameters).is_empty());
+
+        let mismatched = parameters.to_csv_string().replacen(",12,", ",13,", 1);
+        assert!(Parameters::parse_csv(&mismatched, parameters.clone()).is_err());
+        let unknown = parameters
+            .to_csv_string()
+            .replacen("pruning", "prunning", 1);
+        assert!(Parameters::parse_csv(&unknown, parameters).is_err());
+    }
+
+    #[test]
+    fn test_diff_lists_every_change() {
+        let parameters = Heuristics::default().parameters;
+        let mut other = parameters.clone();
+        other.special_cost[1] += 1.0;
+        other.exploration_variables[6] = -1.0;
+        other.final_round_positions = 0;
+        // Off by the last digit, as after a round trip through JSON
+        other.piece_connects_to_other_piece[3] =
+            f64::from_bits(parameters.piece_connects_to_other_piece[3].to_bits() + 1);
+
+        let changes = parameters.diff(&other);
+        assert_eq!(changes.len(), 3);
+        assert!(changes.contains(&ParameterChange::Weight {
+            row: "special_cost",
+            turn: 2,
+            from: parameters.special_cost[1],
+            to: other.special_cost[1],
+        }));
+        assert!(changes
+            .iter()
+            .any(|change| matches!(change, ParameterChange::Setting { name, .. } if name == "final_round_positions")));
+        assert_eq!(other.problems().len(), 1);
+    }
+
+    #[test]
+    fn test_prune_alpha_may_be_zero() {
+        let mut parameters = Heuristics::default().parameters;
+        parameters.prune_alpha = 0.0;
+        assert!(parameters.problems().is_empty());
+        for alpha in [-1.0, f64::NAN, f64::INFINITY] {
+            parameters.prune_alpha = alpha;
+            assert_eq!(parameters.problems().len(), 1);
+        }
+    }
+
+    #[test]
+    fn test_value_network_is_no_problem() {
+        // Whether a model is loaded is up to the search, not the file
+        let mut parameters = Heuristics::default().parameters;
+        parameters.leaf_evaluator = LeafEvaluatorKind::ValueNetwork;
+        assert!(parameters.problems().is_empty());
+    }
+}
@@ -1,6 +1,16 @@
 use crate::board::Board;
 use crate::game::mv::Move;
 use crate::game::Game;
+use crate::mcts::amaf::McRave;
+use crate::mcts::chance::{ChanceValue, RollSampling};
+use crate::mcts::distribution::Objective;
+use crate::mcts::evaluator::{LeafEvaluatorKind, RolloutCutoff};
+use crate::mcts::final_move::FinalMoveSelection;
+use crate::mcts::halving::RootAlgorithm;
+use crate::mcts::normalization::{ScoreNormalization, ValueBounds};
+use crate::mcts::rollout::RolloutPolicyKind;
+use crate::mcts::selection::SelectionPolicyKind;
+use crate::mcts::solver::{default_position_limit, DEFAULT_POSITION_LIMIT};
 use crate::mcts::Score;
 use crate::pieces::Piece;
 use burn::{
@@ -13,8 +23,8 @@ use nn::{data::DataItem, training::TrainingConfig, Model};
 use ord_subset::OrdSubsetIterExt;
 use std::fs::File;
 use std::io::prelude::*;
+pub mod format;
 pub mod nn;
-mod rave;
 
 pub type HeuristicOptions = [[f64; 7]; 8];
 use serde::{Deserialize, Serialize};
@@ -29,8 +39,42 @@ pub struct Parameters {
     pub piece_locks_out_other_piece: [f64; 7],
     pub piece_is_2nd_order_neighbor: [f64; 7],
     pub piece_is_3rd_order_neighbor: [f64; 7],
+    /// Only consider the children with the best heuristic estimates, adding more as
+    /// the parent is visited more. See `mcts::pruning`.
+    #[serde(default)]
+    pub pruning: bool,
     pub prune_minimum_node_count: u16,
     pub prune_alpha: f64,
+    #[serde(default)]
+    pub roll_sampling: RollSampling,
+    #[serde(default)]
+    pub chance_value: ChanceValue,
+    #[serde(default)]
+    pub rollout_policy: RolloutPolicyKind,
+    /// Where to cut rollouts off. `None` plays them out to the end of the game
+    #[serde(default)]
+    pub rollout_cutoff: Option<RolloutCutoff>,
+    #[serde(default)]
+    pub leaf_evaluator: LeafEvaluatorKind,
+    #[serde(default)]
+    pub selection_policy: SelectionPolicyKind,
+    #[serde(default)]
+    pub root_algorithm: RootAlgorithm,
+    /// Blend per-node AMAF statistics into selection. `None` uses the heuristic estimate alone
+    #[serde(default)]
+    pub mc_rave: Option<McRave>,
+    #[serde(default)]
+    pub final_move_selection: FinalMoveSelection,
+    #[serde(default)]
+    pub objective: Objective,
+    /// Scale of the values UCT compares. Missing from parameters tuned for raw scores,
+    /// which then keep using raw scores. See `mcts::normalization`.
+    #[serde(default)]
+    pub score_normalization: ScoreNormalization,
+    /// Most positions to search when solving the last turn exactly, before falling
+    /// back to the tree search. Zero always searches. See `mcts::solver`.
+    #[serde(default = "default_position_limit")]
+    pub final_round_positions: usize,
 }
 
 /// TODO: make "from_json" and "to_json", and make the appropriate json
@@ -71,6 +115,43 @@ impl Parameters {
         }
     }
 
+    /// Check that a search with these parameters has what it needs, given
+    /// whether a trained model is loaded into its `Heuristics`
+    /// # Errors
+    /// Returns an error if the leaf evaluator is the value network, and there is no model
+    pub fn check_model(&self, has_model: bool) -> Result<(), String> {
+        if self.leaf_evaluator == LeafEvaluatorKind::ValueNetwork && !has_model {
+            return Err("The value_network leaf evaluator needs a trained model".to_string());
+        }
+        Ok(())
+    }
+
+    /// Re-express exploration parameters tuned for raw scores in units of the
+    /// spread of values, taking the spread to be `score_range` points, and
+    /// normalise scores with per-turn min/max bounds.
+    /// Parameters that already normalise scores are left as they are.
+    pub fn normalize_units(&mut self, score_range: f64) {
+        if self.score_normalization != ScoreNormalization::None {
+            return;
+        }
+        for bias in &mut self.exploration_variables {
+            *bias /= score_range;
+        }
+        self.score_normalization = ScoreNormalization::MinMax { per_turn: true };
+    }
+
+    /// Names of the rows of `as_array`
+    pub const ROW_NAMES: [&'static str; 8] = [
+        "unexplored_value",
+        "exploration_variables",
+        "special_cost",
+        "piece_connects_to_exit",
+        "piece_connects_to_other_piece",
+        "piece_locks_out_other_piece",
+        "piece_is_2nd_order_neighbor",
+        "piece_is_3rd_order_neighbor",
+    ];
+
     #[must_use]
     pub fn as_array(&self) -> [[f64; 7]; 8] {
         [
@@ -84,6 +165,20 @@ impl Parameters {
             self.piece_is_3rd_order_neighbor,
         ]
     }
+
+    /// The rows of `as_array`, to change in place
+    pub fn rows_mut(&mut self) -> [&mut [f64; 7]; 8] {
+        [
+            &mut self.unexplored_value,
+            &mut self.exploration_variables,
+            &mut self.special_cost,
+            &mut self.piece_connects_to_exit,
+            &mut self.piece_connects_to_other_piece,
+            &mut self.piece_locks_out_other_piece,
+            &mut self.piece_is_2nd_order_neighbor,
+            &mut self.piece_is_3rd_order_neighbor,
+        ]
+    }
 }
 
 impl From<[[f64; 7]; 8]> for Parameters {
@@ -97,8 +192,21 @@ impl From<[[f64; 7]; 8]> for Parameters {
             piece_locks_out_other_piece: array[5],
             piece_is_2nd_order_neighbor: array[6],
             piece_is_3rd_order_neighbor: array[7],
+            pruning: false,
             prune_minimum_node_count: 60,
             prune_alpha: 4.0,
+            roll_sampling: RollSampling::default(),
+            chance_value: ChanceValue::default(),
+            rollout_policy: RolloutPolicyKind::default(),
+            rollout_cutoff: None,
+            leaf_evaluator: LeafEvaluatorKind::default(),
+            selection_policy: SelectionPolicyKind::default(),
+            root_algorithm: RootAlgorithm::default(),
+            mc_rave: None,
+            final_move_selection: FinalMoveSelection::default(),
+            objective: Objective::default(),
+            score_normalization: ScoreNormalization::None,
+            final_round_positions: DEFAULT_POSITION_LIMIT,
         }
     }
 }
@@ -106,34 +214,39 @@ impl From<[[f64; 7]; 8]> for Parameters {
 #[derive(Clone)]
 pub struct Heuristics {
     pub parameters: Parameters,
-    pub rave: Option<rave::Rave>,
     pub tree_reuse: bool,
     pub move_nn: Option<Model<Wgpu>>,
+    /// Bounds of the values backed up in the current search tree, for `Parameters::score_normalization`.
+    /// Reset whenever the tree starts over or moves on.
+    pub value_bounds: ValueBounds,
 }
 
 impl Heuristics {
     #[must_use]
     pub fn new(parameters: Parameters) -> Self {
-        // let mut rave = rave::Rave::new();
-        // let rave = Some(rave);
-
         Self {
             parameters,
             move_nn: None,
-            rave: None,
             tree_reuse: true,
+            value_bounds: ValueBounds::default(),
         }
     }
 
     pub fn get_default_model(device: WgpuDevice) -> Model<Wgpu> {
         const MODEL_DIR: &str = "./src/mcts/heuristics/nn";
-        let config = TrainingConfig::load(format!("{MODEL_DIR}/model.config.json"))
-            .expect("Config should exist for the model");
+        Self::load_model(MODEL_DIR, &device).expect("Trained model should exist")
+    }
+
+    /// Load the model trained into `dir`, from its `model.config.json` and `model` files
+    /// # Errors
+    /// Returns an error if either file is missing or cannot be read
+    pub fn load_model(dir: &str, device: &WgpuDevice) -> Result<Model<Wgpu>, String> {
+        let config = TrainingConfig::load(format!("{dir}/model.config.json"))
+            .map_err(|e| format!("Error loading model config from {dir}: {e}"))?;
         let record = CompactRecorder::new()
-            .load(format!("{MODEL_DIR}/model").into(), &device)
-            .expect("Trained model should exist");
-        let model = config.model.init(&device).load_record(record);
-        model
+            .load(format!("{dir}/model").into(), device)
+            .map_err(|e| format!("Error loading model from {dir}: {e}"))?;
+        Ok(config.model.init(device).load_record(record))
     }
 
     pub fn with_model(mut self, device: WgpuDevice) -> Self {
@@ -152,20 +265,27 @@ impl Heuristics {
 
     /// Import a Heuristics instance from a `.json`-file at the given `path`
     /// # Errors
-    /// Returns an error if the file cannot be opened or read.
+    /// Returns an error if the file cannot be opened or read, or if the parameters
+    /// need a model, as none is loaded. See `Parameters::check_model`, and
+    /// `SharedHeuristics::load` to load them with a model.
     /// # Panics
     /// Panics if the file cannot be read.
     pub fn from_json(path: &str) -> Result<Self, String> {
         let parameters = Parameters::from_json(path)?;
+        parameters
+            .check_model(false)
+            .map_err(|e| format!("Error loading Heuristics: {e}"))?;
         Ok(Self::new(parameters))
     }
 
     #[must_use]
+    /// Preference of the softmax rollout policy for `mv`. The policy adds the randomness.
     pub fn get_rollout_policy_value(&mut self, game: &Game, mv: Move) -> f64 {
-        self.get_move_estimation(game, mv) // todo: maybe add some randomness here
+        self.get_move_estimation(game, mv)
     }
 
     #[must_use]
+    /// The move picked by the greedy rollout policy
     pub fn select_rollout_move(&mut self, game: &Game, moves: Vec<Move>) -> Option<Move> {
         moves
             .into_iter()
@@ -247,12 +367,6 @@ impl Heuristics {
         0.0
     }
 
-    pub fn update(&mut self, turn: u8, mv: Move, score: f64) {
-        if let Some(rave) = &mut self.rave {
-            rave.update_rave(turn, mv, score);
-        }
-    }
-
     // #[must_use]
     /// Recieve a value if the move connects to the edge of the longest network path
     // fn piece_connects_to_longest_path(&self, game: &Game, mv: Move) -> f64 {
@@ -322,6 +436,27 @@ impl Heuristics {
         }
 
         let ucb = mean_score;
+        let exploration_term = self.get_exploration_term(mv, visits, parent_visits, game);
+
+        let k = 1.;
+        let n = visits as f64;
+        let beta = (k / 3.0f64.mul_add(n, k)).sqrt();
+        let q = (1.0 - beta).mul_add(ucb, beta * estimated_value);
+
+        self.normalize_value(game.turn, q) + exploration_term
+    }
+
+    /// The part of the exploration value that is added to the estimated mean.
+    /// Not defined for the last turn, where only the mean is used.
+    #[must_use]
+    pub fn get_exploration_term(
+        &self,
+        mv: Move,
+        visits: u64,
+        parent_visits: u64,
+        game: &Game,
+    ) -> f64 {
+        let turn = usize::from(game.turn);
         let exploration_bias = self.exploration_bias(turn);
         let exploration: f64 = if visits == 0 {
             // self.get_rollout_policy_value(game, mv)
@@ -332,14 +467,7 @@ impl Heuristics {
 
         let exploration_term = exploration_bias * exploration;
 
-        let exploration_term = exploration_term + self.special_use(turn, mv);
-
-        let k = 1.;
-        let n = visits as f64;
-        let beta = (k / 3.0f64.mul_add(n, k)).sqrt();
-        let q = (1.0 - beta).mul_add(ucb, beta * estimated_value);
-
-        q + exploration_term
+        exploration_term + self.normalize_difference(game.turn, self.special_use(turn, mv))
     }
 
     #[must_use]
@@ -371,15 +499,6 @@ impl Heuristics {
 
         if let Some(_) = &self.move_nn {
             unimplemented!("Neural net not yet implemented!");
-        } else if let Some(rave) = self.rave.as_ref() {
-            let k = 1.;
-            let rave_value = rave.get_rave(turn as u8, mv);
-            let rave_value = rave_value + rave.rave_exploration_bias;
-            let n = visits as f64;
-            let beta = (k / 3.0f64.mul_add(n, k)).sqrt();
-            let q = (1.0 - beta).mul_add(ucb, beta * rave_value);
-
-            q + exploration_term
         } else {
             let estimated_value = self.get_move_estimation(game, mv);
             let k = 1.;
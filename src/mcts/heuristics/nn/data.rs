@@ -19,7 +19,10 @@ pub fn get_connection() -> rusqlite::Connection {
     );
 
     match conn {
-        Ok(c) => c,
+        Ok(c) => {
+            add_turn_column(&c).expect("Could not add the turn column to the sqlite database");
+            c
+        }
         Err(_) => {
             let conn = rusqlite::Connection::open("./data.sqlite").unwrap();
 
@@ -28,7 +31,8 @@ pub fn get_connection() -> rusqlite::Connection {
                     id     INTEGER PRIMARY KEY,
                     board  TEXT NOT NULL,
                     move   TEXT NOT NULL,
-                    score  INTEGER NOT NULL
+                    score  INTEGER NOT NULL,
+                    turn   INTEGER
                 )",
                 (), // empty list of parameters.
             )
@@ -39,6 +43,20 @@ pub fn get_connection() -> rusqlite::Connection {
     }
 }
 
+/// Give tables from before the turn was recorded the column, without a turn for their rows
+fn add_turn_column(conn: &rusqlite::Connection) -> rusqlite::Result<()> {
+    let has_turn = conn
+        .prepare("PRAGMA table_info(matches)")?
+        .query_map([], |row| row.get::<usize, String>(1))?
+        .collect::<rusqlite::Result<Vec<_>>>()?
+        .iter()
+        .any(|column| column == "turn");
+    if !has_turn {
+        conn.execute("ALTER TABLE matches ADD COLUMN turn INTEGER", ())?;
+    }
+    Ok(())
+}
+
 #[derive(Clone)]
 pub struct DataBatcher<B: Backend> {
     device: B::Device,
@@ -52,22 +70,25 @@ impl<B: Backend> DataBatcher<B> {
 
 #[derive(Clone, Debug)]
 pub struct DataItem {
-    board: Board,
-    mv: Move,
-    score: Score,
+    pub(crate) board: Board,
+    pub(crate) mv: Move,
+    pub(crate) score: Score,
+    /// The turn the move was made in, unless it was recorded before turns were
+    pub(crate) turn: Option<u8>,
 }
 pub struct GameDataset {}
 
 impl GameDataset {
-    fn get_all() -> Vec<DataItem> {
+    pub(crate) fn get_all() -> Vec<DataItem> {
         get_connection()
-            .prepare("SELECT board, move, score FROM matches")
+            .prepare("SELECT board, move, score, turn FROM matches")
             .expect("Could not get data from sqlite database")
             .query_map([], |row| {
                 Ok(DataItem {
                     board: Board::decode(row.get::<usize, String>(0).unwrap().as_str()),
                     mv: Move::from_str(row.get::<usize, String>(1).unwrap().as_str()).unwrap(),
                     score: row.get(2).unwrap(),
+                    turn: row.get(3).unwrap(),
                 })
             })
             .unwrap()
@@ -200,3 +221,25 @@ impl<B: Backend> Batcher<DataItem, DataBatch<B>> for DataBatcher<B> {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_turn_column_is_added_once() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        conn.execute(
+            "CREATE TABLE matches (id INTEGER PRIMARY KEY, board TEXT NOT NULL, move TEXT NOT NULL, score INTEGER NOT NULL)",
+            (),
+        )
+        .unwrap();
+        add_turn_column(&conn).unwrap();
+        add_turn_column(&conn).unwrap();
+        conn.execute(
+            "INSERT INTO matches (board, move, score, turn) VALUES ('', '', 0, 1)",
+            (),
+        )
+        .unwrap();
+    }
+}
@@ -0,0 +1,216 @@
+//! Playing a game in hindsight, with every roll known in advance.
+//!
+//! The dice of a seeded game do not depend on the placements, so the seven
+//! rolls can be read off the seed before the game is played. Knowing them,
+//! the game has no chance left, and `solve_hindsight` searches it with a beam
+//! search: within each turn, the `beam_width` boards with the best
+//! `Board::score` are kept after every placement, and the best boards at the end
+//! of the turn go on to the next roll. The last turn is solved exactly by
+//! `solver::solve_final_round` where it is small enough.
+//!
+//! The score found, the hindsight best found, is what a player who knew the dice
+//! could have scored, so its difference from the score actually played, the
+//! regret, measures how much is lost to not knowing them and to imperfect search.
+//! A beam search may miss the best line, so the hindsight best found is only a
+//! lower bound on perfect play, which grows towards it with the beam width.
+
+use super::solver::{solve_final_round, DEFAULT_POSITION_LIMIT};
+use crate::game::mv::Move;
+use crate::game::roll::Roll;
+use crate::game::Game;
+use rand::SeedableRng;
+use rand_xoshiro::SplitMix64;
+use std::collections::HashSet;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HindsightOptions {
+    /// Number of boards kept after every placement
+    pub beam_width: usize,
+    /// Position limit of the exact solver on the last turn, see `solver::solve_final_round`
+    pub final_round_positions: usize,
+}
+
+impl Default for HindsightOptions {
+    fn default() -> Self {
+        Self {
+            beam_width: 16,
+            final_round_positions: DEFAULT_POSITION_LIMIT,
+        }
+    }
+}
+
+/// The best line found for a known sequence of rolls
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HindsightSolution {
+    pub score: i32,
+    /// Every move of the game, with `Move::SetRoll` for the rolls after the first
+    pub moves: Vec<Move>,
+}
+
+/// A game in progress, and the moves that led to it
+#[derive(Clone)]
+pub(super) struct Line {
+    pub(super) game: Game,
+    pub(super) moves: Vec<Move>,
+}
+
+impl Line {
+    pub(super) fn then(&self, mv: Move) -> Self {
+        let mut line = self.clone();
+        line.game.do_move(mv);
+        line.moves.push(mv);
+        line
+    }
+}
+
+/// The seven rolls of the game started by `Game::new_from_seed(seed)`
+#[must_use]
+pub fn rolls_from_seed(seed: [u8; 8]) -> [Roll; 7] {
+    let mut game = Game::default();
+    game.set_rng(SplitMix64::from_seed(seed));
+    [(); 7].map(|()| game.generate_roll())
+}
+
+/// The `width` lines with the best scores, dropping lines that reach the same position
+pub(super) fn best_lines(lines: Vec<Line>, width: usize) -> Vec<Line> {
+    let mut positions = HashSet::new();
+    let mut lines = lines
+        .into_iter()
+        .filter(|line| positions.insert(line.game.encode()))
+        .map(|line| (line.game.board.score(), line))
+        .collect::<Vec<_>>();
+    lines.sort_by_key(|(score, _)| std::cmp::Reverse(*score));
+    lines
+        .into_iter()
+        .take(width)
+        .map(|(_, line)| line)
+        .collect()
+}
+
+/// Play the rest of the turn from every line in `beam`, keeping the `beam_width` lines
+/// with the best scores after every placement. Returns the lines that reached the end
+/// of the turn. On the last turn, lines that `solver::solve_final_round` can solve
+/// within `final_round_positions` are played out exactly.
+pub(super) fn finish_turn(
+    mut beam: Vec<Line>,
+    beam_width: usize,
+    final_round_positions: usize,
+) -> Vec<Line> {
+    let mut turn_ended = vec![];
+    while !beam.is_empty() {
+        let mut placed = vec![];
+        for mut line in beam {
+            if let Some(solution) = solve_final_round(&line.game, final_round_positions) {
+                for mv in solution.moves {
+                    line = line.then(mv);
+                }
+                turn_ended.push(line);
+                continue;
+            }
+            for mv in line.game.generate_moves() {
+                match mv {
+                    Move::Roll | Move::End => turn_ended.push(line.clone()),
+                    mv => placed.push(line.then(mv)),
+                }
+            }
+        }
+        beam = best_lines(placed, beam_width);
+    }
+    turn_ended
+}
+
+/// Search for the best score with `rolls` known in advance
+/// # Panics
+/// Panics if `options.beam_width` is zero
+#[must_use]
+pub fn solve_hindsight(rolls: &[Roll; 7], options: &HindsightOptions) -> HindsightSolution {
+    assert!(options.beam_width > 0, "The beam should keep some boards");
+    let mut game = Game::default();
+    game.set_roll(rolls[0]);
+    let mut beam = vec![Line {
+        game,
+        moves: vec![],
+    }];
+    let mut best: Option<HindsightSolution> = None;
+
+    for turn in 1..=rolls.len() {
+        let turn_ended = finish_turn(
+            std::mem::take(&mut beam),
+            options.beam_width,
+            options.final_round_positions,
+        );
+
+        if turn == rolls.len() {
+            for mut line in turn_ended {
+                if !line.game.ended {
+                    line = line.then(Move::End);
+                }
+                let score = line.game.board.score();
+                if best.as_ref().is_none_or(|best| score > best.score) {
+                    best = Some(HindsightSolution {
+                        score,
+                        moves: line.moves,
+                    });
+                }
+            }
+        } else {
+            beam = best_lines(turn_ended, options.beam_width)
+                .iter()
+                .map(|line| line.then(Move::SetRoll(rolls[turn])))
+                .collect();
+        }
+    }
+
+    best.expect("Some line should reach the end of the game")
+}
+
+/// The score actually played subtracted from the hindsight best found. Negative when the
+/// game played beat the hindsight search, which then missed a line it could have found:
+/// the beam is too narrow for these rolls.
+#[must_use]
+pub fn regret(played: i32, best_found: i32) -> i32 {
+    best_found - played
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_rolls_from_seed_match_the_game() {
+        let seed = [0, 0, 0, 0, 0, 0, 0, 89];
+        let rolls = rolls_from_seed(seed);
+        let mut game = Game::new_from_seed(seed);
+        for roll in &rolls[..6] {
+            assert_eq!(game.current_roll(), Some(*roll));
+            loop {
+                let mv = game.generate_moves()[0];
+                game.do_move(mv);
+                if mv == Move::Roll {
+                    break;
+                }
+            }
+        }
+        assert_eq!(game.current_roll(), Some(rolls[6]));
+    }
+
+    #[test]
+    fn test_hindsight_line_replays() {
+        let seed = [0, 0, 0, 0, 0, 0, 0, 97];
+        let options = HindsightOptions {
+            beam_width: 2,
+            ..HindsightOptions::default()
+        };
+        let solution = solve_hindsight(&rolls_from_seed(seed), &options);
+
+        let mut game = Game::new_from_seed(seed);
+        for &mv in &solution.moves {
+            assert!(game.generate_moves().contains(&mv) || matches!(mv, Move::SetRoll(_)));
+            assert_eq!(game.do_move(mv), None);
+        }
+        assert!(game.ended);
+        assert_eq!(game.board.score(), solution.score);
+        assert_eq!(regret(solution.score - 5, solution.score), 5);
+        assert_eq!(regret(solution.score + 5, solution.score), -5);
+    }
+}
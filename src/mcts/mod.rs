@@ -3,16 +3,47 @@ use crate::game::{mv::Move, roll::Roll, Game};
 use rand::{RngCore, SeedableRng};
 
 use rand_xoshiro::SplitMix64;
-use std::convert::TryInto;
+pub mod amaf;
+pub mod analysis;
+pub mod arena;
+pub mod book;
+pub mod chance;
+pub mod distribution;
+pub mod dot;
+pub mod engine;
+pub mod evaluator;
+pub mod expectimax;
+pub mod final_move;
+pub mod halving;
 pub mod heuristics;
+pub mod hindsight;
+pub mod normalization;
+pub mod persist;
+pub mod planner;
+pub mod pruning;
+pub mod rollout;
+pub mod scaling;
+pub mod selection;
+pub mod solver;
+pub mod tournament;
 pub mod trainer;
+use amaf::AmafTrail;
+use analysis::SearchLimits;
+use arena::{NodeArena, NodeId};
+use distribution::ScoreDistribution;
+use evaluator::LeafEvaluator;
+use final_move::FinalMoveSelection;
 use heuristics::Heuristics;
+use normalization::ValueBounds;
+use rollout::RolloutPolicy;
+use selection::SelectionPolicy;
 
+use ord_subset::OrdSubsetIterExt;
 use rand;
-use rand::seq::SliceRandom;
 use rand::Rng;
 use serde::{ser::SerializeMap, Serialize, Serializer};
 use std::collections::HashMap;
+use std::sync::Arc;
 
 use crate::identity_hasher::BuildHasher;
 
@@ -22,6 +53,8 @@ pub type Score = f64;
 pub struct Node {
     pub visits: u64,
     pub total_score: f64,
+    /// Distribution of the scores that make up `total_score`
+    pub distribution: ScoreDistribution,
     pub is_terminal: bool,
     pub heuristic: f64,
     pub children: Box<[Edge]>,
@@ -34,6 +67,7 @@ impl Node {
             children: Box::new([]),
             is_terminal: false,
             total_score: 0.,
+            distribution: ScoreDistribution::new(),
             heuristic: 0.,
             visits: 0,
         }
@@ -53,10 +87,11 @@ impl Node {
     }
 }
 
+/// The child of an edge, as indices into the `NodeArena`
 #[derive(Debug)]
 pub enum SingleOrMultiple {
-    Single(Node),
-    Multiple(HashMap<Roll, Node, BuildHasher>),
+    Single(NodeId),
+    Multiple(HashMap<Roll, NodeId, BuildHasher>),
 }
 use SingleOrMultiple::{Multiple, Single};
 
@@ -66,7 +101,7 @@ impl Serialize for SingleOrMultiple {
         S: Serializer,
     {
         match self {
-            Single(node) => node.serialize(serializer),
+            Single(id) => id.serialize(serializer),
             Multiple(nodes) => {
                 let mut map = serializer.serialize_map(Some(nodes.len()))?;
                 for (k, v) in nodes {
@@ -82,10 +117,22 @@ impl Serialize for SingleOrMultiple {
 pub struct Edge {
     pub mv: Move,
     pub visits: u64,
+    /// Value of this edge under `Parameters::objective`. By default, the mean score.
     pub mean_score: Score,
+    /// Distribution of the scores backed up through this edge
+    pub distribution: ScoreDistribution,
     pub heuristic_value: Option<Score>,
+    /// Prior probability of this edge among its siblings, set by selection policies that use one
+    pub prior: Option<Score>,
+    /// Number of iterations through the parent node that played this move, see `amaf`
+    pub amaf_visits: u64,
+    /// Mean score of those iterations
+    pub amaf_score: Score,
     pub child: Option<SingleOrMultiple>,
     pub pruned: bool,
+    /// A mirror image of an earlier sibling, never searched whatever `pruned` says.
+    /// See `book`.
+    pub folded: bool,
 }
 
 impl Edge {
@@ -96,11 +143,22 @@ impl Edge {
             child: None,
             visits: 0,
             mean_score: 0.,
+            distribution: ScoreDistribution::new(),
             heuristic_value: None,
+            prior: None,
+            amaf_visits: 0,
+            amaf_score: 0.,
             pruned: false,
+            folded: false,
         }
     }
 
+    /// Variance of the scores backed up through this edge
+    #[must_use]
+    pub fn variance(&self) -> Score {
+        self.distribution.variance()
+    }
+
     /// One iteration of mcts
     /// Recursively `select`s through the tree,
     /// updating the `visits` count and scores along the way
@@ -114,199 +172,273 @@ impl Edge {
     pub fn select(
         &mut self,
         mut game: Game,
+        arena: &mut NodeArena,
         heuristics: &mut Heuristics,
         rng: &mut dyn RngCore,
     ) -> Score {
         // Expand and rollout
         if self.visits == 0 {
-            return self.expand(game, heuristics, rng);
+            return self.expand(game, arena, heuristics, rng);
         }
-        let mut generate_children = self.visits == 1 || game.turn == 0;
-
-        let node = self.child.as_mut().unwrap();
-        let node = match node {
-            Multiple(nodes) => {
-                debug_assert_eq!(self.mv, Move::Roll);
-                // If edge Move is `Roll`, we don't get to choose which roll
-                // to search. We have to actually roll the dice.
-                let roll = game.generate_roll();
-                if let Some(child) = nodes.get_mut(&roll) {
-                    child
-                } else {
-                    let child = Node::new();
-                    nodes.insert(roll, child);
-                    generate_children = true;
-                    nodes.get_mut(&roll).unwrap()
-                }
-            }
-            Single(node) => node,
-        };
+        let (node_id, generate_children) = self.child_node(&mut game, arena, heuristics, rng);
         if generate_children {
-            node.generate_children(&mut game);
+            arena.generate_children(node_id, &mut game);
         }
+        let node = &mut arena[node_id];
         if node.is_terminal {
             // Increment `visits`. But don't change `self.mean`: it's the same, still
+            let score = f64::from(game.board.score());
             self.visits += 1;
             node.visits += 1;
-            node.total_score += self.mean_score;
-            return self.mean_score;
+            node.total_score += score;
+            let objective = heuristics.parameters.objective;
+            node.distribution.add(score, objective);
+            self.distribution.add(score, objective);
+            arena.amaf_trail.start();
+            return score;
         }
 
         assert_ne!(node.children.len(), 0, "No legal moves!");
-        let mut best_child_node_index = 0;
-
-        #[cfg(feature = "pruning")]
-        {
-            let parent_visits = self.visits;
-            let mut children = node
-                .children
-                .iter()
-                .filter(|edge| !edge.pruned)
-                .map(|edge| edge.exploration_value(parent_visits, heuristics, &game))
-                .enumerate()
-                .collect::<Vec<_>>();
-
-            #[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
-            struct ComparableScore(Score);
-            impl Ord for ComparableScore {
-                fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-                    self.partial_cmp(other).unwrap_or(std::cmp::Ordering::Equal)
-                }
-            }
 
-            impl Eq for ComparableScore {}
-            let n = node.children.len();
-            let t = heuristics.parameters.prune_minimum_node_count as f64;
-            let alpha = heuristics.parameters.prune_alpha;
-
-            let remaining_nodes = (alpha * (n as f64).ln()).max(t).ceil() as usize;
+        // Take the children out of the arena while we recurse into them
+        let mut children = std::mem::take(&mut node.children);
+        let mut best_child_node_index = 0;
+        let policy = heuristics.parameters.selection_policy;
+        policy.prepare(&mut children, &game, heuristics);
 
-            if n > remaining_nodes {
-                children.sort_unstable_by_key(|(_, val)| ComparableScore(-*val));
+        if heuristics.parameters.pruning {
+            pruning::prune_children(&mut children, self.visits, &game, heuristics);
+        }
 
-                for (i, _) in children.iter().skip(remaining_nodes) {
-                    let child = node.children.get_mut(*i).unwrap();
-                    child.pruned = true;
-                }
-                best_child_node_index = children.first().unwrap().0;
-            } else {
-                let mut best_exploration_value = Score::MIN;
-
-                for (i, edge) in node.children.iter().enumerate() {
-                    let child_exploration_value =
-                        edge.exploration_value(self.visits, heuristics, &game);
-                    if child_exploration_value >= best_exploration_value {
-                        best_child_node_index = i;
-                        best_exploration_value = child_exploration_value;
-                    }
-                }
+        let mut best_exploration_value = Score::MIN;
+        for (i, edge) in children.iter_mut().enumerate() {
+            if edge.pruned || edge.folded {
+                continue;
+            }
+            let child_exploration_value = edge.exploration_value(self, heuristics, &game, rng);
+            if child_exploration_value >= best_exploration_value {
+                best_child_node_index = i;
+                best_exploration_value = child_exploration_value;
             }
         }
 
-        #[cfg(not(feature = "pruning"))]
-        {
-            let mut best_exploration_value = Score::MIN;
-
-            for (i, edge) in node.children.iter_mut().enumerate() {
-                let child_exploration_value =
-                    edge.exploration_value(self.visits, heuristics, &game);
-                if child_exploration_value >= best_exploration_value {
-                    best_child_node_index = i;
-                    best_exploration_value = child_exploration_value;
-                }
-            }
+        self.descend(
+            node_id,
+            children,
+            best_child_node_index,
+            game,
+            arena,
+            heuristics,
+            rng,
+        )
+    }
+
+    /// Search the child edge at `index` of an edge that has been visited and has children,
+    /// bypassing the selection policy. Used by root algorithms that pick the edge themselves.
+    ///
+    /// # Panics
+    /// Panics if the node below this edge has no child at `index`
+    pub fn select_child(
+        &mut self,
+        index: usize,
+        mut game: Game,
+        arena: &mut NodeArena,
+        heuristics: &mut Heuristics,
+        rng: &mut dyn RngCore,
+    ) -> Score {
+        debug_assert_ne!(self.visits, 0);
+        let (node_id, generate_children) = self.child_node(&mut game, arena, heuristics, rng);
+        if generate_children {
+            arena.generate_children(node_id, &mut game);
         }
+        let children = std::mem::take(&mut arena[node_id].children);
+        assert!(index < children.len(), "No child at index {}", index);
+        self.descend(node_id, children, index, game, arena, heuristics, rng)
+    }
 
-        let child_edge = node.children.get_mut(best_child_node_index).unwrap();
+    /// Search the child at `index` of `children`, which were taken out of the node at `node_id`,
+    /// then put them back and backpropagate the result
+    #[allow(clippy::too_many_arguments)]
+    fn descend(
+        &mut self,
+        node_id: NodeId,
+        mut children: Box<[Self]>,
+        index: usize,
+        mut game: Game,
+        arena: &mut NodeArena,
+        heuristics: &mut Heuristics,
+        rng: &mut dyn RngCore,
+    ) -> Score {
+        let child_edge = &mut children[index];
 
+        let selection_turn = game.turn;
         game.do_move(child_edge.mv);
-        let turn = game.turn;
-        let result = child_edge.select(game, heuristics, rng);
+        let result = child_edge.select(game, arena, heuristics, rng);
+        heuristics.observe_value(selection_turn, child_edge.mean_score);
+        let rave = heuristics.parameters.mc_rave;
+        arena.amaf_trail.extend(rave, child_edge.mv);
+        arena.amaf_trail.update(&mut children, result);
 
         // Backpropagate
+        let node = &mut arena[node_id];
+        node.children = children;
         self.visits += 1;
         node.visits += 1;
         node.total_score += result;
-        self.mean_score = node.total_score / self.visits as f64;
-        if !matches!(self.mv, Move::SetRoll(..)) {
-            heuristics.update(turn, self.mv, result);
-        }
+        let objective = heuristics.parameters.objective;
+        node.distribution.add(result, objective);
+        self.distribution.add(result, objective);
+        self.mean_score = match self.child.as_ref() {
+            Some(Multiple(nodes)) => {
+                let chance_value = heuristics.parameters.chance_value;
+                chance_value.value(nodes, arena, objective)
+            }
+            _ => objective.value(node.total_score / self.visits as f64, &self.distribution),
+        };
         result
     }
 
+    /// Find the node to continue the search from, creating it if necessary.
+    /// Returns the node, and whether its children need to be (re)generated
+    fn child_node(
+        &mut self,
+        game: &mut Game,
+        arena: &mut NodeArena,
+        heuristics: &Heuristics,
+        rng: &mut dyn RngCore,
+    ) -> (NodeId, bool) {
+        let mut generate_children = self.visits == 1 || game.turn == 0;
+
+        // The subtree below this edge was recycled by the garbage collector
+        if self.child.is_none() {
+            self.regrow(arena, game);
+            generate_children = true;
+        }
+
+        let node_id = match self.child.as_mut().unwrap() {
+            Multiple(nodes) => {
+                debug_assert_eq!(self.mv, Move::Roll);
+                // If edge Move is `Roll`, we don't get to choose which roll
+                // to search. We either roll the dice ourselves, or pick a roll
+                // by its probability.
+                let roll = heuristics
+                    .parameters
+                    .roll_sampling
+                    .sample(nodes, arena, game, rng);
+                if let Some(&child) = nodes.get(&roll) {
+                    child
+                } else {
+                    let child = arena.insert(Node::new());
+                    nodes.insert(roll, child);
+                    generate_children = true;
+                    child
+                }
+            }
+            Single(node_id) => *node_id,
+        };
+        (node_id, generate_children)
+    }
+
     fn exploration_value(
         &mut self,
-        parent_visits: u64,
+        parent: &Self,
         heuristics: &mut Heuristics,
         game: &Game,
+        rng: &mut dyn RngCore,
     ) -> Score {
         if self.heuristic_value.is_none() {
             self.heuristic_value = Some(heuristics.get_move_estimation(game, self.mv))
         }
 
-        heuristics.get_exploration_value_given_heuristic(
-            self.mv,
-            self.mean_score,
-            self.visits,
-            parent_visits,
-            game,
-            self.heuristic_value.unwrap(),
-        )
+        let policy = heuristics.parameters.selection_policy;
+        policy.value(self, parent, game, heuristics, rng)
     }
 
     // Never inline, to make CPU profiling easier
     #[inline(never)]
-    fn expand(&mut self, game: Game, heuristics: &mut Heuristics, rng: &mut dyn RngCore) -> Score {
+    fn expand(
+        &mut self,
+        game: Game,
+        arena: &mut NodeArena,
+        heuristics: &mut Heuristics,
+        rng: &mut dyn RngCore,
+    ) -> Score {
         debug_assert!(self.child.is_none());
+        let trail = &mut arena.amaf_trail;
+        trail.start();
+        let objective = heuristics.parameters.objective;
 
         if self.mv == Move::Roll {
             self.visits = 1;
-            let nodes: HashMap<Roll, Node, BuildHasher> = HashMap::with_hasher(BuildHasher);
+            let nodes: HashMap<Roll, NodeId, BuildHasher> = HashMap::with_hasher(BuildHasher);
             self.child = Some(Multiple(nodes));
-            let (score, _) = Self::rollout(game, heuristics, 0, rng);
+            let start_turn = game.turn;
+            let (score, _) = Self::rollout(game, heuristics, start_turn, 0, rng, trail);
+            self.distribution.add(score, objective);
             score
         } else {
             let mut child = Node::new();
-            let (score, is_terminal) = Self::rollout(game, heuristics, 0, rng);
+            let start_turn = game.turn;
+            let (score, is_terminal) = Self::rollout(game, heuristics, start_turn, 0, rng, trail);
             self.visits = 1;
-            self.mean_score = score;
+            self.distribution.add(score, objective);
+            self.mean_score = objective.value(score, &self.distribution);
             child.total_score = score;
+            child.distribution.add(score, objective);
             child.is_terminal = is_terminal;
-            self.child = Some(Single(child));
+            self.child = Some(Single(arena.insert(child)));
             score
         }
     }
 
-    /// Does random moves until `game.ended`
+    /// Recreate the child of a visited edge whose subtree has been recycled,
+    /// keeping the statistics of the edge. The new node has no children yet.
+    fn regrow(&mut self, arena: &mut NodeArena, game: &Game) {
+        if self.mv == Move::Roll {
+            self.child = Some(Multiple(HashMap::with_hasher(BuildHasher)));
+        } else {
+            let mut child = Node::new();
+            child.visits = self.visits;
+            child.total_score = self.distribution.mean * self.visits as f64;
+            child.distribution = self.distribution.clone();
+            child.is_terminal = game.ended;
+            self.child = Some(Single(arena.insert(child)));
+        }
+    }
+
+    /// Does moves picked by the rollout policy in `heuristics.parameters` until `game.ended`,
+    /// or until the rollout cutoff is reached and the leaf evaluator estimates the rest
     /// Returns `(score, depth_zero_is_terminal)`
-    ///
-    /// ### Ideas:
-    /// * Use heuristics instead of random moves
-    /// * Drop rollouts and just use the heuristic to estimate the score
 
     // Never inline, to make CPU profiling easier
     #[inline(never)]
     fn rollout(
         mut game: Game,
         heuristics: &mut Heuristics,
+        start_turn: u8,
         depth: u16,
         rng: &mut dyn RngCore,
+        trail: &mut AmafTrail,
     ) -> (Score, bool) {
         if game.ended {
             return (f64::from(game.board.score()), depth == 0);
         }
 
-        let mv = game
-            .generate_moves()
-            .choose(rng)
-            .copied()
-            .expect("Rollout failed to find a valid move");
+        if let Some(cutoff) = heuristics.parameters.rollout_cutoff {
+            if cutoff.is_reached(&game, start_turn, depth) {
+                let evaluator = heuristics.parameters.leaf_evaluator;
+                return (evaluator.evaluate(&mut game, heuristics), false);
+            }
+        }
 
-        game.do_move(mv);
-        let turn = game.turn;
-        let (score, is_terminal) = Self::rollout(game, heuristics, depth + 1, rng);
+        let moves = game.generate_moves();
+        let policy = heuristics.parameters.rollout_policy;
+        let mv = policy.choose(&game, &moves, heuristics, rng);
 
-        heuristics.update(turn, mv, score);
+        game.do_move(mv);
+        let (score, is_terminal) =
+            Self::rollout(game, heuristics, start_turn, depth + 1, rng, trail);
+        trail.extend(heuristics.parameters.mc_rave, mv);
 
         (score, is_terminal)
     }
@@ -321,8 +453,20 @@ impl Default for Edge {
 pub struct MonteCarloTree {
     game: Game,
     pub root: Edge,
+    pub arena: NodeArena,
     pub heuristics: Heuristics,
     seed: [u8; 8],
+    /// Move picked by the root algorithm, overriding the most visited move
+    root_choice: Option<Move>,
+    book: Option<Arc<book::OpeningBook>>,
+    /// The rest of a line planned ahead by the opening book or the final round solver,
+    /// see `planned_move`
+    planned_line: Vec<Move>,
+    /// Expected final score of the planned line: exact for a solved final round,
+    /// the book's mean score through the last placement of the line otherwise
+    planned_score: Option<Score>,
+    /// Whether the final round solver gave up on the current position
+    unsolved: bool,
 }
 
 impl MonteCarloTree {
@@ -335,8 +479,14 @@ impl MonteCarloTree {
         Self {
             game,
             root,
+            arena: NodeArena::new(),
             heuristics,
             seed,
+            root_choice: None,
+            book: None,
+            planned_line: vec![],
+            planned_score: None,
+            unsolved: false,
         }
     }
 
@@ -348,19 +498,52 @@ impl MonteCarloTree {
         Self {
             game,
             root,
+            arena: NodeArena::new(),
             heuristics,
             seed,
+            root_choice: None,
+            book: None,
+            planned_line: vec![],
+            planned_score: None,
+            unsolved: false,
         }
     }
 
     #[must_use]
-    pub fn new_with_heuristics(game: Game, heuristics: Heuristics) -> Self {
+    pub fn new_with_heuristics(game: Game, mut heuristics: Heuristics) -> Self {
         let seed: [u8; 8] = rand::thread_rng().gen();
+        // Values seen by another tree say nothing about the values of this one
+        heuristics.value_bounds = ValueBounds::default();
         Self {
             game,
             heuristics,
             root: Edge::default(),
+            arena: NodeArena::new(),
             seed,
+            root_choice: None,
+            book: None,
+            planned_line: vec![],
+            planned_score: None,
+            unsolved: false,
+        }
+    }
+
+    /// Limit the number of nodes kept in the tree.
+    /// When the budget is exceeded, the subtrees with the fewest visits are recycled.
+    #[must_use]
+    pub fn with_node_budget(mut self, budget: usize) -> Self {
+        self.arena.budget = Some(budget);
+        self
+    }
+
+    /// The node below `edge` with the most visits
+    fn most_visited_node<'a>(&'a self, edge: &'a Edge) -> Option<&'a Node> {
+        match edge.child.as_ref()? {
+            Single(id) => Some(&self.arena[*id]),
+            Multiple(nodes) => nodes
+                .values()
+                .map(|id| &self.arena[*id])
+                .max_by_key(|node| node.visits),
         }
     }
 
@@ -368,29 +551,12 @@ impl MonteCarloTree {
     pub fn calculate_depth(&self) -> u16 {
         let mut depth = 0;
         let mut edge = &self.root;
-        while let Some(child) = edge.child.as_ref() {
-            match child {
-                Single(node) => {
-                    if node.children.len() == 0 {
-                        break;
-                    }
-                    edge = node.children.iter().max_by_key(|edge| edge.visits).unwrap();
-                    depth += 1;
-                }
-                Multiple(nodes) => {
-                    let node = nodes.values().max_by_key(|node| node.visits);
-
-                    if let Some(node) = node {
-                        if node.children.len() == 0 {
-                            break;
-                        }
-                        edge = node.children.iter().max_by_key(|edge| edge.visits).unwrap();
-                        depth += 1;
-                    } else {
-                        break;
-                    }
-                }
+        while let Some(node) = self.most_visited_node(edge) {
+            if node.children.len() == 0 {
+                break;
             }
+            edge = node.children.iter().max_by_key(|edge| edge.visits).unwrap();
+            depth += 1;
         }
         depth
     }
@@ -399,8 +565,25 @@ impl MonteCarloTree {
     /// This function panics if the move is not possible in this state of the game.
     pub fn progress(mut mcts: Self, mv: Move, game: &mut Game) -> Self {
         game.do_move(mv);
+        mcts.root_choice = None;
+        mcts.unsolved = false;
+        mcts.heuristics.value_bounds = ValueBounds::default();
+        if mcts.planned_line.first() == Some(&mv) {
+            mcts.planned_line.remove(0);
+        } else {
+            mcts.planned_line.clear();
+        }
+        if mcts.planned_line.is_empty() {
+            mcts.planned_score = None;
+        }
         if !mcts.heuristics.tree_reuse {
-            return Self::new_with_heuristics(game.clone(), mcts.heuristics);
+            let budget = mcts.arena.budget;
+            let mut next = Self::new_with_heuristics(game.clone(), mcts.heuristics);
+            next.arena.budget = budget;
+            next.book = mcts.book;
+            next.planned_line = mcts.planned_line;
+            next.planned_score = mcts.planned_score;
+            return next;
         }
 
         if matches!(mv, Move::Roll) {
@@ -415,56 +598,60 @@ impl MonteCarloTree {
             mv
         };
 
-        mcts.root = match mcts.root.child.as_mut() {
+        let arena = &mut mcts.arena;
+        mcts.root = match mcts.root.child.take() {
             None => Edge::default(),
-            Some(child) => match child {
-                Multiple(_) => {
-                    panic!("Could not `progress`. Root edge has child of type Some(Multiple)")
-                }
-                Single(node) => {
-                    // `node` is where we're choosing some action from
-                    let children: Box<[Edge]> = std::mem::take(&mut node.children);
-                    let child_index = children.iter().position(|child| child.mv == move_to_match);
-                    match child_index {
-                        None => Edge::default(),
-                        Some(index) => {
-                            let mut next = Vec::from(children).swap_remove(index);
-                            match next.child.as_mut() {
-                                None => Edge::default(),
-                                Some(child) => match child {
-                                    Single(_) => next,
-                                    Multiple(nodes) => {
-                                        let roll = match mv {
-                                        Move::SetRoll(roll) => roll,
-                                        Move::Roll => {
-                                          let roll = game
-                                            .to_place
-                                            .clone()
-                                            .try_into()
-                                            .unwrap_or_else(|_| panic!("Could not progress. Received Move::Roll, and could not infer roll from game due to incorrect `to_place` length."));
-                                          Roll::new(roll)
-                                        },
-                                        _ => panic!("MCTS cannot progress to a Some(Multiple)-child unless the move is a knowable Roll. Received {:?}", &mv),
-                                      };
-
-                                        match nodes.remove(&roll) {
-                                            None => Edge::default(),
-                                            Some(node) => Edge {
-                                                mv: Move::SetRoll(roll),
-                                                visits: node.visits,
-                                                mean_score: node.visits as f64 / node.total_score,
-                                                child: Some(Single(node)),
-                                                pruned: false,
-                                                heuristic_value: None,
-                                            },
+            Some(Multiple(_)) => {
+                panic!("Could not `progress`. Root edge has child of type Some(Multiple)")
+            }
+            Some(Single(node_id)) => {
+                // `node` is where we're choosing some action from.
+                // The chosen child is moved out, and the rest of `node`,
+                // including every sibling subtree, is discarded in one go.
+                let node = &mut arena[node_id];
+                let child_index = node
+                    .children
+                    .iter()
+                    .position(|child| child.mv == move_to_match);
+                let next = match child_index {
+                    None => Edge::default(),
+                    Some(index) => {
+                        let next = node.children.get_mut(index).unwrap();
+                        match next.child.as_mut() {
+                            None => Edge::default(),
+                            Some(Single(_)) => std::mem::take(next),
+                            Some(Multiple(nodes)) => {
+                                let roll = match mv {
+                                    Move::SetRoll(roll) => roll,
+                                    Move::Roll => {
+                                        game.current_roll().unwrap_or_else(|| panic!("Could not progress. Received Move::Roll, and could not infer roll from game due to incorrect `to_place` length."))
+                                    }
+                                    _ => panic!("MCTS cannot progress to a Some(Multiple)-child unless the move is a knowable Roll. Received {:?}", &mv),
+                                };
+
+                                match nodes.remove(&roll) {
+                                    None => Edge::default(),
+                                    Some(id) => {
+                                        let node = &arena[id];
+                                        let mean_score = node.total_score / node.visits as f64;
+                                        let objective = mcts.heuristics.parameters.objective;
+                                        Edge {
+                                            visits: node.visits,
+                                            mean_score: objective
+                                                .value(mean_score, &node.distribution),
+                                            distribution: node.distribution.clone(),
+                                            child: Some(Single(id)),
+                                            ..Edge::new(Move::SetRoll(roll))
                                         }
                                     }
-                                },
+                                }
                             }
                         }
                     }
-                }
-            },
+                };
+                arena.discard(node_id);
+                next
+            }
         };
 
         mcts
@@ -477,61 +664,161 @@ impl MonteCarloTree {
     /// faster, maybe just revert.
     /// Although, having undo-capabilities would be nice for frontend ...
     pub fn search(&mut self) {
-        self.seed = SplitMix64::from_seed(self.seed).gen();
-        let mut rng = SplitMix64::from_seed(self.seed);
+        self.root_choice = None;
+        let mut rng = self.next_rng();
+        let game = self.simulated_game(&mut rng);
 
         self.root
-            .select(self.game.clone(), &mut self.heuristics, &mut rng);
+            .select(game, &mut self.arena, &mut self.heuristics, &mut rng);
+
+        if self.arena.is_over_budget() {
+            self.collect_garbage();
+        }
     }
 
-    pub fn search_iterations(&mut self, iterations: u64) -> &mut Self {
-        for _ in 0..iterations {
-            self.search();
+    /// Run one iteration of MCTS through the child at `index` of the root node
+    fn search_child(&mut self, index: usize) {
+        let mut rng = self.next_rng();
+        let game = self.simulated_game(&mut rng);
+
+        self.root
+            .select_child(index, game, &mut self.arena, &mut self.heuristics, &mut rng);
+
+        if self.arena.is_over_budget() {
+            self.collect_garbage();
         }
-        self
     }
 
+    /// Advance the seed, and return a generator for the next iteration
+    fn next_rng(&mut self) -> SplitMix64 {
+        self.seed = SplitMix64::from_seed(self.seed).gen();
+        SplitMix64::from_seed(self.seed)
+    }
+
+    /// A copy of the game to search, with its dice rolled by `rng`. The dice of
+    /// `self.game` would roll what the game is going to roll, and an iteration
+    /// must not know that.
+    fn simulated_game(&self, rng: &mut SplitMix64) -> Game {
+        let mut game = self.game.clone();
+        game.set_rng(SplitMix64::from_seed(rng.gen()));
+        game
+    }
+
+    /// Free discarded nodes, and if the tree is still above its node budget,
+    /// recycle the subtrees with the fewest visits until it is comfortably below it
+    pub fn collect_garbage(&mut self) {
+        self.arena.reclaim_discarded();
+
+        if let Some(budget) = self.arena.budget {
+            let target = budget - budget / 4;
+            let mut threshold = 2;
+            while self.arena.len() > target && threshold <= 2 * self.root.visits {
+                self.arena.recycle_below(&mut self.root, threshold);
+                self.arena.reclaim_discarded();
+                threshold *= 2;
+            }
+        }
+    }
+
+    /// Run `iterations` iterations of MCTS, see `search_with_limits`
+    pub fn search_iterations(&mut self, iterations: u64) -> &mut Self {
+        self.search_with_limits(SearchLimits::iterations(iterations))
+    }
+
+    /// Search for `milliseconds`, see `search_with_limits`
     pub fn search_duration(&mut self, milliseconds: u128) -> &mut Self {
-        let start = std::time::Instant::now();
-        while start.elapsed().as_millis() < milliseconds {
-            self.search();
+        self.search_with_limits(SearchLimits::duration(milliseconds))
+    }
+
+    /// The next move of a line planned ahead, if the game is following one: a line of
+    /// the opening book on the first turn, or the solution of the final round on the last.
+    pub fn planned_move(&mut self) -> Option<Move> {
+        if self.planned_line.is_empty() {
+            self.start_book_line();
+            self.start_solved_line();
+        }
+        let mv = *self.planned_line.first()?;
+        if self.game.generate_moves().contains(&mv) {
+            Some(mv)
+        } else {
+            self.planned_line.clear();
+            self.planned_score = None;
+            None
         }
-        self
     }
 
-    /// Return the best move given the current state of search
+    /// Render the current search tree as a Graphviz DOT digraph
+    #[must_use]
+    pub fn to_dot(&self, options: dot::DotOptions) -> String {
+        dot::to_dot(&self.root, &self.arena, options)
+    }
+
+    /// Write the current search tree to a Graphviz DOT file
+    /// # Errors
+    /// Returns an error if the file cannot be created or written to
+    pub fn write_dot(&self, options: dot::DotOptions, path: &str) -> std::io::Result<()> {
+        dot::write_dot(&self.root, &self.arena, options, path)
+    }
+
+    /// The edges below the root, empty if the root has not been expanded
+    #[must_use]
+    pub fn root_children(&self) -> &[Edge] {
+        match self.root.child {
+            Some(Single(node_id)) => &self.arena[node_id].children,
+            _ => &[],
+        }
+    }
+
+    /// Return the best move given the current state of search, picked by
+    /// `Parameters::final_move_selection`. If no move has been searched yet,
+    /// the move with the best heuristic estimate is returned. Nothing is searched,
+    /// see `settle_final_move` for the extra search of `RobustMax`.
     /// # Panics
     /// Panics if no move could be selected from the current game position.
     #[must_use]
     pub fn best_move(&mut self) -> Move {
-        match self.root.child.as_ref() {
-            None => *self
-                .game
-                .generate_moves()
-                .choose(&mut SplitMix64::from_seed(self.seed))
-                .expect("Found no moves"),
-            Some(child) => match child {
-                Multiple(_) => Move::Roll,
-                Single(node) => {
-                    if let Some(child) = node
-                        .children
-                        .iter()
-                        .filter(|edge| edge.visits != 0)
-                        .max_by_key(|edge| edge.visits)
-                    {
-                        child.mv
-                    } else if let Some(mv) = self
-                        .game
-                        .generate_moves()
-                        .choose(&mut SplitMix64::from_seed(self.seed))
-                    {
-                        *mv
-                    } else {
-                        Move::End
-                    }
+        if let Some(mv) = self.planned_move() {
+            return mv;
+        }
+        if let Some(mv) = self.root_choice {
+            return mv;
+        }
+        if let Some(Multiple(_)) = self.root.child {
+            return Move::Roll;
+        }
+
+        let selection = self.heuristics.parameters.final_move_selection;
+        match selection.choose(self.root_children()) {
+            Some(index) => self.root_children()[index].mv,
+            None => self.heuristic_best_move(),
+        }
+    }
+
+    /// With `FinalMoveSelection::RobustMax`, search on until the most visited move
+    /// also has the best mean, for at most `extra_iterations` more iterations.
+    /// Called at the end of `search_with_limits`.
+    pub fn settle_final_move(&mut self) -> &mut Self {
+        if let FinalMoveSelection::RobustMax { extra_iterations } =
+            self.heuristics.parameters.final_move_selection
+        {
+            for _ in 0..extra_iterations {
+                if FinalMoveSelection::is_settled(self.root_children()) {
+                    break;
                 }
-            },
+                self.search();
+            }
         }
+        self
+    }
+
+    /// The legal move with the best heuristic estimate
+    fn heuristic_best_move(&mut self) -> Move {
+        let game = &mut self.game;
+        let heuristics = &mut self.heuristics;
+        game.generate_moves()
+            .into_iter()
+            .ord_subset_max_by_key(|mv| heuristics.get_move_estimation(game, *mv))
+            .unwrap_or(Move::End)
     }
 }
 
@@ -0,0 +1,253 @@
+//! Normalisation of backed-up values for UCT, so the exploration parameters do
+//! not depend on the scale of the scores.
+//!
+//! The search keeps running bounds of the values backed up through the edges,
+//! per turn and over the whole game. The bounds belong to one search tree: they
+//! start empty for every new tree and every move played. With `ScoreNormalization::MinMax`, UCT
+//! maps the blended value of an edge into [0, 1] with those bounds before the
+//! exploration term is added, so `exploration_variables` are in units of the
+//! observed spread of values instead of points. `MeanStd` maps it to standard
+//! deviations from the mean instead.
+//!
+//! Parameters written for raw scores have no `score_normalization`, and keep
+//! searching with raw scores. `Parameters::normalize_units` re-expresses them in
+//! normalised units, see `REFERENCE_SCORE_RANGE`.
+
+use super::heuristics::Heuristics;
+use super::Score;
+use serde::{Deserialize, Serialize};
+
+/// The spread of scores that raw exploration parameters are taken to be tuned
+/// against, when migrating them to normalised units
+pub const REFERENCE_SCORE_RANGE: Score = 90.;
+
+/// Spreads smaller than this, in points, are taken to be this, so a handful of
+/// equal values does not blow up the normalised values
+const MINIMUM_SPREAD: Score = 1.;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "normalization")]
+pub enum ScoreNormalization {
+    /// Raw scores, in points
+    #[default]
+    None,
+    /// `(value - min) / (max - min)`
+    MinMax { per_turn: bool },
+    /// `(value - mean) / standard deviation`
+    MeanStd { per_turn: bool },
+}
+
+/// Running minimum, maximum, mean and variance of a stream of values
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct RunningBounds {
+    pub count: u64,
+    pub min: Score,
+    pub max: Score,
+    pub mean: Score,
+    /// Sum of squared differences from the mean, for Welford's algorithm
+    squared_deviations: Score,
+}
+
+impl Default for RunningBounds {
+    fn default() -> Self {
+        Self {
+            count: 0,
+            min: Score::MAX,
+            max: Score::MIN,
+            mean: 0.,
+            squared_deviations: 0.,
+        }
+    }
+}
+
+impl RunningBounds {
+    pub fn add(&mut self, value: Score) {
+        self.count += 1;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        let delta = value - self.mean;
+        self.mean += delta / self.count as f64;
+        self.squared_deviations += delta * (value - self.mean);
+    }
+
+    #[must_use]
+    pub fn standard_deviation(&self) -> Score {
+        if self.count < 2 {
+            return 0.;
+        }
+        (self.squared_deviations / self.count as f64).sqrt()
+    }
+}
+
+/// Bounds of the values backed up in a search
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct ValueBounds {
+    pub global: RunningBounds,
+    /// Indexed by `turn - 1`
+    pub per_turn: [RunningBounds; 7],
+}
+
+impl ValueBounds {
+    /// Record a value backed up to an edge chosen on `turn`
+    pub fn add(&mut self, turn: u8, value: Score) {
+        self.global.add(value);
+        if let Some(bounds) = self.per_turn.get_mut(usize::from(turn).wrapping_sub(1)) {
+            bounds.add(value);
+        }
+    }
+
+    fn bounds(&self, turn: u8, per_turn: bool) -> &RunningBounds {
+        let bounds = self.per_turn.get(usize::from(turn).wrapping_sub(1));
+        match bounds {
+            Some(bounds) if per_turn && bounds.count > 0 => bounds,
+            _ => &self.global,
+        }
+    }
+
+    /// `value` on the scale of `normalization`
+    #[must_use]
+    pub fn normalize(&self, normalization: ScoreNormalization, turn: u8, value: Score) -> Score {
+        match normalization {
+            ScoreNormalization::None => value,
+            ScoreNormalization::MinMax { per_turn } => {
+                let bounds = self.bounds(turn, per_turn);
+                if bounds.count == 0 {
+                    return value / REFERENCE_SCORE_RANGE;
+                }
+                (value - bounds.min) / (bounds.max - bounds.min).max(MINIMUM_SPREAD)
+            }
+            ScoreNormalization::MeanStd { per_turn } => {
+                let bounds = self.bounds(turn, per_turn);
+                (value - bounds.mean) / bounds.standard_deviation().max(MINIMUM_SPREAD)
+            }
+        }
+    }
+
+    /// A difference of `difference` points on the scale of `normalization`
+    #[must_use]
+    pub fn scale(&self, normalization: ScoreNormalization, turn: u8, difference: Score) -> Score {
+        self.normalize(normalization, turn, difference) - self.normalize(normalization, turn, 0.)
+    }
+}
+
+impl Heuristics {
+    /// Record the value of an edge chosen on `turn`, after a search through it
+    pub fn observe_value(&mut self, turn: u8, value: Score) {
+        if self.parameters.score_normalization != ScoreNormalization::None {
+            self.value_bounds.add(turn, value);
+        }
+    }
+
+    /// `value` of an edge chosen on `turn`, on the scale UCT compares edges on
+    #[must_use]
+    pub fn normalize_value(&self, turn: u8, value: Score) -> Score {
+        let normalization = self.parameters.score_normalization;
+        self.value_bounds.normalize(normalization, turn, value)
+    }
+
+    /// A difference of `difference` points on the scale UCT compares edges on
+    #[must_use]
+    pub fn normalize_difference(&self, turn: u8, difference: Score) -> Score {
+        let normalization = self.parameters.score_normalization;
+        self.value_bounds.scale(normalization, turn, difference)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::game::Game;
+    use crate::mcts::heuristics::Parameters;
+    use crate::mcts::MonteCarloTree;
+
+    #[test]
+    fn test_bounds_map_values_into_unit_interval() {
+        let mut bounds = ValueBounds::default();
+        for value in [30., 50., 70.] {
+            bounds.add(3, value);
+        }
+        bounds.add(4, 100.);
+        let per_turn = ScoreNormalization::MinMax { per_turn: true };
+        let global = ScoreNormalization::MinMax { per_turn: false };
+
+        assert!(bounds.normalize(per_turn, 3, 30.).abs() < 1e-9);
+        assert!((bounds.normalize(per_turn, 3, 50.) - 0.5).abs() < 1e-9);
+        assert!((bounds.normalize(global, 3, 65.) - 0.5).abs() < 1e-9);
+        assert!((bounds.scale(per_turn, 3, 4.) - 0.1).abs() < 1e-9);
+        assert!((bounds.normalize(ScoreNormalization::None, 3, 50.) - 50.).abs() < 1e-9);
+
+        let standard = ScoreNormalization::MeanStd { per_turn: true };
+        let deviation = (800.0f64 / 3.).sqrt();
+        assert!((bounds.normalize(standard, 3, 70.) - 20. / deviation).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_exploration_is_independent_of_score_scale() {
+        // Scaling every score should not change how the normalised values compare
+        let mut small = ValueBounds::default();
+        let mut large = ValueBounds::default();
+        for value in [12., 15., 21., 40.] {
+            small.add(2, value);
+            large.add(2, value * 10.);
+        }
+        let normalization = ScoreNormalization::MinMax { per_turn: true };
+        for value in [12., 18., 40.] {
+            let a = small.normalize(normalization, 2, value);
+            let b = large.normalize(normalization, 2, value * 10.);
+            assert!((a - b).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_migrating_raw_parameters() {
+        let mut parameters = Parameters::from([[1.; 7]; 8]);
+        parameters.exploration_variables = [18.; 7];
+        assert_eq!(parameters.score_normalization, ScoreNormalization::None);
+
+        parameters.normalize_units(90.);
+        assert!(parameters
+            .exploration_variables
+            .iter()
+            .all(|bias| (bias - 0.2).abs() < 1e-9));
+        assert_eq!(
+            parameters.score_normalization,
+            ScoreNormalization::MinMax { per_turn: true }
+        );
+        // Migrating twice does not scale twice
+        parameters.normalize_units(90.);
+        assert!((parameters.exploration_variables[0] - 0.2).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_normalized_search_observes_values() {
+        let seed = [0, 0, 0, 0, 0, 0, 0, 47];
+        let game = Game::new_from_seed(seed);
+        let mut mcts = MonteCarloTree::new_from_seed(game, seed);
+        mcts.heuristics.parameters.score_normalization =
+            ScoreNormalization::MinMax { per_turn: true };
+        mcts.search_iterations(200);
+
+        let bounds = mcts.heuristics.value_bounds;
+        assert!(bounds.global.count >= 200);
+        assert!(bounds.per_turn[0].count > 0);
+        assert!(bounds.global.min <= bounds.global.max);
+    }
+
+    #[test]
+    fn test_bounds_do_not_carry_over() {
+        let seed = [0, 0, 0, 0, 0, 0, 0, 53];
+        let mut game = Game::new_from_seed(seed);
+        let mut mcts = MonteCarloTree::new_from_seed(game.clone(), seed);
+        mcts.heuristics.parameters.score_normalization =
+            ScoreNormalization::MinMax { per_turn: true };
+        mcts.heuristics.tree_reuse = true;
+        let mv = mcts.search_iterations(100).best_move();
+        assert!(mcts.heuristics.value_bounds.global.count > 0);
+
+        let next_game = MonteCarloTree::new_with_heuristics(game.clone(), mcts.heuristics.clone());
+        assert_eq!(next_game.heuristics.value_bounds, ValueBounds::default());
+
+        let mcts = MonteCarloTree::progress(mcts, mv, &mut game);
+        assert_eq!(mcts.heuristics.value_bounds, ValueBounds::default());
+    }
+}
@@ -0,0 +1,405 @@
+//! Saving a search tree to disk, and resuming the search later.
+//!
+//! The file starts with `MAGIC` and the little-endian `FORMAT_VERSION`,
+//! followed by the tree encoded with bincode. Besides the nodes and edges, the
+//! file holds the game with the state of its dice, the seed of the search, and
+//! the heuristics parameters and value bounds of the tree, the opening book and
+//! the planned line, so that a loaded tree searches and plays on exactly as the
+//! saved one would have. Only the nodes reachable from the root are saved,
+//! numbered depth first. A neural network in the heuristics is not saved;
+//! attach it again after loading.
+
+use super::arena::{NodeArena, NodeId};
+use super::book::OpeningBook;
+use super::distribution::ScoreDistribution;
+use super::heuristics::{Heuristics, Parameters};
+use super::normalization::ValueBounds;
+use super::{Edge, MonteCarloTree, Node, Score, SingleOrMultiple};
+use crate::board::placement::Placement;
+use crate::game::mv::Move;
+use crate::game::roll::Roll;
+use crate::game::Game;
+use rand_xoshiro::SplitMix64;
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::prelude::*;
+use std::sync::Arc;
+
+pub const MAGIC: &[u8; 8] = b"RRINKMCT";
+/// Version of the file format. Files of other versions are refused.
+pub const FORMAT_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize)]
+pub(super) enum SavedMove {
+    Place(Placement),
+    SetRoll([u8; 4]),
+    Roll,
+    End,
+}
+
+impl From<Move> for SavedMove {
+    fn from(mv: Move) -> Self {
+        match mv {
+            Move::Place(placement) => Self::Place(placement),
+            Move::SetRoll(roll) => Self::SetRoll(roll.0),
+            Move::Roll => Self::Roll,
+            Move::End => Self::End,
+        }
+    }
+}
+
+impl From<SavedMove> for Move {
+    fn from(mv: SavedMove) -> Self {
+        match mv {
+            SavedMove::Place(placement) => Self::Place(placement),
+            SavedMove::SetRoll(roll) => Self::SetRoll(Roll(roll)),
+            SavedMove::Roll => Self::Roll,
+            SavedMove::End => Self::End,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+enum SavedChild {
+    Single(NodeId),
+    /// In the order the chance node iterated its rolls
+    Multiple(Vec<([u8; 4], NodeId)>),
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedEdge {
+    mv: SavedMove,
+    visits: u64,
+    mean_score: Score,
+    distribution: ScoreDistribution,
+    heuristic_value: Option<Score>,
+    prior: Option<Score>,
+    amaf_visits: u64,
+    amaf_score: Score,
+    child: Option<SavedChild>,
+    pruned: bool,
+    folded: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedNode {
+    visits: u64,
+    total_score: f64,
+    distribution: ScoreDistribution,
+    is_terminal: bool,
+    heuristic: f64,
+    children: Vec<SavedEdge>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedTree {
+    /// As encoded by `Game::encode`
+    game: String,
+    game_ended: bool,
+    game_rng: SplitMix64,
+    seed: [u8; 8],
+    root_choice: Option<SavedMove>,
+    /// As JSON, since the parameters use internally tagged enums
+    parameters: String,
+    value_bounds: ValueBounds,
+    tree_reuse: bool,
+    node_budget: Option<usize>,
+    /// As encoded by `OpeningBook::to_bytes`
+    book: Option<Vec<u8>>,
+    planned_line: Vec<SavedMove>,
+    planned_score: Option<Score>,
+    unsolved: bool,
+    root: SavedEdge,
+    nodes: Vec<SavedNode>,
+}
+
+/// Save `edge` and the nodes below it, appending the nodes to `nodes`
+fn save_edge(edge: &Edge, arena: &NodeArena, nodes: &mut Vec<SavedNode>) -> SavedEdge {
+    let mut save_node = |id: NodeId| {
+        let saved_id = NodeId::try_from(nodes.len()).expect("Too many nodes to save");
+        let node = &arena[id];
+        nodes.push(SavedNode {
+            visits: node.visits,
+            total_score: node.total_score,
+            distribution: node.distribution.clone(),
+            is_terminal: node.is_terminal,
+            heuristic: node.heuristic,
+            children: vec![],
+        });
+        let children = node
+            .children
+            .iter()
+            .map(|child| save_edge(child, arena, nodes))
+            .collect();
+        nodes[saved_id as usize].children = children;
+        saved_id
+    };
+    let child = match &edge.child {
+        None => None,
+        Some(SingleOrMultiple::Single(id)) => Some(SavedChild::Single(save_node(*id))),
+        Some(SingleOrMultiple::Multiple(chance_nodes)) => Some(SavedChild::Multiple(
+            chance_nodes
+                .iter()
+                .map(|(roll, id)| (roll.0, save_node(*id)))
+                .collect(),
+        )),
+    };
+
+    SavedEdge {
+        mv: edge.mv.into(),
+        visits: edge.visits,
+        mean_score: edge.mean_score,
+        distribution: edge.distribution.clone(),
+        heuristic_value: edge.heuristic_value,
+        prior: edge.prior,
+        amaf_visits: edge.amaf_visits,
+        amaf_score: edge.amaf_score,
+        child,
+        pruned: edge.pruned,
+        folded: edge.folded,
+    }
+}
+
+impl From<SavedEdge> for Edge {
+    fn from(edge: SavedEdge) -> Self {
+        let child = edge.child.map(|child| match child {
+            SavedChild::Single(id) => SingleOrMultiple::Single(id),
+            SavedChild::Multiple(chance_nodes) => SingleOrMultiple::Multiple(
+                chance_nodes
+                    .into_iter()
+                    .map(|(roll, id)| (Roll(roll), id))
+                    .collect(),
+            ),
+        });
+        Self {
+            mv: edge.mv.into(),
+            visits: edge.visits,
+            mean_score: edge.mean_score,
+            distribution: edge.distribution,
+            heuristic_value: edge.heuristic_value,
+            prior: edge.prior,
+            amaf_visits: edge.amaf_visits,
+            amaf_score: edge.amaf_score,
+            child,
+            pruned: edge.pruned,
+            folded: edge.folded,
+        }
+    }
+}
+
+impl MonteCarloTree {
+    /// Encode the tree, with everything needed to resume the search
+    /// # Panics
+    /// Panics if the heuristics parameters cannot be encoded
+    #[must_use]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut nodes = vec![];
+        let root = save_edge(&self.root, &self.arena, &mut nodes);
+        let saved = SavedTree {
+            game: self.game.encode(),
+            game_ended: self.game.ended,
+            game_rng: self.game.rng().clone(),
+            seed: self.seed,
+            root_choice: self.root_choice.map(SavedMove::from),
+            parameters: serde_json::to_string(&self.heuristics.parameters)
+                .expect("Heuristics parameters should encode as JSON"),
+            value_bounds: self.heuristics.value_bounds,
+            tree_reuse: self.heuristics.tree_reuse,
+            node_budget: self.arena.budget,
+            book: self.book.as_ref().map(|book| book.to_bytes()),
+            planned_line: self.planned_line.iter().map(|&mv| mv.into()).collect(),
+            planned_score: self.planned_score,
+            unsolved: self.unsolved,
+            root,
+            nodes,
+        };
+
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        let payload = bincode::serde::encode_to_vec(&saved, bincode::config::standard())
+            .expect("Search trees should encode");
+        bytes.extend(payload);
+        bytes
+    }
+
+    /// Decode a tree encoded by `to_bytes`
+    /// # Errors
+    /// Returns an error if `bytes` are not a search tree of the current format version
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        let header_length = MAGIC.len() + 4;
+        if bytes.len() < header_length || &bytes[..MAGIC.len()] != MAGIC {
+            return Err("Error loading search tree: Not a search tree file".to_string());
+        }
+        let mut version = [0; 4];
+        version.copy_from_slice(&bytes[MAGIC.len()..header_length]);
+        let version = u32::from_le_bytes(version);
+        if version != FORMAT_VERSION {
+            return Err(format!(
+                "Error loading search tree: Format version {version} is not supported, expected {FORMAT_VERSION}"
+            ));
+        }
+
+        let (saved, _): (SavedTree, _) =
+            bincode::serde::decode_from_slice(&bytes[header_length..], bincode::config::standard())
+                .map_err(|e| format!("Error loading search tree: {e}"))?;
+
+        let mut game = Game::decode(&saved.game)?;
+        game.ended = saved.game_ended;
+        game.set_rng(saved.game_rng);
+
+        let parameters: Parameters = serde_json::from_str(&saved.parameters)
+            .map_err(|e| format!("Error loading search tree: {e}"))?;
+        let mut heuristics = Heuristics::new(parameters);
+        heuristics.value_bounds = saved.value_bounds;
+        heuristics.tree_reuse = saved.tree_reuse;
+
+        let book = saved
+            .book
+            .map(|bytes| OpeningBook::from_bytes(&bytes).map(Arc::new))
+            .transpose()?;
+
+        let mut arena = NodeArena::new();
+        arena.budget = saved.node_budget;
+        for node in saved.nodes {
+            arena.insert(Node {
+                visits: node.visits,
+                total_score: node.total_score,
+                distribution: node.distribution,
+                is_terminal: node.is_terminal,
+                heuristic: node.heuristic,
+                children: node.children.into_iter().map(Edge::from).collect(),
+            });
+        }
+
+        Ok(Self {
+            game,
+            root: saved.root.into(),
+            arena,
+            heuristics,
+            seed: saved.seed,
+            root_choice: saved.root_choice.map(Move::from),
+            book,
+            planned_line: saved.planned_line.into_iter().map(Move::from).collect(),
+            planned_score: saved.planned_score,
+            unsolved: saved.unsolved,
+        })
+    }
+
+    /// Save the tree to a file at `path`, see `to_bytes`
+    /// # Errors
+    /// Returns an error if the file cannot be created or written to
+    pub fn save(&self, path: &str) -> std::io::Result<()> {
+        let mut file = File::create(path)?;
+        file.write_all(&self.to_bytes())
+    }
+
+    /// Load a tree saved by `save`
+    /// # Errors
+    /// Returns an error if the file cannot be read, or is not a search tree of the current format version
+    pub fn load(path: &str) -> Result<Self, String> {
+        let mut bytes = vec![];
+        File::open(path)
+            .and_then(|mut file| file.read_to_end(&mut bytes))
+            .map_err(|e| format!("Error loading search tree: {e}"))?;
+        Self::from_bytes(&bytes)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mcts::book::{BookEntry, MoveStatistics};
+
+    fn root_statistics(mcts: &MonteCarloTree) -> Vec<(u64, Score)> {
+        mcts.root_children()
+            .iter()
+            .map(|edge| (edge.visits, edge.mean_score))
+            .collect()
+    }
+
+    #[test]
+    fn test_resumed_search_continues_exactly() {
+        let seed = [0, 0, 0, 0, 0, 0, 0, 61];
+        let game = Game::new_from_seed(seed);
+        let mut uninterrupted = MonteCarloTree::new_from_seed(game.clone(), seed);
+        uninterrupted.search_iterations(400);
+
+        let mut saved = MonteCarloTree::new_from_seed(game, seed);
+        saved.search_iterations(200);
+        let mut resumed = MonteCarloTree::from_bytes(&saved.to_bytes()).unwrap();
+        assert_eq!(resumed.arena.len(), saved.arena.len());
+        assert_eq!(root_statistics(&resumed), root_statistics(&saved));
+
+        resumed.search_iterations(200);
+        assert_eq!(resumed.root.visits, uninterrupted.root.visits);
+        assert_eq!(root_statistics(&resumed), root_statistics(&uninterrupted));
+        assert_eq!(resumed.best_move(), uninterrupted.best_move());
+    }
+
+    #[test]
+    fn test_resume_after_progress() {
+        // Chance nodes and a game past its first turn
+        let seed = [0, 0, 0, 0, 0, 0, 0, 67];
+        let mut game = Game::new_from_seed(seed);
+        let mut mcts = MonteCarloTree::new_from_seed(game.clone(), seed);
+        while game.turn < 3 {
+            let mv = mcts.search_iterations(50).best_move();
+            mcts = MonteCarloTree::progress(mcts, mv, &mut game);
+        }
+        mcts.search_iterations(50);
+
+        let mut resumed = MonteCarloTree::from_bytes(&mcts.to_bytes()).unwrap();
+        assert_eq!(resumed.game, mcts.game);
+        resumed.search_iterations(50);
+        mcts.search_iterations(50);
+        assert_eq!(root_statistics(&resumed), root_statistics(&mcts));
+    }
+
+    #[test]
+    fn test_resumed_tree_keeps_its_book_line() {
+        let seed = [0, 0, 0, 0, 0, 0, 0, 73];
+        let mut game = Game::new_from_seed(seed);
+        let mut line_game = game.clone();
+        let mut moves = vec![];
+        while let Some(mv) = line_game
+            .generate_moves()
+            .into_iter()
+            .find(|mv| matches!(mv, Move::Place(_)))
+        {
+            line_game.do_move(mv);
+            moves.push(mv);
+        }
+        let mut book = OpeningBook::new();
+        book.insert(BookEntry {
+            roll: game.current_roll().unwrap(),
+            moves: moves.clone(),
+            iterations: 0,
+            statistics: vec![MoveStatistics::default(); moves.len()],
+        });
+
+        let mut mcts =
+            MonteCarloTree::new_from_seed(game.clone(), seed).with_opening_book(Arc::new(book));
+        let mv = mcts.search_iterations(10).best_move();
+        mcts = MonteCarloTree::progress(mcts, mv, &mut game);
+
+        let mut resumed = MonteCarloTree::from_bytes(&mcts.to_bytes()).unwrap();
+        for &expected in &moves[1..] {
+            let mv = resumed.search_iterations(10).best_move();
+            assert_eq!(mv, expected);
+            assert_eq!(resumed.root.visits, 0);
+            resumed = MonteCarloTree::progress(resumed, mv, &mut game);
+        }
+    }
+
+    #[test]
+    fn test_refuses_other_versions() {
+        let seed = [0, 0, 0, 0, 0, 0, 0, 71];
+        let mut mcts = MonteCarloTree::new_from_seed(Game::new_from_seed(seed), seed);
+        mcts.search_iterations(10);
+        let mut bytes = mcts.to_bytes();
+        bytes[MAGIC.len()] += 1;
+        assert!(MonteCarloTree::from_bytes(&bytes).is_err());
+        assert!(MonteCarloTree::from_bytes(b"not a tree").is_err());
+    }
+}
@@ -0,0 +1,255 @@
+//! A fast planner for whole turns, for play where a tree search is too slow.
+//!
+//! `BeamPlanner` plans the rest of the current turn with a beam search. From
+//! each board in the beam it tries every placement of the pieces left, as
+//! found by `Board::find_possible`, and keeps the `beam_width` best boards. A
+//! board is ranked by its `LeafEvaluator` value plus the
+//! `Heuristics::get_move_estimation` of every placement made this turn, so the
+//! heuristics can steer it away from placements that only pay off now, like
+//! using a special piece early. The best board at the end of the turn is
+//! played.
+//!
+//! With `lookahead_rolls`, the boards at the end of the turn are instead
+//! ranked by how well the next turn can be planned from them, on average over
+//! that many rolls. The rolls are drawn once per plan, and shared by every
+//! board, so the boards are compared on the same dice.
+
+use super::analysis::SearchLimits;
+use super::engine::{Engine, TurnPlan};
+use super::evaluator::{LeafEvaluator, LeafEvaluatorKind};
+use super::heuristics::Heuristics;
+use super::Score;
+use crate::board::placement::Placement;
+use crate::game::mv::Move;
+use crate::game::roll::Roll;
+use crate::game::Game;
+use rand::SeedableRng;
+use rand_xoshiro::SplitMix64;
+use std::collections::HashSet;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PlannerOptions {
+    /// Number of boards kept after every placement
+    pub beam_width: usize,
+    /// Number of rolls of the next turn to rank the boards at the end of the turn by.
+    /// With none, they are ranked like the boards within the turn.
+    pub lookahead_rolls: usize,
+    pub evaluator: LeafEvaluatorKind,
+}
+
+impl Default for PlannerOptions {
+    fn default() -> Self {
+        Self {
+            beam_width: 8,
+            lookahead_rolls: 0,
+            evaluator: LeafEvaluatorKind::default(),
+        }
+    }
+}
+
+/// A board in the beam, the moves that led to it this turn, and their heuristic estimations
+#[derive(Clone)]
+struct Plan {
+    game: Game,
+    moves: Vec<Move>,
+    estimation: f64,
+}
+
+pub struct BeamPlanner {
+    pub options: PlannerOptions,
+    pub heuristics: Heuristics,
+    /// Draws the rolls to look ahead with
+    rng: SplitMix64,
+    plan: TurnPlan,
+}
+
+/// The placements from `game`: of the pieces left to place, and of the special pieces
+/// if one may be placed
+fn placements(game: &Game) -> (Vec<Placement>, Vec<Placement>) {
+    let mut pieces = game.to_place.clone();
+    pieces.sort_unstable();
+    pieces.dedup();
+    let dice = pieces
+        .into_iter()
+        .flat_map(|piece| game.board.find_possible(piece))
+        .collect();
+    let specials = if game.can_play_specials() {
+        (0x0a..=0x0f)
+            .filter(|piece| !game.expended_specials.contains(&Some(*piece)))
+            .flat_map(|piece| game.board.find_possible(piece))
+            .collect()
+    } else {
+        vec![]
+    };
+    (dice, specials)
+}
+
+impl BeamPlanner {
+    /// # Panics
+    /// Panics if `options.beam_width` is zero
+    #[must_use]
+    pub fn new(heuristics: Heuristics, options: PlannerOptions, seed: [u8; 8]) -> Self {
+        assert!(options.beam_width > 0, "The beam should keep some boards");
+        Self {
+            options,
+            heuristics,
+            rng: SplitMix64::from_seed(seed),
+            plan: TurnPlan::default(),
+        }
+    }
+
+    fn rank(&mut self, plan: &Plan) -> Score {
+        let evaluator = self.options.evaluator;
+        let value = if plan.game.ended {
+            f64::from(plan.game.board.score())
+        } else {
+            evaluator.evaluate(&mut plan.game.clone(), &mut self.heuristics)
+        };
+        value + plan.estimation
+    }
+
+    /// The `beam_width` best of `plans`, dropping plans that reach the same board
+    fn best_plans(&mut self, plans: Vec<Plan>) -> Vec<Plan> {
+        let mut boards = HashSet::new();
+        let mut ranked = plans
+            .into_iter()
+            .filter(|plan| boards.insert(plan.game.encode()))
+            .map(|plan| (self.rank(&plan), plan))
+            .collect::<Vec<_>>();
+        ranked.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+        ranked
+            .into_iter()
+            .take(self.options.beam_width)
+            .map(|(_, plan)| plan)
+            .collect()
+    }
+
+    /// The best boards at the end of the turn from `game`, best first
+    fn finish_turn(&mut self, game: &Game) -> Vec<Plan> {
+        let mut beam = vec![Plan {
+            game: game.clone(),
+            moves: vec![],
+            estimation: 0.0,
+        }];
+        let mut finished = vec![];
+        while !beam.is_empty() {
+            let mut placed = vec![];
+            for plan in beam {
+                let (dice, specials) = placements(&plan.game);
+                let turn_over = dice.is_empty();
+                for placement in dice.into_iter().chain(specials) {
+                    let mv = Move::Place(placement);
+                    let mut next = plan.clone();
+                    next.estimation += self.heuristics.get_move_estimation(&plan.game, mv);
+                    next.game.do_move(mv);
+                    next.moves.push(mv);
+                    placed.push(next);
+                }
+                if turn_over {
+                    finished.push(plan);
+                }
+            }
+            beam = self.best_plans(placed);
+        }
+        self.best_plans(finished)
+    }
+
+    /// Rolls for the next turn, drawn by their probabilities
+    fn sample_rolls(&mut self) -> Vec<Roll> {
+        (0..self.options.lookahead_rolls)
+            .map(|_| Roll::sample(&mut self.rng))
+            .collect()
+    }
+
+    /// The moves of the best plan for the rest of the turn from `game`
+    /// # Panics
+    /// Panics if no plan reaches the end of the turn
+    pub fn plan_turn(&mut self, game: &Game) -> Vec<Move> {
+        let finished = self.finish_turn(game);
+        if self.options.lookahead_rolls == 0 || game.turn >= 7 {
+            return finished
+                .into_iter()
+                .next()
+                .expect("Some board should reach the end of the turn")
+                .moves;
+        }
+
+        let rolls = self.sample_rolls();
+        let mut best: Option<(Score, Vec<Move>)> = None;
+        for plan in finished {
+            let mut total = 0.0;
+            for &roll in &rolls {
+                let mut next = plan.game.clone();
+                next.do_move(Move::SetRoll(roll));
+                let next_best = self.finish_turn(&next).into_iter().next();
+                total += next_best.map_or(0.0, |next_best| self.rank(&next_best));
+            }
+            let value = plan.estimation + total / rolls.len() as f64;
+            if best.as_ref().is_none_or(|(best, _)| value > *best) {
+                best = Some((value, plan.moves));
+            }
+        }
+        best.expect("Some board should reach the end of the turn").1
+    }
+}
+
+impl Engine for BeamPlanner {
+    /// Plays the best plan for the turn, regardless of `limits`
+    fn choose_move(&mut self, game: &Game, _limits: &SearchLimits) -> Move {
+        if let Some(mv) = self.plan.next(game) {
+            return mv;
+        }
+        let moves = self.plan_turn(game);
+        self.plan.start(game, moves)
+    }
+
+    fn observe(&mut self, game: &Game, mv: Move) {
+        self.plan.observe(game, mv);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mcts::engine::play_game;
+
+    #[test]
+    fn test_plan_finishes_the_turn() {
+        let seed = [0, 0, 0, 0, 0, 0, 0, 127];
+        let mut game = Game::new_from_seed(seed);
+        let mut planner = BeamPlanner::new(Heuristics::default(), PlannerOptions::default(), seed);
+        let moves = planner.plan_turn(&game);
+        assert!(moves.len() >= 4);
+        for mv in moves {
+            assert!(game.generate_moves().contains(&mv));
+            game.do_move(mv);
+        }
+        assert_eq!(game.generate_moves()[0], Move::Roll);
+        // The default heuristics make an early special piece too costly
+        assert_eq!(game.special_placed, None);
+    }
+
+    #[test]
+    fn test_plays_with_lookahead_deterministically() {
+        let seed = [0, 0, 0, 0, 0, 0, 0, 131];
+        let options = PlannerOptions {
+            beam_width: 2,
+            lookahead_rolls: 1,
+            ..PlannerOptions::default()
+        };
+        let scores = [0, 1].map(|_| {
+            let mut game = Game::new_from_seed(seed);
+            let mut planner = BeamPlanner::new(Heuristics::default(), options, seed);
+            play_game(
+                &mut planner,
+                &mut game,
+                &SearchLimits::default(),
+                |_, game, mv| {
+                    assert!(game.clone().generate_moves().contains(&mv));
+                },
+            );
+            game.board.score()
+        });
+        assert_eq!(scores[0], scores[1]);
+    }
+}
@@ -0,0 +1,119 @@
+//! Progressive pruning and unpruning of the children of a node.
+//!
+//! With `Parameters::pruning`, only the children with the best heuristic
+//! estimates are considered when selecting. The number of active children is
+//! `max(prune_minimum_node_count, prune_alpha * ln(parent visits), 1)`, so pruned
+//! edges rejoin the search, best estimate first, as the parent is visited more.
+//! Pruned edges keep their statistics while they are out. Edges folded onto a
+//! mirror image by the opening book stay out, and do not count as children.
+
+use super::heuristics::{Heuristics, Parameters};
+use super::Edge;
+use crate::game::Game;
+
+/// Number of children to consider after `parent_visits` visits to their parent.
+/// At least one, so a node with children always has one to search.
+#[must_use]
+#[allow(clippy::cast_sign_loss)] // The count is at least the minimum, which is never negative
+pub fn active_children(parameters: &Parameters, parent_visits: u64, children: usize) -> usize {
+    let minimum = f64::from(parameters.prune_minimum_node_count);
+    let active = (parameters.prune_alpha * (parent_visits.max(1) as f64).ln())
+        .max(minimum)
+        .ceil() as usize;
+    active.max(1).min(children)
+}
+
+/// Mark every child but the active ones with the best heuristic estimates as pruned
+pub fn prune_children(
+    children: &mut [Edge],
+    parent_visits: u64,
+    game: &Game,
+    heuristics: &mut Heuristics,
+) {
+    let unfolded = children.iter().filter(|edge| !edge.folded).count();
+    let active = active_children(&heuristics.parameters, parent_visits, unfolded);
+    let unpruned = children
+        .iter()
+        .filter(|edge| !edge.folded && !edge.pruned)
+        .count();
+    if unpruned == active {
+        return;
+    }
+
+    for edge in children.iter_mut().filter(|edge| !edge.folded) {
+        if edge.heuristic_value.is_none() {
+            edge.heuristic_value = Some(heuristics.get_move_estimation(game, edge.mv));
+        }
+    }
+
+    let mut order = (0..children.len())
+        .filter(|&i| !children[i].folded)
+        .collect::<Vec<_>>();
+    order.sort_by(|&a, &b| {
+        let estimate = |i: usize| children[i].heuristic_value.unwrap_or_default();
+        estimate(b).total_cmp(&estimate(a))
+    });
+    for (rank, i) in order.into_iter().enumerate() {
+        children[i].pruned = rank >= active;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mcts::{MonteCarloTree, Single};
+
+    #[test]
+    fn test_active_children_grow_with_visits() {
+        let mut parameters = Heuristics::default().parameters;
+        parameters.prune_minimum_node_count = 3;
+        parameters.prune_alpha = 2.;
+
+        assert_eq!(active_children(&parameters, 1, 50), 3);
+        assert_eq!(active_children(&parameters, 100, 50), 10);
+        assert_eq!(active_children(&parameters, 100_000, 50), 24);
+        assert_eq!(active_children(&parameters, 100_000, 5), 5);
+
+        // Never prunes every child
+        parameters.prune_minimum_node_count = 0;
+        parameters.prune_alpha = 0.;
+        assert_eq!(active_children(&parameters, 1, 50), 1);
+        assert_eq!(active_children(&parameters, 100, 50), 1);
+        assert_eq!(active_children(&parameters, 100, 0), 0);
+    }
+
+    fn pruned_search(pruning: bool, iterations: u64) -> (usize, usize) {
+        let seed = [0, 0, 0, 0, 0, 0, 0, 31];
+        let game = Game::new_from_seed(seed);
+        let mut mcts = MonteCarloTree::new_from_seed(game, seed);
+        mcts.heuristics.parameters.pruning = pruning;
+        mcts.heuristics.parameters.prune_minimum_node_count = 2;
+        mcts.heuristics.parameters.prune_alpha = 1.;
+        mcts.search_iterations(iterations);
+
+        let Some(Single(node_id)) = mcts.root.child else {
+            panic!("Root was not expanded")
+        };
+        let children = &mcts.arena[node_id].children;
+        let visited = children.iter().filter(|edge| edge.visits > 0).count();
+        let pruned = children.iter().filter(|edge| edge.pruned).count();
+        (visited, pruned)
+    }
+
+    #[test]
+    fn test_pruned_edges_rejoin() {
+        let (few_visited, few_pruned) = pruned_search(true, 20);
+        let (many_visited, many_pruned) = pruned_search(true, 2000);
+        // ln(20) rounds up to 3 active children, and the one visited while expanding the root
+        assert!(few_visited <= 4);
+        assert!(many_visited > few_visited);
+        assert!(many_pruned < few_pruned);
+    }
+
+    #[test]
+    fn test_no_pruning_when_disabled() {
+        let (visited, pruned) = pruned_search(false, 200);
+        assert_eq!(pruned, 0);
+        assert!(visited > 4);
+    }
+}
@@ -0,0 +1,310 @@
+//! Policies for picking moves during rollouts.
+//!
+//! All randomness comes from the `rng` passed in by the search, so rollouts stay
+//! deterministic under a seeded `SplitMix64`.
+
+use super::heuristics::Heuristics;
+use crate::game::mv::Move;
+use crate::game::Game;
+use rand::seq::SliceRandom;
+use rand::{Rng, RngCore};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::str::FromStr;
+
+pub trait RolloutPolicy {
+    /// Pick the next move of a rollout from the legal `moves`, which must not be empty
+    fn choose(
+        &self,
+        game: &Game,
+        moves: &[Move],
+        heuristics: &mut Heuristics,
+        rng: &mut dyn RngCore,
+    ) -> Move;
+}
+
+/// Uniformly random moves
+pub struct Random;
+
+/// The move with the best `Heuristics::get_move_estimation`
+pub struct Greedy;
+
+/// A random move with probability `epsilon`, otherwise the greedy move
+pub struct EpsilonGreedy {
+    pub epsilon: f64,
+}
+
+/// A move sampled with probability proportional to `exp(estimation / temperature)`
+pub struct Softmax {
+    pub temperature: f64,
+}
+
+impl RolloutPolicy for Random {
+    fn choose(
+        &self,
+        _game: &Game,
+        moves: &[Move],
+        _heuristics: &mut Heuristics,
+        rng: &mut dyn RngCore,
+    ) -> Move {
+        *moves
+            .choose(rng)
+            .expect("Rollout failed to find a valid move")
+    }
+}
+
+impl RolloutPolicy for Greedy {
+    fn choose(
+        &self,
+        game: &Game,
+        moves: &[Move],
+        heuristics: &mut Heuristics,
+        _rng: &mut dyn RngCore,
+    ) -> Move {
+        heuristics
+            .select_rollout_move(game, moves.to_vec())
+            .expect("Rollout failed to find a valid move")
+    }
+}
+
+impl RolloutPolicy for EpsilonGreedy {
+    fn choose(
+        &self,
+        game: &Game,
+        moves: &[Move],
+        heuristics: &mut Heuristics,
+        rng: &mut dyn RngCore,
+    ) -> Move {
+        if rng.gen::<f64>() < self.epsilon {
+            Random.choose(game, moves, heuristics, rng)
+        } else {
+            Greedy.choose(game, moves, heuristics, rng)
+        }
+    }
+}
+
+impl RolloutPolicy for Softmax {
+    fn choose(
+        &self,
+        game: &Game,
+        moves: &[Move],
+        heuristics: &mut Heuristics,
+        rng: &mut dyn RngCore,
+    ) -> Move {
+        let values = moves
+            .iter()
+            .map(|mv| heuristics.get_rollout_policy_value(game, *mv))
+            .collect::<Vec<_>>();
+        let max = values.iter().copied().fold(f64::MIN, f64::max);
+        let weights = values
+            .iter()
+            .map(|value| ((value - max) / self.temperature).exp())
+            .collect::<Vec<_>>();
+
+        let mut target = rng.gen::<f64>() * weights.iter().sum::<f64>();
+        for (mv, weight) in moves.iter().zip(&weights) {
+            if target < *weight {
+                return *mv;
+            }
+            target -= weight;
+        }
+        *moves.last().expect("Rollout failed to find a valid move")
+    }
+}
+
+/// The rollout policy to use, as stored in `Parameters`
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "policy")]
+pub enum RolloutPolicyKind {
+    #[default]
+    Random,
+    Greedy,
+    EpsilonGreedy {
+        #[serde(deserialize_with = "deserialize_epsilon")]
+        epsilon: f64,
+    },
+    Softmax {
+        #[serde(deserialize_with = "deserialize_temperature")]
+        temperature: f64,
+    },
+}
+
+/// `temperature`, if it is a positive number. Softmax weights are undefined for
+/// any other temperature.
+/// # Errors
+/// Returns an error if `temperature` is zero, negative or not finite
+pub fn positive_temperature(temperature: f64) -> Result<f64, String> {
+    if temperature > 0. && temperature.is_finite() {
+        Ok(temperature)
+    } else {
+        Err(format!(
+            "The temperature should be a positive number, not {temperature}"
+        ))
+    }
+}
+
+/// Deserialize a temperature that `positive_temperature` accepts
+pub(crate) fn deserialize_temperature<'de, D>(deserializer: D) -> Result<f64, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    positive_temperature(f64::deserialize(deserializer)?).map_err(serde::de::Error::custom)
+}
+
+/// `epsilon`, if it is a probability, between 0 and 1
+/// # Errors
+/// Returns an error if `epsilon` is below 0, above 1 or not a number
+pub fn probability_epsilon(epsilon: f64) -> Result<f64, String> {
+    if (0.0..=1.0).contains(&epsilon) {
+        Ok(epsilon)
+    } else {
+        Err(format!(
+            "Epsilon should be a probability between 0 and 1, not {epsilon}"
+        ))
+    }
+}
+
+/// Deserialize an epsilon that `probability_epsilon` accepts
+fn deserialize_epsilon<'de, D>(deserializer: D) -> Result<f64, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    probability_epsilon(f64::deserialize(deserializer)?).map_err(serde::de::Error::custom)
+}
+
+impl RolloutPolicy for RolloutPolicyKind {
+    fn choose(
+        &self,
+        game: &Game,
+        moves: &[Move],
+        heuristics: &mut Heuristics,
+        rng: &mut dyn RngCore,
+    ) -> Move {
+        match *self {
+            Self::Random => Random.choose(game, moves, heuristics, rng),
+            Self::Greedy => Greedy.choose(game, moves, heuristics, rng),
+            Self::EpsilonGreedy { epsilon } => {
+                EpsilonGreedy { epsilon }.choose(game, moves, heuristics, rng)
+            }
+            Self::Softmax { temperature } => {
+                Softmax { temperature }.choose(game, moves, heuristics, rng)
+            }
+        }
+    }
+}
+
+/// Parses `random`, `greedy`, `epsilon-greedy:<epsilon>` and `softmax:<temperature>`
+impl FromStr for RolloutPolicyKind {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let (name, argument) = match input.split_once(':') {
+            Some((name, argument)) => (name, Some(argument)),
+            None => (input, None),
+        };
+        let argument = |default: f64| -> Result<f64, String> {
+            argument.map_or(Ok(default), |argument| {
+                argument
+                    .parse()
+                    .map_err(|_| format!("Could not parse rollout policy argument: {argument}"))
+            })
+        };
+
+        match name {
+            "random" => Ok(Self::Random),
+            "greedy" => Ok(Self::Greedy),
+            "epsilon-greedy" => Ok(Self::EpsilonGreedy {
+                epsilon: probability_epsilon(argument(0.1)?)?,
+            }),
+            "softmax" => Ok(Self::Softmax {
+                temperature: positive_temperature(argument(1.0)?)?,
+            }),
+            _ => Err(format!("Unknown rollout policy: {input}")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mcts::MonteCarloTree;
+    use rand::SeedableRng;
+    use rand_xoshiro::SplitMix64;
+
+    #[test]
+    fn test_parse_rollout_policy() {
+        assert_eq!(
+            RolloutPolicyKind::from_str("random"),
+            Ok(RolloutPolicyKind::Random)
+        );
+        assert_eq!(
+            RolloutPolicyKind::from_str("epsilon-greedy:0.25"),
+            Ok(RolloutPolicyKind::EpsilonGreedy { epsilon: 0.25 })
+        );
+        assert_eq!(
+            RolloutPolicyKind::from_str("softmax"),
+            Ok(RolloutPolicyKind::Softmax { temperature: 1.0 })
+        );
+        assert!(RolloutPolicyKind::from_str("softmax:hot").is_err());
+        assert!(RolloutPolicyKind::from_str("softmax:0").is_err());
+        assert!(RolloutPolicyKind::from_str("softmax:-1").is_err());
+        assert!(serde_json::from_str::<RolloutPolicyKind>(
+            r#"{"policy": "softmax", "temperature": 0.0}"#
+        )
+        .is_err());
+        let warm = serde_json::from_str(r#"{"policy": "softmax", "temperature": 0.5}"#);
+        assert_eq!(
+            warm.ok(),
+            Some(RolloutPolicyKind::Softmax { temperature: 0.5 })
+        );
+        assert!(RolloutPolicyKind::from_str("epsilon-greedy:1.5").is_err());
+        assert!(RolloutPolicyKind::from_str("epsilon-greedy:-0.1").is_err());
+        assert!(RolloutPolicyKind::from_str("epsilon-greedy:NaN").is_err());
+        assert!(serde_json::from_str::<RolloutPolicyKind>(
+            r#"{"policy": "epsilon_greedy", "epsilon": 2.0}"#
+        )
+        .is_err());
+        let half = serde_json::from_str(r#"{"policy": "epsilon_greedy", "epsilon": 0.5}"#);
+        assert_eq!(
+            half.ok(),
+            Some(RolloutPolicyKind::EpsilonGreedy { epsilon: 0.5 })
+        );
+        assert!(RolloutPolicyKind::from_str("best").is_err());
+    }
+
+    #[test]
+    fn test_cold_policies_are_greedy() {
+        let mut game = Game::new_from_seed([5; 8]);
+        let moves = game.generate_moves();
+        let mut heuristics = Heuristics::default();
+        let mut rng = SplitMix64::from_seed([5; 8]);
+
+        let greedy = Greedy.choose(&game, &moves, &mut heuristics, &mut rng);
+        let epsilon =
+            EpsilonGreedy { epsilon: 0. }.choose(&game, &moves, &mut heuristics, &mut rng);
+        assert_eq!(greedy, epsilon);
+
+        let best = heuristics.get_move_estimation(&game, greedy);
+        let softmax =
+            Softmax { temperature: 1e-9 }.choose(&game, &moves, &mut heuristics, &mut rng);
+        assert!((heuristics.get_move_estimation(&game, softmax) - best).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_seeded_rollout_policies_are_deterministic() {
+        let policies = [
+            RolloutPolicyKind::EpsilonGreedy { epsilon: 0.5 },
+            RolloutPolicyKind::Softmax { temperature: 2.0 },
+        ];
+        for policy in policies {
+            let run = || {
+                let seed = [0, 0, 0, 0, 0, 0, 0, 11];
+                let game = Game::new_from_seed(seed);
+                let mut mcts = MonteCarloTree::new_from_seed(game, seed);
+                mcts.heuristics.parameters.rollout_policy = policy;
+                mcts.search_iterations(30);
+                (mcts.best_move(), mcts.root.mean_score)
+            };
+            assert_eq!(run(), run());
+        }
+    }
+}
@@ -0,0 +1,265 @@
+//! Scaling curves: how the score of an engine grows with its search budget.
+//!
+//! Every configuration plays the same games, with seeds `seed + i`, at every
+//! point of a grid of search budgets and numbers of games played in parallel,
+//! so the points of a curve are paired like the entrants of a tournament. Every
+//! search runs on a single thread; the games of a point are played in parallel
+//! on a pool of `parallel_games` threads, which shows what sharing the machine
+//! costs each search.
+//!
+//! Every game becomes a row of a tidy CSV, with the breakdown of its score and
+//! the iterations per second the engine reached, and a record of its moves
+//! that the row refers to by id.
+
+use super::analysis::SearchLimits;
+use super::engine::GameRecord;
+use super::tournament::{Entrant, SharedHeuristics, TournamentConfig};
+use rayon::prelude::*;
+use serde::Serialize;
+use std::fs::File;
+use std::io::prelude::*;
+
+/// A search budget per move
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Budget {
+    Iterations(u64),
+    Milliseconds(u128),
+}
+
+impl Budget {
+    #[must_use]
+    pub fn limits(self) -> SearchLimits {
+        match self {
+            Self::Iterations(iterations) => SearchLimits::iterations(iterations),
+            Self::Milliseconds(milliseconds) => SearchLimits::duration(milliseconds),
+        }
+    }
+
+    /// Name of the limit, for the CSV
+    #[must_use]
+    pub const fn kind(self) -> &'static str {
+        match self {
+            Self::Iterations(_) => "iterations",
+            Self::Milliseconds(_) => "milliseconds",
+        }
+    }
+
+    #[must_use]
+    pub const fn value(self) -> u128 {
+        match self {
+            Self::Iterations(iterations) => iterations as u128,
+            Self::Milliseconds(milliseconds) => milliseconds,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScalingOptions {
+    pub budgets: Vec<Budget>,
+    /// Numbers of games to play at once, each on a thread of its own
+    pub parallel_games: Vec<usize>,
+    /// Games per point
+    pub games: u64,
+    /// Seed of the first game. Game `i` of every point is played with seed `seed + i`
+    pub seed: u64,
+}
+
+/// A game played at a point of the grid
+#[derive(Debug, Clone, Serialize)]
+pub struct ScalingGame {
+    /// Name of the configuration
+    pub config: String,
+    /// Number of games played at once with this one
+    pub parallel_games: usize,
+    pub budget: Budget,
+    pub seed: u64,
+    pub record: GameRecord,
+}
+
+pub struct Scaling {
+    /// The configurations to sweep. Their own `limits` are replaced by the budgets swept.
+    pub configs: Vec<Entrant>,
+    heuristics: Vec<SharedHeuristics>,
+    /// Every game played, its index being its record id
+    pub games: Vec<ScalingGame>,
+}
+
+impl Scaling {
+    /// # Errors
+    /// Returns an error if there are no configurations, or the heuristics or model of one cannot be loaded
+    pub fn new(config: TournamentConfig) -> Result<Self, String> {
+        if config.entrants.is_empty() {
+            return Err("A scaling run needs a configuration to sweep".to_string());
+        }
+        let heuristics = config
+            .entrants
+            .iter()
+            .map(Entrant::heuristics)
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            configs: config.entrants,
+            heuristics,
+            games: vec![],
+        })
+    }
+
+    /// Play every point of the grid, calling `on_point` with the games of each point
+    /// # Errors
+    /// Returns an error if a thread pool cannot be built
+    pub fn run(
+        &mut self,
+        options: &ScalingOptions,
+        mut on_point: impl FnMut(&[ScalingGame]),
+    ) -> Result<(), String> {
+        for &parallel_games in &options.parallel_games {
+            let pool = rayon::ThreadPoolBuilder::new()
+                .num_threads(parallel_games)
+                .build()
+                .map_err(|e| format!("Could not build a pool of {parallel_games} threads: {e}"))?;
+            let parallel_games = pool.current_num_threads();
+            for (config, heuristics) in self.configs.iter().zip(&self.heuristics) {
+                for &budget in &options.budgets {
+                    let limits = budget.limits();
+                    let records = pool.install(|| {
+                        (0..options.games)
+                            .into_par_iter()
+                            .map(|game| {
+                                let seed = options.seed.wrapping_add(game);
+                                (seed, config.play(heuristics, seed, &limits))
+                            })
+                            .collect::<Vec<_>>()
+                    });
+                    let start = self.games.len();
+                    self.games
+                        .extend(records.into_iter().map(|(seed, record)| ScalingGame {
+                            config: config.name.clone(),
+                            parallel_games,
+                            budget,
+                            seed,
+                            record,
+                        }));
+                    on_point(&self.games[start..]);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Write every game as a row of CSV
+    /// # Errors
+    /// Returns an error if `writer` cannot be written to
+    pub fn write_csv(&self, mut writer: impl Write) -> std::io::Result<()> {
+        writeln!(
+            writer,
+            "record_id,config,parallel_games,limit_kind,limit,seed,score,networks,longest_road,\
+             longest_rail,center,open_ends,iterations,seconds,iterations_per_second"
+        )?;
+        for (id, game) in self.games.iter().enumerate() {
+            let record = &game.record;
+            let score = record.score;
+            writeln!(
+                writer,
+                "{id},{},{},{},{},{},{},{},{},{},{},{},{},{:.3},{:.1}",
+                game.config,
+                game.parallel_games,
+                game.budget.kind(),
+                game.budget.value(),
+                game.seed,
+                score.total(),
+                score.networks,
+                score.longest_road,
+                score.longest_rail,
+                score.center,
+                score.open_ends,
+                record.iterations,
+                record.seconds,
+                record.iterations_per_second(),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Write the moves of every game to a file, as a JSON object per line with the record id
+    /// # Errors
+    /// Returns an error if the file cannot be created or written to
+    pub fn write_records(&self, path: &str) -> std::io::Result<()> {
+        #[derive(Serialize)]
+        struct Line<'a> {
+            id: usize,
+            #[serde(flatten)]
+            game: &'a ScalingGame,
+        }
+
+        let mut file = std::io::BufWriter::new(File::create(path)?);
+        for (id, game) in self.games.iter().enumerate() {
+            serde_json::to_writer(&mut file, &Line { id, game })?;
+            writeln!(file)?;
+        }
+        file.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mcts::engine::EngineKind;
+
+    fn config(name: &str, engine: EngineKind) -> Entrant {
+        Entrant {
+            name: name.to_string(),
+            engine,
+            heuristics: None,
+            model: None,
+            limits: SearchLimits::default(),
+        }
+    }
+
+    #[test]
+    fn test_points_are_played_on_the_same_seeds() {
+        let mut scaling = Scaling::new(TournamentConfig {
+            entrants: vec![
+                config("greedy", EngineKind::GreedyScore),
+                config("random", EngineKind::Random),
+            ],
+        })
+        .unwrap();
+        let options = ScalingOptions {
+            budgets: vec![Budget::Iterations(1), Budget::Iterations(3)],
+            parallel_games: vec![1, 2],
+            games: 2,
+            seed: 11,
+        };
+        let mut points = 0;
+        scaling
+            .run(&options, |games| {
+                points += 1;
+                let seeds = games.iter().map(|game| game.seed).collect::<Vec<_>>();
+                assert_eq!(seeds, [11, 12]);
+            })
+            .unwrap();
+        assert_eq!(points, 8);
+        assert_eq!(scaling.games.len(), 16);
+
+        // The baseline ignores the budget, so it scores the same at every point
+        let greedy = scaling
+            .games
+            .iter()
+            .filter(|game| game.config == "greedy" && game.seed == 11)
+            .map(|game| game.record.score)
+            .collect::<Vec<_>>();
+        assert_eq!(greedy.len(), 4);
+        assert!(greedy.iter().all(|&score| score == greedy[0]));
+        assert!(scaling.games.iter().all(|game| game.record.iterations == 0));
+
+        let mut csv = vec![];
+        scaling.write_csv(&mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert_eq!(csv.lines().count(), 17);
+        assert!(csv
+            .lines()
+            .nth(1)
+            .unwrap()
+            .starts_with("0,greedy,1,iterations,1,11,"));
+    }
+}
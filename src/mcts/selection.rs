@@ -0,0 +1,337 @@
+//! Policies for picking which child edge to search next.
+//!
+//! `Edge::select` asks the policy in `Parameters::selection_policy` for the value
+//! of every child, and searches the child with the highest value.
+
+use super::heuristics::Heuristics;
+use super::rollout::deserialize_temperature;
+use super::{Edge, Score};
+use crate::game::Game;
+use rand::{Rng, RngCore};
+use serde::{Deserialize, Serialize};
+
+pub trait SelectionPolicy {
+    /// Called before the children of a node are valued.
+    /// Policies that need to compare siblings, like priors, set them up here.
+    fn prepare(&self, _children: &mut [Edge], _game: &Game, _heuristics: &mut Heuristics) {}
+
+    /// Value of searching `edge` next. `parent` is the edge leading to the node `edge` belongs to.
+    /// `edge.heuristic_value` is set before this is called.
+    fn value(
+        &self,
+        edge: &Edge,
+        parent: &Edge,
+        game: &Game,
+        heuristics: &Heuristics,
+        rng: &mut dyn RngCore,
+    ) -> Score;
+}
+
+/// The mean blended with the heuristic estimate, plus `exploration_bias * sqrt(ln(parent/visits))`.
+/// See `Heuristics::get_exploration_value_given_heuristic`. With `Parameters::mc_rave`,
+/// the AMAF mean is blended in instead of the heuristic estimate, see `amaf`.
+/// The blended value is normalised per `Parameters::score_normalization`, see `normalization`.
+pub struct Uct;
+
+/// UCB1 with the exploration term bounded by the variance of the edge.
+/// Scores are divided by `score_range` so the variance bound of 1/4 applies.
+pub struct Ucb1Tuned {
+    pub score_range: f64,
+}
+
+/// `mean + c * prior * sqrt(parent visits) / (1 + visits)`, where the priors are a
+/// softmax over the heuristic estimates of the siblings. Unvisited edges use the
+/// mean of the parent.
+pub struct Puct {
+    pub c: f64,
+    pub temperature: f64,
+}
+
+/// A sample from a Gaussian around the mean of the edge, with the standard
+/// deviation of its scores shrinking with `sqrt(visits)`. Unvisited edges are
+/// sampled around `Parameters::unexplored_value`.
+pub struct Thompson {
+    /// Standard deviation of the scores of edges with too few visits to estimate it
+    pub prior_deviation: f64,
+}
+
+impl SelectionPolicy for Uct {
+    fn value(
+        &self,
+        edge: &Edge,
+        parent: &Edge,
+        game: &Game,
+        heuristics: &Heuristics,
+        _rng: &mut dyn RngCore,
+    ) -> Score {
+        // With MC-RAVE, the AMAF mean takes the place of the heuristic estimate once there is one
+        if let Some(rave) = heuristics.parameters.mc_rave {
+            if edge.amaf_visits > 0 && game.turn != 7 {
+                let beta = rave.beta(edge.visits);
+                let q = (1. - beta).mul_add(edge.mean_score, beta * edge.amaf_score);
+                return heuristics.normalize_value(game.turn, q)
+                    + heuristics.get_exploration_term(edge.mv, edge.visits, parent.visits, game);
+            }
+        }
+
+        heuristics.get_exploration_value_given_heuristic(
+            edge.mv,
+            edge.mean_score,
+            edge.visits,
+            parent.visits,
+            game,
+            edge.heuristic_value.unwrap_or_default(),
+        )
+    }
+}
+
+impl SelectionPolicy for Ucb1Tuned {
+    fn value(
+        &self,
+        edge: &Edge,
+        parent: &Edge,
+        _game: &Game,
+        _heuristics: &Heuristics,
+        _rng: &mut dyn RngCore,
+    ) -> Score {
+        if edge.visits == 0 {
+            return Score::MAX;
+        }
+        let n = edge.visits as f64;
+        let log_parent = (parent.visits.max(1) as f64).ln();
+        let mean = edge.mean_score / self.score_range;
+        let variance = edge.variance() / (self.score_range * self.score_range);
+        let bound = (variance + (2. * log_parent / n).sqrt()).min(0.25);
+        (mean + (log_parent / n * bound).sqrt()) * self.score_range
+    }
+}
+
+impl SelectionPolicy for Puct {
+    fn prepare(&self, children: &mut [Edge], game: &Game, heuristics: &mut Heuristics) {
+        if children.iter().all(|edge| edge.prior.is_some()) {
+            return;
+        }
+        let estimations = children
+            .iter_mut()
+            .map(|edge| {
+                let mv = edge.mv;
+                *edge
+                    .heuristic_value
+                    .get_or_insert_with(|| heuristics.get_move_estimation(game, mv))
+            })
+            .collect::<Vec<_>>();
+        let max = estimations.iter().copied().fold(f64::MIN, f64::max);
+        let weights = estimations
+            .iter()
+            .map(|estimation| ((estimation - max) / self.temperature).exp())
+            .collect::<Vec<_>>();
+        let total = weights.iter().sum::<f64>();
+        for (edge, weight) in children.iter_mut().zip(weights) {
+            edge.prior = Some(weight / total);
+        }
+    }
+
+    fn value(
+        &self,
+        edge: &Edge,
+        parent: &Edge,
+        _game: &Game,
+        _heuristics: &Heuristics,
+        _rng: &mut dyn RngCore,
+    ) -> Score {
+        let mean = if edge.visits == 0 {
+            parent.mean_score
+        } else {
+            edge.mean_score
+        };
+        let prior = edge.prior.unwrap_or_default();
+        let exploration = (parent.visits as f64).sqrt() / (1 + edge.visits) as f64;
+        (self.c * prior).mul_add(exploration, mean)
+    }
+}
+
+impl SelectionPolicy for Thompson {
+    fn value(
+        &self,
+        edge: &Edge,
+        _parent: &Edge,
+        game: &Game,
+        heuristics: &Heuristics,
+        rng: &mut dyn RngCore,
+    ) -> Score {
+        let (mean, deviation) = if edge.visits == 0 {
+            let turn = usize::from(game.turn).min(6);
+            (
+                heuristics.parameters.unexplored_value[turn],
+                self.prior_deviation,
+            )
+        } else if edge.visits < 2 {
+            (edge.mean_score, self.prior_deviation)
+        } else {
+            (edge.mean_score, edge.variance().sqrt())
+        };
+        let deviation = deviation / ((edge.visits + 1) as f64).sqrt();
+        deviation.mul_add(standard_normal(rng), mean)
+    }
+}
+
+/// Sample from the standard normal distribution, with the Box-Muller transform
+fn standard_normal(rng: &mut dyn RngCore) -> f64 {
+    let u = 1. - rng.gen::<f64>();
+    let v = rng.gen::<f64>();
+    (-2. * u.ln()).sqrt() * (std::f64::consts::TAU * v).cos()
+}
+
+/// The selection policy to use, as stored in `Parameters`
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "policy")]
+pub enum SelectionPolicyKind {
+    #[default]
+    Uct,
+    Ucb1Tuned {
+        score_range: f64,
+    },
+    Puct {
+        c: f64,
+        /// Temperature of the softmax over the heuristic estimates. Must be positive.
+        #[serde(deserialize_with = "deserialize_temperature")]
+        temperature: f64,
+    },
+    Thompson {
+        prior_deviation: f64,
+    },
+}
+
+impl SelectionPolicy for SelectionPolicyKind {
+    fn prepare(&self, children: &mut [Edge], game: &Game, heuristics: &mut Heuristics) {
+        if let Self::Puct { c, temperature } = *self {
+            Puct { c, temperature }.prepare(children, game, heuristics);
+        }
+    }
+
+    fn value(
+        &self,
+        edge: &Edge,
+        parent: &Edge,
+        game: &Game,
+        heuristics: &Heuristics,
+        rng: &mut dyn RngCore,
+    ) -> Score {
+        match *self {
+            Self::Uct => Uct.value(edge, parent, game, heuristics, rng),
+            Self::Ucb1Tuned { score_range } => {
+                Ucb1Tuned { score_range }.value(edge, parent, game, heuristics, rng)
+            }
+            Self::Puct { c, temperature } => {
+                Puct { c, temperature }.value(edge, parent, game, heuristics, rng)
+            }
+            Self::Thompson { prior_deviation } => {
+                Thompson { prior_deviation }.value(edge, parent, game, heuristics, rng)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::game::mv::Move;
+    use crate::mcts::distribution::ScoreDistribution;
+    use crate::mcts::MonteCarloTree;
+    use rand::SeedableRng;
+    use rand_xoshiro::SplitMix64;
+
+    fn edge(visits: u64, mean_score: Score, squared_score: Score) -> Edge {
+        Edge {
+            visits,
+            mean_score,
+            distribution: ScoreDistribution {
+                samples: visits,
+                mean: mean_score,
+                squared_mean: squared_score,
+                ..ScoreDistribution::new()
+            },
+            ..Edge::new(Move::End)
+        }
+    }
+
+    #[test]
+    fn test_ucb1_tuned_prefers_uncertain_edges() {
+        let game = Game::new_from_seed([3; 8]);
+        let heuristics = Heuristics::default();
+        let mut rng = SplitMix64::from_seed([3; 8]);
+        let parent = edge(100_000, 40., 1600.);
+        let policy = Ucb1Tuned { score_range: 100. };
+
+        // Same mean and visits, but only one has any spread in its scores
+        let steady = edge(10_000, 40., 1600.);
+        let noisy = edge(10_000, 40., 1600. + 400.);
+        assert!(steady.variance().abs() < 1e-9);
+        assert!((noisy.variance() - 400.).abs() < 1e-9);
+        assert!(
+            policy.value(&noisy, &parent, &game, &heuristics, &mut rng)
+                > policy.value(&steady, &parent, &game, &heuristics, &mut rng)
+        );
+    }
+
+    #[test]
+    fn test_puct_priors_sum_to_one() {
+        let mut game = Game::new_from_seed([3; 8]);
+        let mut heuristics = Heuristics::default();
+        let mut children = game
+            .generate_moves()
+            .into_iter()
+            .map(Edge::new)
+            .collect::<Vec<_>>();
+        Puct {
+            c: 1.,
+            temperature: 1.,
+        }
+        .prepare(&mut children, &game, &mut heuristics);
+
+        let total = children.iter().map(|edge| edge.prior.unwrap()).sum::<f64>();
+        assert!((total - 1.).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_every_policy_searches() {
+        let policies = [
+            SelectionPolicyKind::Uct,
+            SelectionPolicyKind::Ucb1Tuned { score_range: 100. },
+            SelectionPolicyKind::Puct {
+                c: 20.,
+                temperature: 1.,
+            },
+            SelectionPolicyKind::Thompson {
+                prior_deviation: 20.,
+            },
+        ];
+        for policy in policies {
+            let seed = [0, 0, 0, 0, 0, 0, 0, 17];
+            let game = Game::new_from_seed(seed);
+            let mut mcts = MonteCarloTree::new_from_seed(game, seed);
+            mcts.heuristics.parameters.selection_policy = policy;
+            mcts.search_iterations(200);
+            assert_eq!(mcts.root.visits, 200);
+            assert!(mcts.calculate_depth() > 1);
+        }
+    }
+
+    #[test]
+    fn test_puct_temperature_must_be_positive() {
+        let parse = |temperature: &str| {
+            serde_json::from_str::<SelectionPolicyKind>(&format!(
+                r#"{{"policy": "puct", "c": 1.0, "temperature": {temperature}}}"#
+            ))
+        };
+        assert!(parse("0.0").is_err());
+        assert!(parse("-2.0").is_err());
+        assert_eq!(
+            parse("0.5").ok(),
+            Some(SelectionPolicyKind::Puct {
+                c: 1.,
+                temperature: 0.5
+            })
+        );
+    }
+}
@@ -0,0 +1,240 @@
+//! Exact solver for the last turn.
+//!
+//! On turn 7 the roll is known and nothing is left to chance, so instead of
+//! sampling, the solver searches every order of placing the remaining pieces,
+//! with or without a special piece, for the board with the highest
+//! `Board::score`. The search is a branch and bound: moves are tried in order
+//! of `Board::score_upper_bound`, and a branch is cut as soon as its bound is
+//! no better than the best board found so far. Positions reached by placing the
+//! same pieces in a different order are searched only once.
+//!
+//! Early in the turn there may be too many positions to search. The solver
+//! then gives up, either right away when the placements of the remaining pieces
+//! multiply to more than `position_limit`, or after searching that many
+//! positions. `MonteCarloTree` then searches as usual, and tries the solver
+//! again after the next placement.
+
+use super::MonteCarloTree;
+use crate::game::mv::Move;
+use crate::game::Game;
+use crate::pieces::Piece;
+use std::collections::HashSet;
+
+/// Default for `Parameters::final_round_positions`
+pub const DEFAULT_POSITION_LIMIT: usize = 5_000;
+
+#[must_use]
+pub const fn default_position_limit() -> usize {
+    DEFAULT_POSITION_LIMIT
+}
+
+/// The best arrangement of the rest of the last turn
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Solution {
+    /// The moves to play, ending with `Move::End`
+    pub moves: Vec<Move>,
+    pub score: i32,
+    /// Number of positions searched
+    pub positions: usize,
+}
+
+struct Solver {
+    position_limit: usize,
+    /// Positions searched, by `Game::encode`
+    visited: HashSet<String>,
+    /// Moves from the root to the current position
+    line: Vec<Move>,
+    best: Option<(i32, Vec<Move>)>,
+}
+
+impl Solver {
+    fn best_score(&self) -> i32 {
+        self.best.as_ref().map_or(i32::MIN, |(score, _)| *score)
+    }
+
+    /// Search the positions after `game`. Returns `false` if the position limit was reached.
+    fn search(&mut self, game: &mut Game) -> bool {
+        // Every board reachable from a position searched before has been scored already
+        if !self.visited.insert(game.encode()) {
+            return true;
+        }
+        if self.visited.len() > self.position_limit {
+            return false;
+        }
+
+        let mut children = vec![];
+        for mv in game.generate_moves() {
+            if mv == Move::End {
+                let score = game.board.score();
+                if score > self.best_score() {
+                    let mut moves = self.line.clone();
+                    moves.push(Move::End);
+                    self.best = Some((score, moves));
+                }
+                continue;
+            }
+            let mut child = game.clone();
+            child.do_move(mv);
+            let bound = child.board.score_upper_bound(pieces_left(&child));
+            children.push((bound, mv, child));
+        }
+
+        children.sort_by_key(|(bound, _, _)| std::cmp::Reverse(*bound));
+        for (bound, mv, mut child) in children {
+            if bound <= self.best_score() {
+                break;
+            }
+            self.line.push(mv);
+            let completed = self.search(&mut child);
+            self.line.pop();
+            if !completed {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// The most pieces that may still be placed this turn
+fn pieces_left(game: &Game) -> usize {
+    game.to_place.len() + usize::from(game.can_play_specials())
+}
+
+/// A rough count of the positions to search from `game`: the product of the number
+/// of placements of each remaining piece, and of the special pieces
+fn estimate_positions(game: &mut Game) -> usize {
+    let moves = game.generate_moves();
+    let placements = |special: bool, piece: Option<u8>| {
+        moves
+            .iter()
+            .filter(|mv| match mv {
+                Move::Place(placement) => {
+                    Piece::is_optional(placement.piece) == special
+                        && piece.is_none_or(|piece| placement.piece == piece)
+                }
+                _ => false,
+            })
+            .count()
+    };
+    game.to_place
+        .iter()
+        .map(|&piece| placements(false, Some(piece)).max(1))
+        .fold(1 + placements(true, None), usize::saturating_mul)
+}
+
+/// Solve the rest of the last turn from `game`, searching at most `position_limit` positions.
+/// Returns `None` before the last turn, or if the rest of the turn is too big to solve.
+#[must_use]
+pub fn solve_final_round(game: &Game, position_limit: usize) -> Option<Solution> {
+    if game.turn != 7 || game.ended || estimate_positions(&mut game.clone()) > position_limit {
+        return None;
+    }
+    let mut solver = Solver {
+        position_limit,
+        visited: HashSet::new(),
+        line: vec![],
+        best: None,
+    };
+    if !solver.search(&mut game.clone()) {
+        return None;
+    }
+    let (score, moves) = solver.best?;
+    Some(Solution {
+        moves,
+        score,
+        positions: solver.visited.len(),
+    })
+}
+
+impl MonteCarloTree {
+    /// Follow the solution of the final round, when the last turn is small enough to solve
+    pub(super) fn start_solved_line(&mut self) {
+        let position_limit = self.heuristics.parameters.final_round_positions;
+        if self.game.turn != 7 || self.unsolved || position_limit == 0 {
+            return;
+        }
+        match solve_final_round(&self.game, position_limit) {
+            Some(solution) => {
+                self.planned_line = solution.moves;
+                self.planned_score = Some(f64::from(solution.score));
+            }
+            None => self.unsolved = true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// A game on its last turn, with `placed` of the dice placed
+    fn last_turn(seed: [u8; 8], placed: usize) -> Game {
+        let mut game = Game::new_from_seed(seed);
+        while game.turn < 7 {
+            let mv = game.generate_moves()[0];
+            game.do_move(mv);
+        }
+        for _ in 0..placed {
+            let mv = game.generate_moves()[0];
+            game.do_move(mv);
+        }
+        game
+    }
+
+    /// The best final score from `game`, trying every sequence of moves
+    fn exhaustive_best(game: &mut Game) -> i32 {
+        game.generate_moves()
+            .into_iter()
+            .map(|mv| {
+                if mv == Move::End {
+                    return game.board.score();
+                }
+                let mut child = game.clone();
+                child.do_move(mv);
+                exhaustive_best(&mut child)
+            })
+            .max()
+            .unwrap_or_else(|| game.board.score())
+    }
+
+    #[test]
+    fn test_solver_finds_best_arrangement() {
+        for seed in [[0, 0, 0, 0, 0, 0, 0, 73], [0, 0, 0, 0, 0, 0, 0, 79]] {
+            let mut game = last_turn(seed, 3);
+            let solution = solve_final_round(&game, 100_000).unwrap();
+            assert_eq!(solution.score, exhaustive_best(&mut game));
+
+            // The solution is a legal line reaching its score
+            for &mv in &solution.moves {
+                assert!(game.generate_moves().contains(&mv));
+                game.do_move(mv);
+            }
+            assert!(game.ended);
+            assert_eq!(game.board.score(), solution.score);
+        }
+    }
+
+    #[test]
+    fn test_solver_gives_up() {
+        let game = last_turn([0, 0, 0, 0, 0, 0, 0, 83], 0);
+        assert!(solve_final_round(&game, 1).is_none());
+        assert!(solve_final_round(&Game::new_from_seed([0; 8]), 100_000).is_none());
+    }
+
+    #[test]
+    fn test_tree_plays_solution_on_last_turn() {
+        let seed = [0, 0, 0, 0, 0, 0, 0, 73];
+        let mut game = last_turn(seed, 3);
+        let solution = solve_final_round(&game, 100_000).unwrap();
+
+        let mut mcts = MonteCarloTree::new_from_seed(game.clone(), seed);
+        mcts.heuristics.parameters.final_round_positions = 100_000;
+        for &expected in &solution.moves {
+            let mv = mcts.search_iterations(100).best_move();
+            assert_eq!(mv, expected);
+            assert_eq!(mcts.root.visits, 0);
+            mcts = MonteCarloTree::progress(mcts, mv, &mut game);
+        }
+        assert_eq!(game.board.score(), solution.score);
+    }
+}
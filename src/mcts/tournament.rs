@@ -0,0 +1,523 @@
+//! Tournaments between engines, played on the same dice.
+//!
+//! Every entrant plays every game of the tournament, and game `i` is played
+//! with the seed `seed + i` for both the dice and the engine, so the entrants
+//! face exactly the same rolls. Comparing the scores game by game removes most
+//! of the luck of the dice: the paired differences vary far less than the
+//! scores themselves, so fewer games tell the entrants apart.
+//!
+//! For every pair of entrants, the mean difference is reported with a 95%
+//! confidence interval, and tested with a sequential probability ratio test
+//! (SPRT) of the second entrant scoring `delta` points more than the first
+//! (H1) against them scoring the same (H0). The games are played in batches,
+//! and with `TournamentOptions::sprt` set, the tournament stops as soon as
+//! every test has accepted one of its hypotheses.
+
+use super::analysis::SearchLimits;
+use super::engine::{record_game, EngineKind, GameRecord};
+use super::heuristics::nn::Model;
+use super::heuristics::{Heuristics, Parameters};
+use crate::game::Game;
+use burn::backend::wgpu::WgpuDevice;
+use burn::backend::Wgpu;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::prelude::*;
+use std::sync::Mutex;
+
+/// Standard normal quantile for the 95% confidence intervals
+const CONFIDENCE_Z: f64 = 1.96;
+
+fn default_limits() -> SearchLimits {
+    SearchLimits::iterations(1000)
+}
+
+/// An engine and what it plays with
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Entrant {
+    pub name: String,
+    #[serde(default)]
+    pub engine: EngineKind,
+    /// Heuristics parameters as a JSON file. Defaults to `Heuristics::default`
+    #[serde(default)]
+    pub heuristics: Option<String>,
+    /// Directory of a trained neural network to estimate moves with, see `Heuristics::load_model`
+    #[serde(default)]
+    pub model: Option<String>,
+    /// Defaults to 1000 iterations per move
+    #[serde(default = "default_limits")]
+    pub limits: SearchLimits,
+}
+
+impl Entrant {
+    /// The heuristics the entrant plays with, with its model loaded
+    /// # Errors
+    /// Returns an error if the parameters or the model cannot be loaded, or the parameters
+    /// need a model the entrant has not
+    pub fn heuristics(&self) -> Result<SharedHeuristics, String> {
+        SharedHeuristics::load(self.heuristics.as_deref(), self.model.as_deref())
+    }
+
+    /// Play the game with `seed`, seeding the engine with it too, searching within `limits`,
+    /// with the entrant's `heuristics`
+    #[must_use]
+    pub fn play(
+        &self,
+        heuristics: &SharedHeuristics,
+        seed: u64,
+        limits: &SearchLimits,
+    ) -> GameRecord {
+        let seed = seed.to_be_bytes();
+        let mut engine = self.engine.build(seed, heuristics.get());
+        record_game(&mut *engine, &mut Game::new_from_seed(seed), limits)
+    }
+}
+
+/// Heuristics loaded once, and shared by the threads playing an entrant's games.
+/// The model cannot be used from several threads at once, so every game gets a clone.
+pub struct SharedHeuristics {
+    pub parameters: Parameters,
+    model: Option<Mutex<Model<Wgpu>>>,
+}
+
+impl SharedHeuristics {
+    /// Load the parameters in the JSON file `heuristics`, or the defaults without one, and the
+    /// model trained into the directory `model`, see `Heuristics::load_model`
+    /// # Errors
+    /// Returns an error if the parameters or the model cannot be loaded, or the parameters
+    /// need a model and none is given, see `Parameters::check_model`
+    pub fn load(heuristics: Option<&str>, model: Option<&str>) -> Result<Self, String> {
+        let parameters = heuristics.map_or_else(
+            || Ok(Heuristics::default().parameters),
+            Parameters::from_json,
+        )?;
+        parameters.check_model(model.is_some())?;
+        let model = model
+            .map(|dir| Heuristics::load_model(dir, &WgpuDevice::default()))
+            .transpose()?;
+        Ok(Self {
+            parameters,
+            model: model.map(Mutex::new),
+        })
+    }
+
+    /// The heuristics to play a game with
+    /// # Panics
+    /// Panics if another thread panicked while cloning the model
+    #[must_use]
+    pub fn get(&self) -> Heuristics {
+        let mut heuristics = Heuristics::new(self.parameters.clone());
+        heuristics.move_nn = self
+            .model
+            .as_ref()
+            .map(|model| model.lock().expect("The model lock was poisoned").clone());
+        heuristics
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TournamentConfig {
+    pub entrants: Vec<Entrant>,
+}
+
+impl TournamentConfig {
+    /// # Errors
+    /// Returns an error if the file cannot be read or is not a tournament configuration
+    pub fn from_json(path: &str) -> Result<Self, String> {
+        let mut contents = String::new();
+        File::open(path)
+            .and_then(|mut file| file.read_to_string(&mut contents))
+            .map_err(|e| format!("Error loading tournament: {e}"))?;
+        serde_json::from_str(&contents).map_err(|e| format!("Error loading tournament: {e}"))
+    }
+}
+
+/// A sequential probability ratio test of the mean paired difference being
+/// `delta` (H1) rather than zero (H0), with error rates `alpha` and `beta`
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Sprt {
+    pub delta: f64,
+    pub alpha: f64,
+    pub beta: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SprtDecision {
+    Continue,
+    AcceptH0,
+    AcceptH1,
+}
+
+impl Sprt {
+    /// Log-likelihood ratio of H1 to H0 for the `differences` so far, taking
+    /// them to be normally distributed with their sample variance
+    #[must_use]
+    pub fn log_likelihood_ratio(&self, differences: &PairedStats) -> f64 {
+        if differences.games < 2 {
+            return 0.0;
+        }
+        let variance = differences.variance().max(f64::EPSILON);
+        differences.games as f64 * self.delta * (differences.mean - self.delta / 2.0) / variance
+    }
+
+    #[must_use]
+    pub fn decision(&self, differences: &PairedStats) -> SprtDecision {
+        let llr = self.log_likelihood_ratio(differences);
+        if llr >= ((1.0 - self.beta) / self.alpha).ln() {
+            SprtDecision::AcceptH1
+        } else if llr <= (self.beta / (1.0 - self.alpha)).ln() {
+            SprtDecision::AcceptH0
+        } else {
+            SprtDecision::Continue
+        }
+    }
+}
+
+/// Running mean and variance of a series of values, by Welford's algorithm
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PairedStats {
+    pub games: u64,
+    pub mean: f64,
+    /// Sum of the squared deviations from the mean
+    squares: f64,
+}
+
+impl PairedStats {
+    pub fn add(&mut self, value: f64) {
+        self.games += 1;
+        let delta = value - self.mean;
+        self.mean += delta / self.games as f64;
+        self.squares += delta * (value - self.mean);
+    }
+
+    /// Sample variance
+    #[must_use]
+    pub fn variance(&self) -> f64 {
+        if self.games < 2 {
+            return 0.0;
+        }
+        self.squares / (self.games - 1) as f64
+    }
+
+    /// Bounds of the 95% confidence interval of the mean
+    #[must_use]
+    pub fn confidence_interval(&self) -> (f64, f64) {
+        let margin = CONFIDENCE_Z * (self.variance() / self.games.max(1) as f64).sqrt();
+        (self.mean - margin, self.mean + margin)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TournamentOptions {
+    /// Most games to play
+    pub games: u64,
+    pub seed: u64,
+    /// Number of games played between checks of the tests
+    pub batch_size: u64,
+    /// Stop once every pair is decided by this test
+    pub sprt: Option<Sprt>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct EntrantReport {
+    pub name: String,
+    pub mean_score: f64,
+    pub lower_bound: f64,
+    pub upper_bound: f64,
+}
+
+/// The second entrant's scores compared with the first's
+#[derive(Debug, Clone, Serialize)]
+pub struct PairReport {
+    pub first: String,
+    pub second: String,
+    pub mean_difference: f64,
+    pub lower_bound: f64,
+    pub upper_bound: f64,
+    pub log_likelihood_ratio: Option<f64>,
+    pub decision: Option<SprtDecision>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TournamentReport {
+    pub games: usize,
+    pub sprt: Option<Sprt>,
+    pub entrants: Vec<EntrantReport>,
+    pub pairs: Vec<PairReport>,
+}
+
+pub struct Tournament {
+    pub entrants: Vec<Entrant>,
+    heuristics: Vec<SharedHeuristics>,
+    /// Seed of each game played
+    pub seeds: Vec<u64>,
+    /// Scores of each game played, in the order of `entrants`
+    pub scores: Vec<Vec<i32>>,
+}
+
+impl Tournament {
+    /// # Errors
+    /// Returns an error if there are fewer than two entrants, or the heuristics or model of one cannot be loaded
+    pub fn new(config: TournamentConfig) -> Result<Self, String> {
+        if config.entrants.len() < 2 {
+            return Err("A tournament needs at least two entrants".to_string());
+        }
+        let heuristics = config
+            .entrants
+            .iter()
+            .map(Entrant::heuristics)
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            entrants: config.entrants,
+            heuristics,
+            seeds: vec![],
+            scores: vec![],
+        })
+    }
+
+    /// The score of entrant `index` in the game with `seed`
+    fn play(&self, index: usize, seed: u64) -> i32 {
+        let entrant = &self.entrants[index];
+        entrant
+            .play(&self.heuristics[index], seed, &entrant.limits)
+            .score
+            .total()
+    }
+
+    /// Paired differences of entrant `second`'s scores from entrant `first`'s
+    #[must_use]
+    pub fn differences(&self, first: usize, second: usize) -> PairedStats {
+        let mut stats = PairedStats::default();
+        for scores in &self.scores {
+            stats.add(f64::from(scores[second] - scores[first]));
+        }
+        stats
+    }
+
+    /// Every pair of entrants, earlier entrants first
+    fn pairs(&self) -> impl Iterator<Item = (usize, usize)> {
+        let count = self.entrants.len();
+        (0..count).flat_map(move |first| (first + 1..count).map(move |second| (first, second)))
+    }
+
+    /// Play until `options.games` games are played, or `options.sprt` decides every pair.
+    /// `on_batch` is called after every batch of games.
+    pub fn run(&mut self, options: &TournamentOptions, mut on_batch: impl FnMut(&Self)) {
+        while (self.scores.len() as u64) < options.games {
+            if let Some(sprt) = options.sprt {
+                let decided = self.pairs().all(|(first, second)| {
+                    sprt.decision(&self.differences(first, second)) != SprtDecision::Continue
+                });
+                if decided {
+                    break;
+                }
+            }
+
+            let start = self.scores.len() as u64;
+            let end = (start + options.batch_size.max(1)).min(options.games);
+            let this = &*self;
+            let batch = (start..end)
+                .into_par_iter()
+                .map(|game| {
+                    let seed = options.seed.wrapping_add(game);
+                    let scores = (0..this.entrants.len())
+                        .map(|index| this.play(index, seed))
+                        .collect::<Vec<_>>();
+                    (seed, scores)
+                })
+                .collect::<Vec<_>>();
+            for (seed, scores) in batch {
+                self.seeds.push(seed);
+                self.scores.push(scores);
+            }
+            on_batch(self);
+        }
+    }
+
+    #[must_use]
+    pub fn report(&self, sprt: Option<Sprt>) -> TournamentReport {
+        let entrants = self
+            .entrants
+            .iter()
+            .enumerate()
+            .map(|(index, entrant)| {
+                let mut stats = PairedStats::default();
+                for scores in &self.scores {
+                    stats.add(f64::from(scores[index]));
+                }
+                let (lower_bound, upper_bound) = stats.confidence_interval();
+                EntrantReport {
+                    name: entrant.name.clone(),
+                    mean_score: stats.mean,
+                    lower_bound,
+                    upper_bound,
+                }
+            })
+            .collect();
+        let pairs = self
+            .pairs()
+            .map(|(first, second)| {
+                let differences = self.differences(first, second);
+                let (lower_bound, upper_bound) = differences.confidence_interval();
+                PairReport {
+                    first: self.entrants[first].name.clone(),
+                    second: self.entrants[second].name.clone(),
+                    mean_difference: differences.mean,
+                    lower_bound,
+                    upper_bound,
+                    log_likelihood_ratio: sprt.map(|sprt| sprt.log_likelihood_ratio(&differences)),
+                    decision: sprt.map(|sprt| sprt.decision(&differences)),
+                }
+            })
+            .collect();
+        TournamentReport {
+            games: self.scores.len(),
+            sprt,
+            entrants,
+            pairs,
+        }
+    }
+
+    /// Write the scores of every game to a CSV file, one row per game
+    /// # Errors
+    /// Returns an error if the file cannot be created or written to
+    pub fn write_csv(&self, path: &str) -> std::io::Result<()> {
+        let mut file = File::create(path)?;
+        let names = self
+            .entrants
+            .iter()
+            .map(|entrant| entrant.name.as_str())
+            .collect::<Vec<_>>();
+        writeln!(file, "game,seed,{}", names.join(","))?;
+        for (game, (seed, scores)) in self.seeds.iter().zip(&self.scores).enumerate() {
+            let scores = scores.iter().map(ToString::to_string).collect::<Vec<_>>();
+            writeln!(file, "{game},{seed},{}", scores.join(","))?;
+        }
+        Ok(())
+    }
+}
+
+impl TournamentReport {
+    /// # Errors
+    /// Returns an error if the file cannot be created or written to
+    pub fn to_json(&self, path: &str) -> std::io::Result<()> {
+        let file = File::create(path)?;
+        serde_json::to_writer_pretty(file, self)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn entrant(name: &str, engine: EngineKind) -> Entrant {
+        Entrant {
+            name: name.to_string(),
+            engine,
+            heuristics: None,
+            model: None,
+            limits: SearchLimits::default(),
+        }
+    }
+
+    #[test]
+    fn test_paired_stats() {
+        let mut stats = PairedStats::default();
+        for value in [2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0] {
+            stats.add(value);
+        }
+        assert!((stats.mean - 5.0).abs() < 1e-9);
+        assert!((stats.variance() - 32.0 / 7.0).abs() < 1e-9);
+        let (lower, upper) = stats.confidence_interval();
+        assert!((5.0 - lower - (upper - 5.0)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_sprt_decides() {
+        let sprt = Sprt {
+            delta: 2.0,
+            alpha: 0.05,
+            beta: 0.05,
+        };
+        let mut better = PairedStats::default();
+        let mut same = PairedStats::default();
+        for i in 0..40 {
+            let noise = f64::from(i % 5) - 2.0;
+            better.add(4.0 + noise);
+            same.add(noise);
+        }
+        assert_eq!(sprt.decision(&better), SprtDecision::AcceptH1);
+        assert_eq!(sprt.decision(&same), SprtDecision::AcceptH0);
+        assert_eq!(
+            sprt.decision(&PairedStats::default()),
+            SprtDecision::Continue
+        );
+    }
+
+    #[test]
+    fn test_entrants_play_the_same_dice() {
+        let config = TournamentConfig {
+            entrants: vec![
+                entrant("greedy", EngineKind::GreedyScore),
+                entrant("greedy again", EngineKind::GreedyScore),
+                entrant("random", EngineKind::Random),
+            ],
+        };
+        let mut tournament = Tournament::new(config).unwrap();
+        let options = TournamentOptions {
+            games: 6,
+            seed: 7,
+            batch_size: 2,
+            sprt: Some(Sprt {
+                delta: 3.0,
+                alpha: 0.05,
+                beta: 0.05,
+            }),
+        };
+        let mut batches = 0;
+        tournament.run(&options, |_| batches += 1);
+        // Random play is so much worse that every pair is decided early
+        let played = tournament.seeds.len();
+        assert!(played < 6);
+        assert_eq!(tournament.seeds, (7..7 + played as u64).collect::<Vec<_>>());
+        assert_eq!(batches, played / 2);
+
+        // The same engine on the same dice scores the same
+        let report = tournament.report(options.sprt);
+        assert!(report.pairs[0].mean_difference.abs() < 1e-9);
+        assert_eq!(report.pairs[0].decision, Some(SprtDecision::AcceptH0));
+        assert!(report.pairs[1].mean_difference < 0.0);
+
+        tournament.run(
+            &TournamentOptions {
+                sprt: None,
+                ..options
+            },
+            |_| batches += 1,
+        );
+        assert_eq!(tournament.seeds, vec![7, 8, 9, 10, 11, 12]);
+        assert_eq!(batches, 3);
+    }
+
+    #[test]
+    fn test_seeds_wrap_around() {
+        let config = TournamentConfig {
+            entrants: vec![
+                entrant("greedy", EngineKind::GreedyScore),
+                entrant("random", EngineKind::Random),
+            ],
+        };
+        let mut tournament = Tournament::new(config).unwrap();
+        let options = TournamentOptions {
+            games: 2,
+            seed: u64::MAX,
+            batch_size: 2,
+            sprt: None,
+        };
+        tournament.run(&options, |_| {});
+        assert_eq!(tournament.seeds, vec![u64::MAX, 0]);
+    }
+}
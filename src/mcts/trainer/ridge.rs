@@ -0,0 +1,390 @@
+//! Fitting of the heuristic weights to recorded games by ridge regression.
+//!
+//! `Heuristics::get_move_estimation` adds a weight of `Parameters` for every
+//! feature a placement has, with separate weights for every turn. Fitting them
+//! is a linear regression: for the placements of every turn in the `matches`
+//! table, the final score of the game is regressed on the features of the
+//! placement. Each coefficient is then the points a feature is worth by the end
+//! of the game, in the same units as the hand-tuned weights.
+//!
+//! Rows recorded before the table had a turn are skipped. The samples of each
+//! turn are split into a training and a validation part, in table order, and the
+//! fit is reported against predicting the mean score of the training part.
+
+use crate::board::placement::Placement;
+use crate::board::Board;
+use crate::game::mv::Move;
+use crate::mcts::heuristics::nn::data::{DataItem, GameDataset};
+use crate::mcts::heuristics::Parameters;
+use crate::pieces::Piece;
+use serde::Serialize;
+use std::fs::File;
+
+/// Number of features of a placement
+pub const FEATURES: usize = 6;
+
+/// The rows of `Parameters::as_array` weighing the features, in order
+const FEATURE_ROWS: [usize; FEATURES] = [SPECIAL_COST_ROW, 3, 4, 5, 6, 7];
+
+/// The row of `Parameters::as_array` with the cost of using a special piece
+const SPECIAL_COST_ROW: usize = 2;
+
+/// The features of `placement` on `board` in `turn` that `Heuristics::get_move_estimation`
+/// weighs, from those `DataItem::get_heuristics` gives the network
+#[must_use]
+pub fn features(board: &Board, placement: Placement, turn: usize) -> [f64; FEATURES] {
+    let indicator = |feature: bool| if feature { 1.0 } else { 0.0 };
+    let heuristics = DataItem::get_heuristics(board, Move::Place(placement)).map(f64::from);
+    [
+        // `Heuristics::special_use` costs nothing on the last turn
+        indicator(turn < 7 && Piece::is_special(placement.piece)),
+        heuristics[2],
+        // The connections are given in quarters, and weighed from two on
+        indicator(heuristics[3] * 4.0 > 1.0),
+        heuristics[4],
+        heuristics[5],
+        heuristics[6],
+    ]
+}
+
+/// A placement and the final score of its game
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sample {
+    pub turn: usize,
+    pub features: [f64; FEATURES],
+    pub score: f64,
+}
+
+impl Sample {
+    /// The sample of `mv` on `board` in `turn`, unless it is not a placement
+    #[must_use]
+    pub fn new(board: &Board, mv: Move, turn: usize, score: f64) -> Option<Self> {
+        match mv {
+            Move::Place(placement) => Some(Self {
+                turn,
+                features: features(board, placement, turn),
+                score,
+            }),
+            _ => None,
+        }
+    }
+
+    /// The placements in the `matches` table that have a turn
+    #[must_use]
+    pub fn load_all() -> Vec<Self> {
+        GameDataset::get_all()
+            .iter()
+            .filter_map(|item| {
+                let turn = usize::from(item.turn?);
+                Self::new(&item.board, item.mv, turn, item.score)
+            })
+            .collect()
+    }
+}
+
+/// A linear model of the final score
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct LinearFit {
+    pub intercept: f64,
+    pub weights: [f64; FEATURES],
+}
+
+impl LinearFit {
+    #[must_use]
+    pub fn predict(&self, features: &[f64; FEATURES]) -> f64 {
+        self.weights
+            .iter()
+            .zip(features)
+            .fold(self.intercept, |total, (weight, x)| {
+                weight.mul_add(*x, total)
+            })
+    }
+
+    /// Root mean squared error on `samples`
+    #[must_use]
+    pub fn rmse(&self, samples: &[Sample]) -> f64 {
+        let squares = samples
+            .iter()
+            .map(|sample| (self.predict(&sample.features) - sample.score).powi(2))
+            .sum::<f64>();
+        (squares / samples.len().max(1) as f64).sqrt()
+    }
+
+    /// The model minimising the squared error on `samples` plus `ridge` times the
+    /// squared weights, leaving the intercept unpenalised. Features that never
+    /// vary get no weight. `None` without samples, or if `ridge` is zero and
+    /// the features are linearly dependent.
+    #[must_use]
+    pub fn ridge(samples: &[Sample], ridge: f64) -> Option<Self> {
+        if samples.is_empty() {
+            return None;
+        }
+        let n = samples.len() as f64;
+        let mut means = [0.0; FEATURES];
+        for sample in samples {
+            for (mean, x) in means.iter_mut().zip(&sample.features) {
+                *mean += x / n;
+            }
+        }
+        let mean_score = samples.iter().map(|sample| sample.score).sum::<f64>() / n;
+        let varies = (0..FEATURES)
+            .map(|i| {
+                samples
+                    .iter()
+                    .any(|s| (s.features[i] - means[i]).abs() > 0.0)
+            })
+            .collect::<Vec<_>>();
+
+        // The normal equations of the centred features: (XᵀX + ridge I) w = Xᵀy
+        let mut gram = [[0.0; FEATURES]; FEATURES];
+        let mut moments = [0.0; FEATURES];
+        for sample in samples {
+            let x = centred(&sample.features, &means);
+            for i in 0..FEATURES {
+                moments[i] += x[i] * (sample.score - mean_score);
+                for j in 0..FEATURES {
+                    gram[i][j] += x[i] * x[j];
+                }
+            }
+        }
+        for i in 0..FEATURES {
+            gram[i][i] += if varies[i] { ridge } else { 1.0 };
+        }
+
+        let weights = solve_cholesky(&gram, &moments)?;
+        let intercept = weights
+            .iter()
+            .zip(&means)
+            .fold(mean_score, |total, (weight, mean)| {
+                weight.mul_add(-mean, total)
+            });
+        Some(Self { intercept, weights })
+    }
+}
+
+fn centred(features: &[f64; FEATURES], means: &[f64; FEATURES]) -> [f64; FEATURES] {
+    let mut x = *features;
+    for (x, mean) in x.iter_mut().zip(means) {
+        *x -= mean;
+    }
+    x
+}
+
+/// Solve `matrix x = rhs` for a symmetric positive definite `matrix`
+fn solve_cholesky(
+    matrix: &[[f64; FEATURES]; FEATURES],
+    rhs: &[f64; FEATURES],
+) -> Option<[f64; FEATURES]> {
+    // matrix = lower lowerᵀ
+    let mut lower = [[0.0; FEATURES]; FEATURES];
+    for row in 0..FEATURES {
+        for col in 0..=row {
+            let sum = (0..col).map(|k| lower[row][k] * lower[col][k]).sum::<f64>();
+            if row == col {
+                let pivot = matrix[row][row] - sum;
+                if pivot <= f64::EPSILON {
+                    return None;
+                }
+                lower[row][row] = pivot.sqrt();
+            } else {
+                lower[row][col] = (matrix[row][col] - sum) / lower[col][col];
+            }
+        }
+    }
+    // lower y = rhs, then lowerᵀ x = y
+    let mut forward = [0.0; FEATURES];
+    for row in 0..FEATURES {
+        let sum = (0..row).map(|k| lower[row][k] * forward[k]).sum::<f64>();
+        forward[row] = (rhs[row] - sum) / lower[row][row];
+    }
+    let mut solution = [0.0; FEATURES];
+    for row in (0..FEATURES).rev() {
+        let sum = (row + 1..FEATURES)
+            .map(|k| lower[k][row] * solution[k])
+            .sum::<f64>();
+        solution[row] = (forward[row] - sum) / lower[row][row];
+    }
+    Some(solution)
+}
+
+/// How the fit of a turn did
+#[derive(Debug, Clone, Serialize)]
+pub struct TurnReport {
+    pub turn: usize,
+    pub training_samples: usize,
+    pub validation_samples: usize,
+    /// `None` if the turn had too few samples to fit, and kept its weights
+    pub fit: Option<LinearFit>,
+    pub training_rmse: Option<f64>,
+    pub validation_rmse: Option<f64>,
+    /// Error of predicting the mean training score for every validation sample
+    pub baseline_rmse: Option<f64>,
+    /// Share of the variance of the validation scores explained by the fit
+    pub validation_r2: Option<f64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FitReport {
+    pub ridge: f64,
+    pub validation_share: f64,
+    pub turns: Vec<TurnReport>,
+}
+
+impl FitReport {
+    /// # Errors
+    /// Returns an error if the file cannot be created or written to
+    pub fn to_json(&self, path: &str) -> std::io::Result<()> {
+        let file = File::create(path)?;
+        serde_json::to_writer_pretty(file, self)?;
+        Ok(())
+    }
+}
+
+/// `parameters` with the weights of every turn fitted to `samples` with penalty `ridge`,
+/// validated on the last `validation_share` of the samples of each turn
+#[must_use]
+pub fn fit_parameters(
+    parameters: &Parameters,
+    samples: &[Sample],
+    ridge: f64,
+    validation_share: f64,
+) -> (Parameters, FitReport) {
+    let mut fitted = parameters.clone();
+    let mut turns = vec![];
+    for turn in 1..=7 {
+        let samples = samples
+            .iter()
+            .filter(|sample| sample.turn == turn)
+            .copied()
+            .collect::<Vec<_>>();
+        #[allow(clippy::cast_sign_loss)] // Clamped to be positive
+        let validation_count =
+            (samples.len() as f64 * validation_share.clamp(0.0, 1.0)).round() as usize;
+        let (training, validation) = samples.split_at(samples.len() - validation_count);
+
+        let fit = if training.len() > FEATURES {
+            LinearFit::ridge(training, ridge)
+        } else {
+            None
+        };
+        if let Some(fit) = &fit {
+            let rows = fitted.rows_mut();
+            for (&row, weight) in FEATURE_ROWS.iter().zip(fit.weights) {
+                // Special pieces cost nothing on the last turn, so there is no weight to fit
+                if row == SPECIAL_COST_ROW && turn == 7 {
+                    continue;
+                }
+                rows[row][turn - 1] = weight;
+            }
+        }
+
+        let has_validation = fit.is_some() && !validation.is_empty();
+        let baseline = LinearFit {
+            intercept: training.iter().map(|sample| sample.score).sum::<f64>()
+                / training.len().max(1) as f64,
+            weights: [0.0; FEATURES],
+        };
+        let baseline_rmse = has_validation.then(|| baseline.rmse(validation));
+        let validation_rmse = fit
+            .filter(|_| has_validation)
+            .map(|fit| fit.rmse(validation));
+        turns.push(TurnReport {
+            turn,
+            training_samples: training.len(),
+            validation_samples: validation.len(),
+            fit,
+            training_rmse: fit.map(|fit| fit.rmse(training)),
+            validation_rmse,
+            baseline_rmse,
+            validation_r2: validation_rmse
+                .zip(baseline_rmse)
+                .filter(|&(_, baseline)| baseline > 0.0)
+                .map(|(rmse, baseline)| 1.0 - (rmse / baseline).powi(2)),
+        });
+    }
+    (
+        fitted,
+        FitReport {
+            ridge,
+            validation_share,
+            turns,
+        },
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::game::Game;
+    use crate::mcts::analysis::SearchLimits;
+    use crate::mcts::engine::{play_game, EngineKind};
+    use crate::mcts::heuristics::Heuristics;
+
+    /// The placements of a few random games, scored by a known linear model
+    fn samples(truth: &LinearFit) -> Vec<Sample> {
+        let mut samples = vec![];
+        for seed in 0..20_u64 {
+            let seed = seed.to_be_bytes();
+            let mut engine = EngineKind::Random.build(seed, Heuristics::default());
+            let mut game = Game::new_from_seed(seed);
+            play_game(
+                &mut *engine,
+                &mut game,
+                &SearchLimits::default(),
+                |_, game, mv| {
+                    let turn = usize::from(game.turn);
+                    samples.extend(
+                        Sample::new(&game.board, mv, turn, 0.0).map(|sample| Sample {
+                            score: truth.predict(&sample.features),
+                            ..sample
+                        }),
+                    );
+                },
+            );
+        }
+        samples
+    }
+
+    #[test]
+    fn test_ridge_recovers_a_linear_model() {
+        let truth = LinearFit {
+            intercept: 30.0,
+            weights: [-8.0, 4.0, 2.0, -3.0, 1.0, 0.5],
+        };
+        let samples = samples(&truth);
+        let fit = LinearFit::ridge(&samples, 1e-6).unwrap();
+        assert!((fit.intercept - truth.intercept).abs() < 1e-3);
+        for (weight, expected) in fit.weights.iter().zip(truth.weights) {
+            assert!((weight - expected).abs() < 1e-3);
+        }
+        // A heavy penalty shrinks the weights
+        let shrunk = LinearFit::ridge(&samples, 1e6).unwrap();
+        assert!(shrunk.weights.iter().all(|weight| weight.abs() < 1.0));
+    }
+
+    #[test]
+    fn test_fitted_parameters_keep_the_other_rows() {
+        let truth = LinearFit {
+            intercept: 30.0,
+            weights: [-8.0, 4.0, 2.0, -3.0, 1.0, 0.5],
+        };
+        let samples = samples(&truth);
+        let parameters = Heuristics::default().parameters;
+        let (fitted, report) = fit_parameters(&parameters, &samples, 1e-6, 0.25);
+        assert_eq!(report.turns.len(), 7);
+
+        let before = parameters.as_array();
+        let after = fitted.as_array();
+        for row in [0, 1] {
+            assert_eq!(before[row].map(f64::to_bits), after[row].map(f64::to_bits));
+        }
+        // Special pieces cost nothing on the last turn, whatever the fit
+        assert!(report.turns[6].fit.is_some());
+        assert_eq!(before[2][6].to_bits(), after[2][6].to_bits());
+        for turn in report.turns.iter().filter(|turn| turn.fit.is_some()) {
+            assert!((after[3][turn.turn - 1] - 4.0).abs() < 1e-3);
+            assert!(turn.validation_rmse.unwrap() < 1e-3);
+        }
+        assert!(report.turns.iter().any(|turn| turn.fit.is_some()));
+    }
+}
@@ -0,0 +1,309 @@
+//! Tuning of the heuristics parameters by simultaneous perturbation stochastic
+//! approximation (SPSA).
+//!
+//! Every generation perturbs all the tuned values at once, in a random
+//! direction of plus or minus `c_k` in every coordinate, and plays the same
+//! games with the parameters moved either way. The difference of the two mean
+//! scores estimates the gradient along every coordinate, and the parameters
+//! take a step of `a_k` up it. Both step sizes shrink as the generations go by,
+//! by the schedule of Spall (1998).
+//!
+//! The values are tuned relative to their starting magnitudes, so a perturbation
+//! of `c` moves each by about that share of itself. Both sides of a generation
+//! are played on the same seeds, and each generation on new ones, so the
+//! comparison is paired and the parameters don't overfit a set of games.
+//!
+//! The tuner can be saved after every generation, and resumes from where it was.
+
+use super::super::analysis::SearchLimits;
+use super::super::engine::{record_game, EngineKind};
+use super::super::heuristics::{Heuristics, Parameters};
+use crate::game::Game;
+use rand::Rng;
+use rand::SeedableRng;
+use rand_xoshiro::SplitMix64;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::prelude::*;
+
+/// Exponent of the decay of the step size
+const STEP_DECAY: f64 = 0.602;
+/// Exponent of the decay of the perturbation
+const PERTURBATION_DECAY: f64 = 0.101;
+/// Smallest magnitude to scale a value by, so values starting at zero can move
+const MIN_SCALE: f64 = 1e-3;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SpsaOptions {
+    /// Rows of `Parameters::as_array` to tune, by index
+    pub rows: Vec<usize>,
+    /// Engine to play the games with
+    pub engine: EngineKind,
+    pub limits: SearchLimits,
+    /// Games per side of every generation
+    pub games: u64,
+    pub seed: u64,
+    /// `a`: step size of the first generation, relative to the values
+    pub learning_rate: f64,
+    /// `c`: perturbation of the first generation, relative to the values
+    pub perturbation: f64,
+    /// `A`: generations added to the step size schedule to slow its early decay
+    pub stability: f64,
+}
+
+impl Default for SpsaOptions {
+    fn default() -> Self {
+        Self {
+            rows: (0..Parameters::ROW_NAMES.len()).collect(),
+            engine: EngineKind::Mcts,
+            limits: SearchLimits::iterations(300),
+            games: 16,
+            seed: 0,
+            learning_rate: 0.01,
+            perturbation: 0.2,
+            stability: 5.0,
+        }
+    }
+}
+
+/// What a generation played
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Generation {
+    pub generation: u64,
+    /// Mean score with the parameters perturbed up
+    pub plus_score: f64,
+    /// Mean score with the parameters perturbed down
+    pub minus_score: f64,
+    pub step_size: f64,
+    pub perturbation: f64,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Spsa {
+    pub options: SpsaOptions,
+    /// The current estimate of the best parameters
+    pub parameters: Parameters,
+    /// Magnitude of every tuned value at the start
+    scales: Vec<f64>,
+    rng: SplitMix64,
+    pub history: Vec<Generation>,
+}
+
+impl Spsa {
+    /// # Panics
+    /// Panics if a row of `options.rows` is out of range
+    #[must_use]
+    pub fn new(parameters: Parameters, options: SpsaOptions) -> Self {
+        let scales = Self::tuned_values(&parameters, &options.rows)
+            .into_iter()
+            .map(|value| value.abs().max(MIN_SCALE))
+            .collect();
+        let rng = SplitMix64::from_seed(options.seed.to_be_bytes());
+        Self {
+            options,
+            parameters,
+            scales,
+            rng,
+            history: vec![],
+        }
+    }
+
+    /// # Errors
+    /// Returns an error if the file cannot be read or is not a saved tuner
+    pub fn load(path: &str) -> Result<Self, String> {
+        let mut contents = String::new();
+        File::open(path)
+            .and_then(|mut file| file.read_to_string(&mut contents))
+            .map_err(|e| format!("Error loading tuner: {e}"))?;
+        serde_json::from_str(&contents).map_err(|e| format!("Error loading tuner: {e}"))
+    }
+
+    /// # Errors
+    /// Returns an error if the file cannot be created or written to
+    pub fn save(&self, path: &str) -> std::io::Result<()> {
+        let file = File::create(path)?;
+        serde_json::to_writer(file, self)?;
+        Ok(())
+    }
+
+    fn tuned_values(parameters: &Parameters, rows: &[usize]) -> Vec<f64> {
+        let array = parameters.as_array();
+        rows.iter().flat_map(|&row| array[row]).collect()
+    }
+
+    /// `parameters` with the tuned values at `scales * relative`
+    fn with_values(&self, relative: &[f64]) -> Parameters {
+        let mut parameters = self.parameters.clone();
+        let mut values = relative
+            .iter()
+            .zip(&self.scales)
+            .map(|(x, scale)| x * scale);
+        let rows = parameters.rows_mut();
+        for &row in &self.options.rows {
+            for value in rows[row].iter_mut() {
+                *value = values
+                    .next()
+                    .expect("Every tuned value should have a scale");
+            }
+        }
+        parameters
+    }
+
+    /// The tuned values of the current parameters, relative to their scales
+    fn relative_values(&self) -> Vec<f64> {
+        Self::tuned_values(&self.parameters, &self.options.rows)
+            .into_iter()
+            .zip(&self.scales)
+            .map(|(value, scale)| value / scale)
+            .collect()
+    }
+
+    /// Mean score of `parameters` over the games with `seeds`
+    fn mean_score(&self, parameters: &Parameters, seeds: &[u64]) -> f64 {
+        let total = seeds
+            .par_iter()
+            .map(|&seed| {
+                let seed = seed.to_be_bytes();
+                let heuristics = Heuristics::new(parameters.clone());
+                let mut engine = self.options.engine.build(seed, heuristics);
+                let mut game = Game::new_from_seed(seed);
+                record_game(&mut *engine, &mut game, &self.options.limits)
+                    .score
+                    .total()
+            })
+            .map(f64::from)
+            .sum::<f64>();
+        total / seeds.len().max(1) as f64
+    }
+
+    /// Play a generation, and step the parameters up the estimated gradient
+    pub fn step(&mut self) -> Generation {
+        let generation = self.history.len() as u64;
+        let k = generation as f64 + 1.0;
+        let step_size = self.options.learning_rate / (k + self.options.stability).powf(STEP_DECAY);
+        let perturbation = self.options.perturbation / k.powf(PERTURBATION_DECAY);
+
+        let relative = self.relative_values();
+        let direction = (0..relative.len())
+            .map(|_| if self.rng.gen() { 1.0 } else { -1.0 })
+            .collect::<Vec<f64>>();
+        let perturbed = |sign: f64| {
+            relative
+                .iter()
+                .zip(&direction)
+                .map(|(x, d)| sign.mul_add(perturbation * d, *x))
+                .collect::<Vec<_>>()
+        };
+        let plus = self.with_values(&perturbed(1.0));
+        let minus = self.with_values(&perturbed(-1.0));
+
+        let games = self.options.games;
+        let first_seed = self
+            .options
+            .seed
+            .wrapping_add(generation.wrapping_mul(games));
+        let seeds = (0..games)
+            .map(|game| first_seed.wrapping_add(game))
+            .collect::<Vec<_>>();
+        let (plus_score, minus_score) = rayon::join(
+            || self.mean_score(&plus, &seeds),
+            || self.mean_score(&minus, &seeds),
+        );
+
+        let difference = (plus_score - minus_score) / (2.0 * perturbation);
+        let stepped = relative
+            .iter()
+            .zip(&direction)
+            .map(|(x, d)| (step_size * difference).mul_add(*d, *x))
+            .collect::<Vec<_>>();
+        self.parameters = self.with_values(&stepped);
+
+        let played = Generation {
+            generation,
+            plus_score,
+            minus_score,
+            step_size,
+            perturbation,
+        };
+        self.history.push(played);
+        played
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// A file of its own in the temporary directory, so tests running at once don't share it
+    fn temp_path() -> String {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "railroad-ink-spsa-{}-{}.json",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        path.to_str().unwrap().to_owned()
+    }
+
+    fn options() -> SpsaOptions {
+        SpsaOptions {
+            rows: vec![2, 4],
+            engine: EngineKind::GreedyHeuristic,
+            limits: SearchLimits::default(),
+            games: 4,
+            seed: 3,
+            learning_rate: 0.5,
+            perturbation: 0.9,
+            ..SpsaOptions::default()
+        }
+    }
+
+    #[test]
+    fn test_steps_only_the_tuned_rows() {
+        let start = Heuristics::default().parameters;
+        let mut spsa = Spsa::new(start.clone(), options());
+        let generation = spsa.step();
+        assert_eq!(generation.generation, 0);
+        assert!((generation.plus_score - generation.minus_score).abs() > 0.0);
+
+        let before = start.as_array();
+        let after = spsa.parameters.as_array();
+        for row in 0..before.len() {
+            if row == 2 || row == 4 {
+                // Relative to their scales, the values all move by the same amount
+                let moves = before[row]
+                    .iter()
+                    .zip(&after[row])
+                    .map(|(a, b)| ((b - a) / a.abs().max(MIN_SCALE)).abs())
+                    .collect::<Vec<_>>();
+                assert!(moves.iter().all(|m| (m - moves[0]).abs() < 1e-9));
+            } else {
+                assert_eq!(before[row].map(f64::to_bits), after[row].map(f64::to_bits));
+            }
+        }
+    }
+
+    #[test]
+    fn test_resumes_where_it_was_saved() {
+        let path = temp_path();
+        let path = path.as_str();
+        let mut straight = Spsa::new(Heuristics::default().parameters, options());
+        straight.step();
+        straight.save(path).unwrap();
+        straight.step();
+
+        let mut resumed = Spsa::load(path).unwrap();
+        assert_eq!(resumed.history.len(), 1);
+        resumed.step();
+        assert_eq!(resumed.history, straight.history);
+        // JSON may round the last bit of the values
+        let resumed = resumed.parameters.as_array();
+        let straight = straight.parameters.as_array();
+        for (a, b) in resumed.iter().flatten().zip(straight.iter().flatten()) {
+            assert!((a - b).abs() <= 1e-9 * b.abs().max(1.0));
+        }
+        std::fs::remove_file(path).ok();
+    }
+}